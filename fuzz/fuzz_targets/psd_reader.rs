@@ -0,0 +1,28 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate psd_lib;
+
+use psd_lib::psd_file::PSDFile;
+use psd_lib::psd_reader::Limits;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+	let mut file = PSDFile::new(Cursor::new(data));
+	// keep decoded images small, so fuzzer spends time on parsing
+	file.set_limits(Limits {
+		max_width: 4096,
+		max_height: 4096,
+		max_layers: 1000,
+		max_resources: 1000,
+	});
+
+	let _ = file.get_lines();
+	let _ = file.get_layer_tree();
+	let _ = file.get_thumbnail();
+	let _ = file.composite_image();
+	let count = file.get_layers().map(|x| x.len()).unwrap_or(0);
+	for index in 0..count {
+		let _ = file.layer_image(index);
+	}
+});
@@ -0,0 +1,5 @@
+#!/bin/sh
+# Seeds psd_reader corpus with files from test_data
+cd "$(dirname "$0")"
+mkdir -p corpus/psd_reader
+cp ../test_data/*.psd ../test_data/*.psb corpus/psd_reader/
@@ -3,23 +3,39 @@
 //! Shows analyze information for psd file
 //!
 //! ```
-//! usage: $: psd_analyzer [--fullpath] [--flat] [--with-size] [--with-hash] file.psd [> analysis.txt]
+//! usage: $: psd_analyzer [--fullpath] [--flat] [--with-size] [--with-hash] [--with-names] [--select PATTERN] file.psd [> analysis.txt]
+//! 	$: psd_analyzer --thumbnail out.jpg file.psd
+//! 	$: psd_analyzer --check file.psd
+//! 	$: cat file.psd | psd_analyzer [--fullpath] [--flat] [--with-size] [--select PATTERN] -
 //! 	--fullpath: show full path
-//! 	--flat: don't indent blocks
+//! 	--flat: don't indent blocks. Blocks are indented by their nesting in the file, so fields
+//! 		of layer record extra data, e.g. `layer_0/name`, are indented under `extra_data`
 //! 	--with-size: show block size in bytes
 //! 	--with-hash: append hash to each block
+//! 	--with-names: append name to each layer
+//! 	--select: show only blocks which labels match glob-style PATTERN, e.g. "**/channel_*:data".
+//! 		`*` matches part of the label between "/" and ":" separators, `**` any number of parts
+//! 	--thumbnail: extract embedded jpeg thumbnail to out.jpg instead of analysis
+//! 	--check: report structural problems instead of analysis, exits with 1 if there are errors
+//! 	-: read file from stdin, blocks are printed as soon as they're read,
+//! 		so nested blocks are printed before the block containing them
 //! ```
 
 extern crate bin_diff;
 extern crate psd_lib;
 extern crate sha2;
 
+use psd_lib::block_tree::{Block, BlockKind};
+use psd_lib::error::PsdError;
+use psd_lib::forward_reader::ForwardReader;
 use psd_lib::psd_file::PSDFile;
+use psd_lib::psd_reader::PSDReader;
+use psd_lib::query::Pattern;
+use psd_lib::validate::{validate, Severity};
 use sha2::{Digest, Sha256};
-use std::cmp::max;
 use std::env;
-use std::fs::File;
-use std::io::{stdout, BufWriter, Read, Seek, SeekFrom, Write};
+use std::fs::{write, File};
+use std::io::{stdin, stdout, BufWriter, Read, Seek, SeekFrom, Write};
 use std::process::exit;
 
 fn compute_hash<T: Read>(input: &mut T) -> String {
@@ -42,21 +58,103 @@ fn compute_hash<T: Read>(input: &mut T) -> String {
 		.join("")
 }
 
+/// Formats block labeled `label` as `{indent}{name} : {start} {end} [({size})]`
+fn format_line(
+	block: &Block,
+	label: &str,
+	depth: usize,
+	fullpath: bool,
+	with_size: bool,
+) -> String {
+	let name = if fullpath { label } else { &block.name };
+	let mut end_s = block.end().to_string();
+	if with_size {
+		end_s = format!("{} ({})", end_s, block.size);
+	};
+	format!("{}{} : {} {}", "  ".repeat(depth), name, block.start, end_s)
+}
+
+/// Prints blocks of psd file read from stdin while it's being read
+fn analyze_stdin(
+	fullpath: bool,
+	flat: bool,
+	with_size: bool,
+	select: Option<&Pattern>,
+) -> Result<(), PsdError> {
+	let input = stdin();
+	let mut input = ForwardReader::new(input.lock());
+	let output = stdout();
+	let mut output = output.lock();
+	{
+		let mut reader = PSDReader::new(&mut input);
+		reader.set_forward_only(true);
+		reader.set_listener(|block, label, depth| {
+			if select.is_some_and(|x| !x.matches(label)) {
+				return;
+			}
+			let depth = if flat { 0 } else { depth };
+			let out = format_line(block, label, depth, fullpath, with_size);
+			if writeln!(output, "{}", out).is_err() {
+				eprintln!("Error while writing output");
+				exit(1);
+			}
+		});
+		reader.get_indexes()?;
+	}
+	output.flush()?;
+	Ok(())
+}
+
+fn write_thumbnail(file: &mut PSDFile<File>, output: &str) -> Result<(), PsdError> {
+	let thumb = file
+		.get_thumbnail()?
+		.ok_or_else(|| PsdError::NotFound("Thumbnail".to_string()))?;
+	if thumb.format != 1 {
+		return Err(PsdError::Unsupported(format!(
+			"thumbnail format {}, only jpeg can be extracted",
+			thumb.format
+		)));
+	}
+	if thumb.is_bgr {
+		eprintln!("Thumbnail is stored in BGR order, colors will be swapped");
+	}
+	write(output, &thumb.data)?;
+	Ok(())
+}
+
 fn main() {
-	let args = env::args().skip(1);
+	let mut args = env::args().skip(1);
 
 	let mut path: Option<String> = None;
 	let mut fullpath = false;
 	let mut flat = false;
 	let mut with_size = false;
 	let mut with_hash = false;
+	let mut with_names = false;
+	let mut thumbnail: Option<String> = None;
+	let mut check = false;
+	let mut select: Option<Pattern> = None;
 
-	for arg in args {
+	while let Some(arg) = args.next() {
 		match arg.as_ref() {
+			"--thumbnail" => {
+				thumbnail = Some(args.next().unwrap_or_else(|| {
+					eprintln!("Thumbnail output is not provided");
+					exit(1);
+				}))
+			}
+			"--select" => {
+				select = Some(Pattern::new(&args.next().unwrap_or_else(|| {
+					eprintln!("Select pattern is not provided");
+					exit(1);
+				})))
+			}
+			"--check" => check = true,
 			"--fullpath" => fullpath = true,
 			"--flat" => flat = true,
 			"--with-size" => with_size = true,
 			"--with-hash" => with_hash = true,
+			"--with-names" => with_names = true,
 			x => {
 				path = Some(x.to_string());
 			}
@@ -68,6 +166,18 @@ fn main() {
 		exit(1);
 	});
 
+	if path == "-" {
+		if with_hash || with_names || thumbnail.is_some() || check {
+			eprintln!("--with-hash, --with-names, --thumbnail and --check need a file, not stdin");
+			exit(1);
+		}
+		analyze_stdin(fullpath, flat, with_size, select.as_ref()).unwrap_or_else(|e| {
+			eprintln!("{}", e);
+			exit(1);
+		});
+		return;
+	}
+
 	let file = File::open(path).unwrap_or_else(|_| {
 		eprintln!("Error reading input psd");
 		exit(1);
@@ -75,41 +185,64 @@ fn main() {
 	let mut file_h = file.try_clone().unwrap();
 	let mut file = PSDFile::new(file);
 
+	if let Some(thumbnail) = thumbnail {
+		write_thumbnail(&mut file, &thumbnail).unwrap_or_else(|e| {
+			eprintln!("{}", e);
+			exit(1);
+		});
+		return;
+	}
+
+	if check {
+		let issues = validate(&mut file);
+		for issue in &issues {
+			println!("{}", issue);
+		}
+		let errors = issues
+			.iter()
+			.filter(|x| x.severity == Severity::Error)
+			.count();
+		println!("{} errors, {} warnings", errors, issues.len() - errors);
+		if errors > 0 {
+			exit(1);
+		}
+		return;
+	}
+
 	let output = stdout();
 	let mut output = output.lock();
 	let mut output = BufWriter::with_capacity(1024 * 64, &mut output);
 
-	let indexes = file
-		.get_indexes()
+	let tree = file
+		.get_block_tree()
 		.unwrap_or_else(|e| {
 			eprintln!("{}", e);
 			exit(1);
 		}).clone();
 
-	for (item, start, size) in indexes {
-		let indent: usize = if flat {
-			0
-		} else {
-			let indent = item.matches('/').count();
-			let indentb = item.matches(':').count();
-			indent + indentb
-		};
-		let mut s = item.clone();
-		if !fullpath {
-			let index = max(
-				s.clone().rfind('/').unwrap_or(0),
-				s.clone().rfind(':').unwrap_or(0),
-			);
-			if index != 0 {
-				s = s.split_at(index + 1).1.to_string();
-			};
-		};
-		let mut end_s = (start + size).to_string();
-		if with_size {
-			end_s = format!("{} ({})", end_s, size);
+	let layers = file
+		.get_layers()
+		.unwrap_or_else(|e| {
+			eprintln!("{}", e);
+			exit(1);
+		}).to_vec();
+	let mut layers = layers.iter();
+
+	tree.walk(|block, label, depth| {
+		let layer = match block.kind {
+			BlockKind::Layer { .. } => layers.next(),
+			_ => None,
 		};
-		let mut out = format!("{}{} : {} {}", "  ".repeat(indent), s, start, end_s);
+		if select.as_ref().is_some_and(|x| !x.matches(label)) {
+			return;
+		}
+		let depth = if flat { 0 } else { depth };
+		let mut out = format_line(block, label, depth, fullpath, with_size);
+		if let (true, Some(layer)) = (with_names, layer) {
+			out = format!("{} {:?}", out, layer.name);
+		}
 		if with_hash {
+			let (start, size) = (block.start, block.size);
 			let max_size = 1024 * 1024 * 100;
 			if size != 0 && size < max_size {
 				let hash = {
@@ -132,7 +265,7 @@ fn main() {
 			eprintln!("Error while reading file");
 			exit(1);
 		}
-	}
+	});
 
 	let res = output.flush();
 	if res.is_err() {
@@ -80,10 +80,8 @@ fn bytes_to_human_readable(size: u64) -> String {
 }
 
 fn measure_diff(old: &str, new: &str, human_readable: bool) -> Result<(), String> {
-	let mut old =
-		PSDFile::new(File::open(old).or_else(|_| Err("Cannot open original file".to_string()))?);
-	let mut new =
-		PSDFile::new(File::open(new).or_else(|_| Err("Cannot open edited file".to_string()))?);
+	let mut old = PSDFile::open(old).map_err(|x| format!("Cannot open original file: {}", x))?;
+	let mut new = PSDFile::open(new).map_err(|x| format!("Cannot open edited file: {}", x))?;
 
 	let printdots = match var("PSDDIFF_VERBOSE") {
 		Ok(ref x) if x == "true" => Some(printdots()),
@@ -104,10 +102,8 @@ fn measure_diff(old: &str, new: &str, human_readable: bool) -> Result<(), String
 }
 
 fn create_diff(old: &str, new: &str, output_path: &str) -> Result<(), String> {
-	let mut old =
-		PSDFile::new(File::open(old).or_else(|_| Err("Cannot open original file".to_string()))?);
-	let mut new =
-		PSDFile::new(File::open(new).or_else(|_| Err("Cannot open edited file".to_string()))?);
+	let mut old = PSDFile::open(old).map_err(|x| format!("Cannot open original file: {}", x))?;
+	let mut new = PSDFile::open(new).map_err(|x| format!("Cannot open edited file: {}", x))?;
 	let mut output = ProxyFile::from(output_path.to_string());
 
 	let printdots = match var("PSDDIFF_VERBOSE") {
@@ -122,7 +118,7 @@ fn create_diff(old: &str, new: &str, output_path: &str) -> Result<(), String> {
 	if res.is_err() {
 		return Err("Cannot create diff".to_string());
 	}
-	output.end()?;
+	output.end().map_err(|x| x.to_string())?;
 	Ok(())
 }
 
@@ -144,7 +140,7 @@ fn apply_diff(old_path: &str, diff_path: &str, output_path: &str) -> Result<(),
 		return Err("Error applying diff".to_string());
 	}
 
-	output.end()?;
+	output.end().map_err(|x| x.to_string())?;
 	Ok(())
 }
 
@@ -170,7 +166,7 @@ fn apply_diff_vec(old_path: &str, diff_paths: &[&str], output_path: &str) -> Res
 		return Err("Error applying diff".to_string());
 	}
 
-	output.end()?;
+	output.end().map_err(|x| x.to_string())?;
 	Ok(())
 }
 
@@ -196,7 +192,7 @@ fn combine_diffs(paths: &[&str], output_path: &str) -> Result<(), String> {
 		return Err(outerr);
 	}
 
-	output.end()?;
+	output.end().map_err(|x| x.to_string())?;
 	Ok(())
 }
 
@@ -0,0 +1,108 @@
+//! psd_export binary
+//!
+//! Exports composite image and every layer of psd file to png
+//!
+//! ```
+//! usage: $: psd_export [--full-canvas] file.psd output_dir
+//! 	--full-canvas: place layers on the full canvas instead of cropping to the layer rect
+//! ```
+//!
+//! Layers which image can't be decoded are reported to stderr and skipped, exit code is 1 then
+
+extern crate psd_lib;
+
+mod output_paths;
+
+use output_paths::OutputPaths;
+use psd_lib::error::PsdError;
+use psd_lib::image::Image;
+use psd_lib::png::write_png;
+use psd_lib::psd_file::PSDFile;
+use std::env::args;
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Error, Write};
+use std::path::Path;
+use std::process::exit;
+
+fn write_image(image: &Image, path: &Path) -> Result<(), PsdError> {
+	if let Some(parent) = path.parent() {
+		create_dir_all(parent)?;
+	}
+	let file = File::create(path)
+		.map_err(|x| Error::new(x.kind(), format!("{}: {}", path.display(), x)))?;
+	let mut output = BufWriter::new(file);
+	write_png(image, &mut output)?;
+	output.flush()?;
+	Ok(())
+}
+
+/// Exports the file, returns number of layers which image couldn't be decoded
+fn export(path: &str, output: &str, full_canvas: bool) -> Result<usize, PsdError> {
+	let mut psd = PSDFile::open(path)?;
+	let mut paths = OutputPaths::new(output, "png");
+
+	let header = psd.get_header()?.clone();
+	let image = psd.composite_image()?;
+	write_image(&image, &paths.reserve("composite"))?;
+
+	let layers = psd.get_layers()?.to_vec();
+	let tree = psd.get_layer_tree()?;
+	let mut failed = 0;
+	for node in tree.nodes() {
+		let layer = &layers[node.layer];
+		if node.is_group() || layer.rect.is_empty() {
+			continue;
+		}
+		let mut image = match psd.layer_image(node.layer) {
+			Ok(image) => image,
+			Err(err) => {
+				eprintln!("layer {} \"{}\": {}", node.layer, node.path, err);
+				failed += 1;
+				continue;
+			}
+		};
+		if full_canvas {
+			image = image.place(header.width, header.height, layer.rect.left, layer.rect.top);
+		}
+		write_image(&image, &paths.layer_path(&tree, node))?;
+	}
+
+	Ok(failed)
+}
+
+fn print_usage() {
+	let usage_str = "\
+usage:
+$: psd_export [--full-canvas] $input_file $output_dir
+   --full-canvas: place layers on the full canvas instead of cropping to the layer rect
+   Layers which image can't be decoded are reported to stderr and skipped, exit code is 1 then\
+";
+	println!("{}", usage_str);
+}
+
+fn main() {
+	let mut full_canvas = false;
+	let mut paths = vec![];
+	for arg in args().skip(1) {
+		match arg.as_ref() {
+			"--full-canvas" => full_canvas = true,
+			x => paths.push(x.to_string()),
+		}
+	}
+	if paths.len() != 2 {
+		print_usage();
+		exit(1);
+	};
+
+	match export(&paths[0], &paths[1], full_canvas) {
+		Ok(0) => (),
+		Ok(failed) => {
+			eprintln!("{} layers weren't exported", failed);
+			exit(1);
+		}
+		Err(err) => {
+			eprintln!("{}", err);
+			exit(1);
+		}
+	};
+}
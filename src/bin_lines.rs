@@ -3,19 +3,176 @@
 //! Shows lines information for one or multiple input files
 //!
 //! ```
-//! usage: $: psd_lines [--truncate] [...file.psd>1] > lines.txt
+//! usage: $: psd_lines [--truncate] [--by-id] [...file.psd>1] > lines.txt
 //! 	--truncate: truncate block label
+//! 	--by-id: label image resources and layers by their ids instead of position
+//! 	-: read one of the files from stdin in a single pass, lines are hashed while it streams,
+//! 		--by-id isn't available then
 //! ```
 
 extern crate bin_diff;
 extern crate psd_lib;
+extern crate sha2;
 
 use bin_diff::lines_with_hash_iterator::LinesWithHashIterator;
-use psd_lib::psd_file::PSDFile;
+use psd_lib::forward_reader::ForwardReader;
+use psd_lib::psd_file::{LineTracker, PSDFile};
+use psd_lib::psd_reader::{LabelMode, PSDReader};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::cmp::min;
+use std::collections::VecDeque;
 use std::env::args;
 use std::fs::File;
-use std::io::{stdout, BufWriter, Write};
+use std::io::{copy, sink, stdin, stdout, BufWriter, Read, Result as IOResult, Write};
 use std::process::exit;
+use std::rc::Rc;
+
+type Line = (String, u64, u64, String);
+
+/// Bytes pulled from stdin which are kept unhashed, as reader may peek ahead
+/// of the block it's reading before the line of the peeked bytes is known
+const LOOKAHEAD: usize = 1024 * 1024;
+
+/// Hashes bytes of the lines of stdin
+struct Hashes {
+	/// bytes pulled from stdin which aren't hashed yet, starting at `hashed`
+	pending: Vec<u8>,
+	hashed: u64,
+	/// line of the bytes at `hashed`
+	line: Option<usize>,
+	/// positions where bytes of another line start, in file order
+	changes: VecDeque<(u64, Option<usize>)>,
+	hashers: Vec<(Sha256, u64)>,
+	error: Option<String>,
+}
+
+impl Hashes {
+	/// Handles start of the bytes of `line` at `pos`, all bytes before it belong to known lines
+	fn change(&mut self, pos: u64, line: Option<usize>) {
+		if let Some(line) = line {
+			if line == self.hashers.len() {
+				self.hashers.push((Sha256::default(), 0));
+			}
+		}
+		if pos < self.hashed {
+			if self.error.is_none() {
+				self.error = Some(format!(
+					"line of bytes at {} is found too late to hash them in a single pass",
+					pos
+				));
+			}
+			return;
+		}
+		self.changes.push_back((pos, line));
+		self.hash(pos);
+	}
+
+	/// Hashes pending bytes up to `end`
+	fn hash(&mut self, end: u64) {
+		let end = min(end, self.hashed + self.pending.len() as u64);
+		let mut hashed = 0;
+		while self.hashed < end {
+			while self.changes.front().is_some_and(|x| x.0 <= self.hashed) {
+				self.line = self.changes.pop_front().unwrap().1;
+			}
+			let next = self.changes.front().map_or(end, |x| min(x.0, end));
+			let size = next - self.hashed;
+			if let Some(line) = self.line {
+				let (hasher, line_size) = &mut self.hashers[line];
+				hasher.input(&self.pending[hashed..hashed + size as usize]);
+				*line_size += size;
+			}
+			hashed += size as usize;
+			self.hashed = next;
+		}
+		self.pending.drain(..hashed);
+	}
+}
+
+/// Passes stdin to the reader and hashes it on the way
+struct Tap<R: Read> {
+	inner: R,
+	hashes: Rc<RefCell<Hashes>>,
+}
+
+impl<R: Read> Read for Tap<R> {
+	fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+		let size = self.inner.read(buf)?;
+		let mut hashes = self.hashes.borrow_mut();
+		hashes.pending.extend_from_slice(&buf[..size]);
+		if hashes.pending.len() > LOOKAHEAD {
+			let end = hashes.hashed + (hashes.pending.len() - LOOKAHEAD) as u64;
+			hashes.hash(end);
+		}
+		Ok(size)
+	}
+}
+
+/// Reads lines of psd file from stdin in a single pass
+fn stdin_lines() -> Result<Vec<Line>, String> {
+	let hashes = Rc::new(RefCell::new(Hashes {
+		pending: vec![],
+		hashed: 0,
+		line: None,
+		changes: VecDeque::new(),
+		hashers: vec![],
+		error: None,
+	}));
+	let tracker = RefCell::new(LineTracker::new());
+	let input = stdin();
+	let mut input = ForwardReader::new(Tap {
+		inner: input.lock(),
+		hashes: hashes.clone(),
+	});
+	{
+		let mut reader = PSDReader::new(&mut input);
+		reader.set_forward_only(true);
+		reader.set_start_listener(|block, label, _| {
+			let line = tracker.borrow_mut().start(block, label);
+			hashes.borrow_mut().change(block.start, line);
+		});
+		reader.set_listener(|block, _, _| {
+			let line = tracker.borrow_mut().end();
+			hashes.borrow_mut().change(block.start + block.size, line);
+		});
+		reader.get_indexes().map_err(|e| e.to_string())?;
+	}
+	// reader may skip the end of the last block without pulling it
+	copy(&mut input, &mut sink()).map_err(|e| e.to_string())?;
+
+	let mut hashes = hashes.borrow_mut();
+	hashes.hash(u64::MAX);
+	if let Some(error) = hashes.error.take() {
+		return Err(error);
+	}
+	let lines = tracker
+		.borrow()
+		.lines()
+		.iter()
+		.zip(hashes.hashers.drain(..))
+		.map(|((label, start), (hasher, size))| {
+			let hash: String = hasher
+				.result()
+				.iter()
+				.map(|b| format!("{:02x}", b))
+				.collect();
+			(label.clone(), *start, size, hash)
+		})
+		.collect();
+	Ok(lines)
+}
+
+fn open(path: &str, label_mode: LabelMode) -> Result<Box<dyn Iterator<Item = Line>>, String> {
+	if path == "-" {
+		return Ok(Box::new(stdin_lines()?.into_iter()));
+	}
+	let file = File::open(path).map_err(|e| e.to_string())?;
+	let mut file = PSDFile::new(file);
+	file.set_label_mode(label_mode);
+	let it = LinesWithHashIterator::new(file).map_err(|e| e.to_string())?;
+	Ok(Box::new(it))
+}
 
 fn pad_right(string: &str, len: usize) -> String {
 	if string.len() >= len {
@@ -28,17 +185,27 @@ fn pad_right(string: &str, len: usize) -> String {
 fn main() {
 	let args = args().skip(1);
 	if args.len() == 0 {
-		eprintln!("usage: bin_lines [--truncate] [...file_path > 0]");
+		eprintln!("usage: bin_lines [--truncate] [--by-id] [...file_path > 0]");
 		exit(1);
 	};
 	let mut truncate = false;
+	let mut label_mode = LabelMode::Position;
 	let mut paths: Vec<String> = vec![];
 	for arg in args {
 		match arg.as_ref() {
 			"--truncate" => truncate = true,
+			"--by-id" => label_mode = LabelMode::Id,
 			x => paths.push(x.to_string()),
 		};
 	}
+	if paths.iter().filter(|x| *x == "-").count() > 1 {
+		eprintln!("stdin can be read only once");
+		exit(1);
+	}
+	if label_mode == LabelMode::Id && paths.iter().any(|x| x == "-") {
+		eprintln!("--by-id needs a file, not stdin");
+		exit(1);
+	}
 	let padding_length = {
 		if truncate {
 			70
@@ -51,11 +218,12 @@ fn main() {
 	let stdout = stdout.lock();
 	let mut stdout = BufWriter::with_capacity(1024 * 64, stdout);
 	let mut data = {
-		let mut o: Vec<LinesWithHashIterator<PSDFile<File>>> = vec![];
+		let mut o: Vec<Box<dyn Iterator<Item = Line>>> = vec![];
 		for path in &paths {
-			let file = File::open(&path).unwrap();
-			let file = PSDFile::new(file);
-			let it = LinesWithHashIterator::new(file).unwrap();
+			let it = open(path, label_mode).unwrap_or_else(|e| {
+				eprintln!("{}: {}", path, e);
+				exit(1);
+			});
 			o.push(it);
 		}
 		o
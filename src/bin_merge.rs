@@ -4,18 +4,19 @@ extern crate psd_lib;
 mod proxy_file;
 
 use proxy_file::ProxyFile;
+use psd_lib::error::PsdError;
 use psd_lib::psd_file::PSDFile;
 use std::env::args;
 use std::path::{Path, PathBuf};
 use std::process::exit;
 
-fn merge<T, U>(path: T, output: U) -> Result<(), String>
+fn merge<T, U>(path: T, output: U) -> Result<(), PsdError>
 where
 	T: AsRef<Path>,
 	U: AsRef<Path>,
 {
 	let mut output = ProxyFile::from(PathBuf::from(output.as_ref()));
-	let mut psd = PSDFile::from(path);
+	let mut psd = PSDFile::open(path)?;
 	psd.write_composite(&mut output)?;
 	output.end()?;
 	Ok(())
@@ -0,0 +1,64 @@
+//! psd_repair binary
+//!
+//! Rebuilds damaged, e.g. truncated, psd file from its readable blocks
+//!
+//! ```
+//! usage: $: psd_repair damaged.psd repaired.psd
+//! ```
+
+extern crate psd_lib;
+
+mod proxy_file;
+
+use proxy_file::ProxyFile;
+use psd_lib::error::PsdError;
+use psd_lib::psd_file::PSDFile;
+use std::env::args;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+fn repair<T, U>(path: T, output: U) -> Result<(), PsdError>
+where
+	T: AsRef<Path>,
+	U: AsRef<Path>,
+{
+	let mut psd = PSDFile::open(path)?;
+	psd.set_lenient(true);
+	if let Some(recovery) = psd.get_recovery()? {
+		eprintln!("Reading stopped: {}", recovery.error);
+		for label in &recovery.partial {
+			eprintln!("Partial block: {}", label);
+		}
+	}
+	let layers = psd.get_layers()?.len();
+
+	let mut output = ProxyFile::from(PathBuf::from(output.as_ref()));
+	psd.write_repaired(&mut output)?;
+	output.end()?;
+	eprintln!("Layers with complete data: {}", layers);
+	Ok(())
+}
+
+fn print_usage() {
+	let usage_str = "\
+usage:
+$: psd_repair $input_file $output_file
+   $output_file can be substituted with \"-\" which means output to stdout\
+";
+	println!("{}", usage_str);
+}
+
+fn main() {
+	let mut args = args().skip(1);
+	if args.len() < 2 {
+		print_usage();
+		exit(1);
+	};
+
+	let path = args.next().unwrap();
+	let output = args.next().unwrap();
+	if let Err(err) = repair(path, output) {
+		eprintln!("{}", err);
+		exit(1);
+	};
+}
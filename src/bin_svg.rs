@@ -0,0 +1,100 @@
+//! psd_svg binary
+//!
+//! Exports vector masks of shape layers of psd file to svg files
+//!
+//! ```
+//! usage: $: psd_svg file.psd output_dir
+//! ```
+
+extern crate psd_lib;
+
+// doesn't write files besides layers, so `OutputPaths::reserve` isn't used
+#[allow(dead_code)]
+mod output_paths;
+
+use output_paths::OutputPaths;
+use psd_lib::descriptor::Descriptor;
+use psd_lib::error::PsdError;
+use psd_lib::psd_file::PSDFile;
+use std::env::args;
+use std::fs::{create_dir_all, File};
+use std::io::{Error, Write};
+use std::path::Path;
+use std::process::exit;
+
+/// Gets color of `SoCo` solid color fill of the layer as hex string, black if there is none
+fn fill_color(psd: &mut PSDFile<File>, layer: usize) -> Result<String, PsdError> {
+	let block = psd.get_layers()?[layer].get_tagged_block("SoCo").cloned();
+	let block = match block {
+		Some(block) => block,
+		None => return Ok("#000000".to_string()),
+	};
+	let data = psd.read_range(block.data_start, block.data_size)?;
+	let descriptor = Descriptor::decode_versioned(&data)?;
+	let color = descriptor
+		.get("Clr ")
+		.and_then(|x| x.as_descriptor())
+		.map(|color| {
+			["Rd  ", "Grn ", "Bl  "]
+				.iter()
+				.map(|key| color.get(key).and_then(|x| x.as_f64()).unwrap_or(0.0))
+				.map(|x| format!("{:02x}", x.round().clamp(0.0, 255.0) as u8))
+				.collect::<String>()
+		});
+	Ok(format!(
+		"#{}",
+		color.unwrap_or_else(|| "000000".to_string())
+	))
+}
+
+fn write_svg(svg: &str, path: &Path) -> Result<(), PsdError> {
+	if let Some(parent) = path.parent() {
+		create_dir_all(parent)?;
+	}
+	let mut file = File::create(path)
+		.map_err(|x| Error::new(x.kind(), format!("{}: {}", path.display(), x)))?;
+	file.write_all(svg.as_bytes())?;
+	Ok(())
+}
+
+fn export(path: &str, output: &str) -> Result<(), PsdError> {
+	let mut psd = PSDFile::open(path)?;
+	let mut paths = OutputPaths::new(output, "svg");
+
+	let header = psd.get_header()?.clone();
+	let tree = psd.get_layer_tree()?;
+	for node in tree.nodes() {
+		let vector_path = match psd.get_layer_vector_path(node.layer)? {
+			Some(x) => x,
+			None => continue,
+		};
+		let fill = fill_color(&mut psd, node.layer)?;
+		let svg = vector_path.to_svg(header.width, header.height, &fill);
+		write_svg(&svg, &paths.layer_path(&tree, node))?;
+	}
+
+	Ok(())
+}
+
+fn print_usage() {
+	let usage_str = "\
+usage:
+$: psd_svg $input_file $output_dir
+   exports vector mask of every shape layer to svg of the document size, named by its group path.
+   Shape is filled with the solid color of the layer\
+";
+	println!("{}", usage_str);
+}
+
+fn main() {
+	let paths: Vec<String> = args().skip(1).collect();
+	if paths.len() != 2 {
+		print_usage();
+		exit(1);
+	};
+
+	if let Err(err) = export(&paths[0], &paths[1]) {
+		eprintln!("{}", err);
+		exit(1);
+	};
+}
@@ -0,0 +1,138 @@
+//! psd_text binary
+//!
+//! Dumps text layers of psd file with their group paths as JSON
+//!
+//! ```
+//! usage: $: psd_text file.psd [> text.json]
+//! ```
+//!
+//! Layers which text can't be decoded are reported to stderr and skipped
+
+extern crate psd_lib;
+
+use psd_lib::error::PsdError;
+use psd_lib::psd_file::PSDFile;
+use psd_lib::text::TextLayer;
+use std::env::args;
+use std::process::exit;
+
+/// Formats string as JSON string literal
+fn json_string(value: &str) -> String {
+	let mut out = String::from("\"");
+	for c in value.chars() {
+		match c {
+			'"' => out.push_str("\\\""),
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\r' => out.push_str("\\r"),
+			'\t' => out.push_str("\\t"),
+			c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+			c => out.push(c),
+		}
+	}
+	out.push('"');
+	out
+}
+
+/// Formats number as JSON number, `null` if it isn't finite
+fn json_number(value: f64) -> String {
+	if value.is_finite() {
+		value.to_string()
+	} else {
+		"null".to_string()
+	}
+}
+
+fn json_option<T, F: Fn(&T) -> String>(value: &Option<T>, f: F) -> String {
+	match value {
+		Some(x) => f(x),
+		None => "null".to_string(),
+	}
+}
+
+fn json_numbers(values: &[f64]) -> String {
+	let values: Vec<String> = values.iter().map(|x| json_number(*x)).collect();
+	format!("[{}]", values.join(", "))
+}
+
+fn format_layer(index: usize, path: &str, name: &str, text: &TextLayer) -> String {
+	let styles: Vec<String> = text
+		.styles
+		.iter()
+		.map(|x| {
+			format!(
+				"{{\"start\": {}, \"length\": {}, \"font\": {}, \"size\": {}, \"color\": {}}}",
+				x.start,
+				x.length,
+				json_option(&x.font, |x| json_string(x)),
+				json_option(&x.size, |x| json_number(*x)),
+				json_option(&x.color, |x| json_numbers(x)),
+			)
+		})
+		.collect();
+	let paragraphs: Vec<String> = text
+		.paragraphs
+		.iter()
+		.map(|x| format!("{{\"start\": {}, \"length\": {}}}", x.start, x.length))
+		.collect();
+	let fonts: Vec<String> = text.fonts.iter().map(|x| json_string(x)).collect();
+	let bounds = &text.bounds;
+	format!(
+		"  {{\n    \"layer\": {},\n    \"path\": {},\n    \"name\": {},\n    \"text\": {},\n    \"transform\": {},\n    \"bounds\": {{\"left\": {}, \"top\": {}, \"right\": {}, \"bottom\": {}}},\n    \"fonts\": [{}],\n    \"styles\": [{}],\n    \"paragraphs\": [{}]\n  }}",
+		index,
+		json_string(path),
+		json_string(name),
+		json_string(&text.text),
+		json_numbers(&text.transform),
+		bounds.left,
+		bounds.top,
+		bounds.right,
+		bounds.bottom,
+		fonts.join(", "),
+		styles.join(", "),
+		paragraphs.join(", "),
+	)
+}
+
+fn dump(path: &str) -> Result<(), PsdError> {
+	let mut psd = PSDFile::open(path)?;
+	let tree = psd.get_layer_tree()?;
+	let mut out = vec![];
+	for node in tree.nodes() {
+		match psd.get_layer_text(node.layer) {
+			Ok(Some(text)) => out.push(format_layer(node.layer, &node.path, &node.name, &text)),
+			Ok(None) => (),
+			Err(err) => eprintln!("layer {} \"{}\": {}", node.layer, node.path, err),
+		}
+	}
+	if out.is_empty() {
+		println!("[]");
+	} else {
+		println!("[\n{}\n]", out.join(",\n"));
+	}
+	Ok(())
+}
+
+fn print_usage() {
+	let usage_str = "\
+usage:
+$: psd_text $input_file [> text.json]
+   prints text layers with their group paths, content, fonts, style runs and paragraph ranges as JSON.
+   Ranges are in UTF-16 code units of the text.
+   Layers which text can't be decoded are reported to stderr and skipped\
+";
+	println!("{}", usage_str);
+}
+
+fn main() {
+	let paths: Vec<String> = args().skip(1).collect();
+	if paths.len() != 1 {
+		print_usage();
+		exit(1);
+	};
+
+	if let Err(err) = dump(&paths[0]) {
+		eprintln!("{}", err);
+		exit(1);
+	};
+}
@@ -0,0 +1,254 @@
+//! Contains `BlockTree` struct, hierarchy of the blocks read by `PSDReader`
+
+use bin_diff::indexes::Indexes;
+use std::borrow::Cow;
+
+/// Kind of the block, so blocks can be matched without parsing their labels
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockKind {
+	/// Block with a fixed name, e.g. `header`, `layer_count` or `channel_0:data`
+	Field,
+	/// Image resource by position in `image_resources` and resource id
+	ImageResource { index: usize, id: u16 },
+	/// Layer record by position in its `layers_info` and `lyid` layer id
+	Layer { index: usize, id: Option<u32> },
+	/// Channel data of the layer in `channel_data`
+	LayerChannels { index: usize, id: Option<u32> },
+	/// Channel by position in the layer record
+	Channel(usize),
+	/// Tagged block by key, `index` counts preceding blocks with the same key
+	TaggedBlock { key: [u8; 4], index: usize },
+}
+
+/// Block of the file with its nested blocks. Labels of `Indexes` aren't stored,
+/// they are built from names of the blocks while the tree is walked
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+	pub kind: BlockKind,
+	/// Last part of the label, e.g. `layer_0` of `layers_resources/layers_info/layer_0`
+	/// or `data` of `.../channel_0:data`
+	pub name: Cow<'static, str>,
+	/// Separator of the name from the label of the parent, `/` or `:`
+	pub separator: char,
+	/// Nested blocks are labeled as if they were nested in the parent, e.g. fields of `extra_data`
+	pub transparent: bool,
+	/// Labels of the parents which weren't read completely in lenient mode and which
+	/// the block took place of, e.g. `/layers_info` of `layers_resources`. Empty for the rest
+	pub unfinished_parents: String,
+	pub start: u64,
+	pub size: u64,
+	/// Nested blocks in file order
+	pub children: Vec<Block>,
+}
+
+impl Block {
+	/// Creates block from its `name`, which is prefixed with `:` if it's separated so
+	pub fn new<N: Into<Cow<'static, str>>>(kind: BlockKind, name: N, start: u64) -> Self {
+		let (name, separator) = match name.into() {
+			Cow::Borrowed(x) if x.starts_with(':') => (Cow::Borrowed(&x[1..]), ':'),
+			Cow::Owned(x) if x.starts_with(':') => (Cow::Owned(x[1..].to_string()), ':'),
+			x => (x, '/'),
+		};
+		Self {
+			kind,
+			name,
+			separator,
+			transparent: false,
+			unfinished_parents: String::new(),
+			start,
+			size: 0,
+			children: vec![],
+		}
+	}
+
+	pub fn end(&self) -> u64 {
+		self.start + self.size
+	}
+
+	/// Appends name of the block to `label` of its parent, making it label of the block
+	pub fn push_label(&self, label: &mut String) {
+		label.push_str(&self.unfinished_parents);
+		if !label.is_empty() {
+			label.push(self.separator);
+		}
+		label.push_str(&self.name);
+	}
+
+	/// Gets nested block by name
+	pub fn child(&self, name: &str) -> Option<&Block> {
+		self.children.iter().find(|x| x.name == name)
+	}
+
+	/// Calls `f` with the block, its label and depth and then with every nested block
+	/// in file order, the block having depth `depth` and nested in the block labeled `parent`
+	pub fn walk<F: FnMut(&Block, &str, usize)>(&self, parent: &str, depth: usize, f: &mut F) {
+		self.walk_label(&mut parent.to_string(), depth, f, false);
+	}
+
+	/// Calls `f` with every nested block and then with the block, the order blocks are read in
+	pub fn walk_nested_first<F: FnMut(&Block, &str, usize)>(
+		&self,
+		parent: &str,
+		depth: usize,
+		f: &mut F,
+	) {
+		self.walk_label(&mut parent.to_string(), depth, f, true);
+	}
+
+	/// Walks the block nested in the block labeled `label`, which is restored afterwards
+	fn walk_label<F: FnMut(&Block, &str, usize)>(
+		&self,
+		label: &mut String,
+		depth: usize,
+		f: &mut F,
+		nested_first: bool,
+	) {
+		let parent = label.len();
+		self.push_label(label);
+		if !nested_first {
+			f(self, label, depth);
+		}
+		if self.transparent {
+			label.truncate(parent);
+		}
+		for child in &self.children {
+			child.walk_label(label, depth + 1, f, nested_first);
+		}
+		if nested_first {
+			label.truncate(parent);
+			self.push_label(label);
+			f(self, label, depth);
+		}
+		label.truncate(parent);
+	}
+}
+
+/// Hierarchy of the blocks of the file. `Indexes` are the same blocks flattened in file order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockTree {
+	/// Top level blocks: `header`, sections and their lengths
+	pub blocks: Vec<Block>,
+}
+
+impl BlockTree {
+	/// Gets block by names of the block and its parents, e.g.
+	/// `["layers_resources", "layers_info", "layer_0"]`
+	pub fn get(&self, path: &[&str]) -> Option<&Block> {
+		let (first, rest) = path.split_first()?;
+		let mut block = self.blocks.iter().find(|x| x.name == *first)?;
+		for name in rest {
+			block = block.child(name)?;
+		}
+		Some(block)
+	}
+
+	/// Calls `f` with every block in file order along with its label and depth,
+	/// top level blocks have 0
+	pub fn walk<F: FnMut(&Block, &str, usize)>(&self, mut f: F) {
+		let mut label = String::new();
+		for block in &self.blocks {
+			block.walk_label(&mut label, 0, &mut f, false);
+		}
+	}
+
+	/// Flattens tree into `Indexes`
+	pub fn to_indexes(&self) -> Indexes {
+		let mut indexes = Indexes::new();
+		self.walk(|block, label, _| indexes.insert(label.to_string(), block.start, block.size));
+		indexes
+	}
+}
+
+#[cfg(test)]
+mod block_tree_tests {
+	use super::*;
+	use psd_file::PSDFile;
+
+	#[test]
+	fn get_block_tree_test() {
+		let mut file = PSDFile::open("./test_data/a_a.psd").unwrap();
+		let tree = file.get_block_tree().unwrap().clone();
+
+		let names: Vec<&str> = tree.blocks.iter().map(|x| x.name.as_ref()).collect();
+		assert_eq!(
+			names,
+			vec![
+				"header",
+				"color_mode_section_length",
+				"color_mode_section",
+				"image_resources_length",
+				"image_resources",
+				"layers_resources_length",
+				"layers_resources",
+				"image_data",
+			]
+		);
+
+		let layer = tree
+			.get(&["layers_resources", "layers_info", "layer_1"])
+			.unwrap();
+		match layer.kind {
+			BlockKind::Layer {
+				index: 1,
+				id: Some(_),
+			} => (),
+			ref x => panic!("expected layer, got {:?}", x),
+		}
+		let channel = layer
+			.child("channel_info")
+			.unwrap()
+			.child("channel_0")
+			.unwrap();
+		assert_eq!(channel.kind, BlockKind::Channel(0));
+		assert_eq!(channel.child("length").unwrap().separator, ':');
+
+		let luni = layer
+			.child("extra_data")
+			.and_then(|x| x.child("additional_data"))
+			.and_then(|x| x.child("luni"))
+			.unwrap();
+		assert_eq!(
+			luni.kind,
+			BlockKind::TaggedBlock {
+				key: *b"luni",
+				index: 0
+			}
+		);
+		assert_eq!(luni.end(), luni.child("data").unwrap().end());
+
+		let resource = tree.get(&["image_resources", "image_resource_0"]).unwrap();
+		match resource.kind {
+			BlockKind::ImageResource { index: 0, .. } => (),
+			ref x => panic!("expected image resource, got {:?}", x),
+		}
+
+		// fields of extra data are nested in it, though their labels aren't
+		let extra_data = layer.child("extra_data").unwrap();
+		assert!(extra_data.transparent);
+		assert!(extra_data.child("name").is_some());
+
+		let mut depths = vec![];
+		tree.walk(|_, label, depth| depths.push((label.to_string(), depth)));
+		assert_eq!(depths.len(), file.get_indexes().unwrap().len());
+		assert!(depths.contains(&(
+			"layers_resources/layers_info/layer_1/extra_data".to_string(),
+			3
+		)));
+		assert!(depths.contains(&("layers_resources/layers_info/layer_1/name".to_string(), 4)));
+		assert!(depths.contains(&(
+			"layers_resources/layers_info/layer_1/channel_info/channel_0:length".to_string(),
+			5
+		)));
+		assert!(depths.contains(&("image_data/data".to_string(), 1)));
+
+		let mut labels = vec![];
+		layer.walk_nested_first("layers_resources/layers_info", 0, &mut |_, label, _| {
+			labels.push(label.to_string())
+		});
+		assert_eq!(labels[0], "layers_resources/layers_info/layer_1/rect/top");
+		assert_eq!(
+			labels.last().unwrap(),
+			"layers_resources/layers_info/layer_1"
+		);
+	}
+}
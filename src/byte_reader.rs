@@ -0,0 +1,129 @@
+//! Contains `ByteReader` struct
+
+use error::PsdError;
+
+/// Characters of MacRoman encoding from 0x80 to 0xFF
+#[rustfmt::skip]
+static MAC_ROMAN: [char; 128] = [
+	'Ä', 'Å', 'Ç', 'É', 'Ñ', 'Ö', 'Ü', 'á',
+	'à', 'â', 'ä', 'ã', 'å', 'ç', 'é', 'è',
+	'ê', 'ë', 'í', 'ì', 'î', 'ï', 'ñ', 'ó',
+	'ò', 'ô', 'ö', 'õ', 'ú', 'ù', 'û', 'ü',
+	'†', '°', '¢', '£', '§', '•', '¶', 'ß',
+	'®', '©', '™', '´', '¨', '≠', 'Æ', 'Ø',
+	'∞', '±', '≤', '≥', '¥', 'µ', '∂', '∑',
+	'∏', 'π', '∫', 'ª', 'º', 'Ω', 'æ', 'ø',
+	'¿', '¡', '¬', '√', 'ƒ', '≈', '∆', '«',
+	'»', '…', '\u{a0}', 'À', 'Ã', 'Õ', 'Œ', 'œ',
+	'–', '—', '“', '”', '‘', '’', '÷', '◊',
+	'ÿ', 'Ÿ', '⁄', '€', '‹', '›', 'ﬁ', 'ﬂ',
+	'‡', '·', '‚', '„', '‰', 'Â', 'Ê', 'Á',
+	'Ë', 'È', 'Í', 'Î', 'Ï', 'Ì', 'Ó', 'Ô',
+	'\u{f8ff}', 'Ò', 'Ú', 'Û', 'Ù', 'ı', 'ˆ', '˜',
+	'¯', '˘', '˙', '˚', '¸', '˝', '˛', 'ˇ',
+];
+
+/// Decodes MacRoman encoded bytes, used by Pascal strings
+pub fn decode_mac_roman(data: &[u8]) -> String {
+	data.iter()
+		.map(|x| match *x {
+			x if x < 0x80 => x as char,
+			x => MAC_ROMAN[(x - 0x80) as usize],
+		})
+		.collect()
+}
+
+/// Big endian reader over a byte slice, used to decode already loaded blocks
+pub struct ByteReader<'a> {
+	data: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+	pub fn new(data: &'a [u8]) -> Self {
+		Self { data, pos: 0 }
+	}
+
+	pub fn position(&self) -> usize {
+		self.pos
+	}
+
+	pub fn remaining(&self) -> usize {
+		self.data.len() - self.pos
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.remaining() == 0
+	}
+
+	pub fn read_bytes(&mut self, size: usize) -> Result<&'a [u8], PsdError> {
+		if size > self.remaining() {
+			return Err(PsdError::Truncated {
+				label: String::new(),
+				offset: self.pos as u64,
+				expected: size as u64,
+				actual: self.remaining() as u64,
+			});
+		}
+		let out = &self.data[self.pos..self.pos + size];
+		self.pos += size;
+		Ok(out)
+	}
+
+	pub fn read_u8(&mut self) -> Result<u8, PsdError> {
+		Ok(self.read_bytes(1)?[0])
+	}
+
+	pub fn read_u16(&mut self) -> Result<u16, PsdError> {
+		let b = self.read_bytes(2)?;
+		Ok(u16::from(b[0]) << 8 | u16::from(b[1]))
+	}
+
+	pub fn read_u32(&mut self) -> Result<u32, PsdError> {
+		let b = self.read_bytes(4)?;
+		Ok(b.iter().fold(0u32, |c, x| c << 8 | u32::from(*x)))
+	}
+
+	pub fn read_i32(&mut self) -> Result<i32, PsdError> {
+		self.read_u32().map(|x| x as i32)
+	}
+
+	pub fn read_u64(&mut self) -> Result<u64, PsdError> {
+		let b = self.read_bytes(8)?;
+		Ok(b.iter().fold(0u64, |c, x| c << 8 | u64::from(*x)))
+	}
+
+	pub fn read_f64(&mut self) -> Result<f64, PsdError> {
+		self.read_u64().map(f64::from_bits)
+	}
+
+	/// Reads 16.16 fixed point number
+	pub fn read_fixed(&mut self) -> Result<f64, PsdError> {
+		self.read_i32().map(|x| f64::from(x) / 65536.0)
+	}
+
+	/// Reads `len` UTF-16BE code units
+	pub fn read_utf16(&mut self, len: usize) -> Result<Vec<u16>, PsdError> {
+		let offset = self.pos as u64;
+		let size = len.checked_mul(2).ok_or_else(|| PsdError::Malformed {
+			label: String::new(),
+			offset,
+			message: "Unicode string length overflow".to_string(),
+		})?;
+		Ok(self
+			.read_bytes(size)?
+			.chunks(2)
+			.map(|x| u16::from(x[0]) << 8 | u16::from(x[1]))
+			.collect())
+	}
+
+	/// Reads u32 length prefixed UTF-16BE string, dropping trailing nul
+	pub fn read_unicode_string(&mut self) -> Result<String, PsdError> {
+		let len = self.read_u32()? as usize;
+		let mut out = String::from_utf16_lossy(&self.read_utf16(len)?);
+		while out.ends_with('\0') {
+			out.pop();
+		}
+		Ok(out)
+	}
+}
@@ -0,0 +1,379 @@
+//! Contains decoders of the channel data compression methods
+
+use error::PsdError;
+use flate2::read::ZlibDecoder;
+use psd_file::PSDFileType;
+use std::io::Read;
+
+// Max ratios of unpacked to packed size, so hostile sizes don't preallocate
+// more than the data can hold: PackBits repeat run of 2 bytes unpacks to 128 bytes,
+// deflate unpacks to at most 1032 times its size
+const MAX_PACKBITS_RATIO: usize = 64;
+const MAX_DEFLATE_RATIO: usize = 1032;
+
+/// Compression method of the channel data and `image_data`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+	Raw,
+	/// PackBits with byte counts of each row
+	Rle,
+	Zip,
+	ZipWithPrediction,
+}
+
+impl Compression {
+	pub fn from_u16(value: u16) -> Option<Self> {
+		match value {
+			0 => Some(Compression::Raw),
+			1 => Some(Compression::Rle),
+			2 => Some(Compression::Zip),
+			3 => Some(Compression::ZipWithPrediction),
+			_ => None,
+		}
+	}
+
+	pub fn to_u16(self) -> u16 {
+		match self {
+			Compression::Raw => 0,
+			Compression::Rle => 1,
+			Compression::Zip => 2,
+			Compression::ZipWithPrediction => 3,
+		}
+	}
+}
+
+/// Size of the planar channel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelSize {
+	pub width: u32,
+	/// number of rows, height multiplied by channel count for `image_data`
+	pub rows: u32,
+	/// bits per sample: 1, 8, 16 or 32
+	pub depth: u16,
+}
+
+impl ChannelSize {
+	/// Bytes in a row, rows of 1 bit channels are padded to byte
+	pub fn row_size(&self) -> Result<usize, PsdError> {
+		(self.width as usize)
+			.checked_mul(self.depth as usize)
+			.map(|x| x.div_ceil(8))
+			.ok_or_else(|| PsdError::malformed("Channel row size overflow"))
+	}
+
+	pub fn size(&self) -> Result<usize, PsdError> {
+		self.row_size()?
+			.checked_mul(self.rows as usize)
+			.ok_or_else(|| PsdError::malformed("Channel size overflow"))
+	}
+}
+
+/// Decodes single PackBits row, which must unpack to exactly `size` bytes
+pub fn decode_packbits(data: &[u8], size: usize) -> Result<Vec<u8>, PsdError> {
+	let mut out = Vec::with_capacity(size.min(data.len().saturating_mul(MAX_PACKBITS_RATIO)));
+	let mut pos = 0;
+	while pos < data.len() {
+		let header = data[pos] as i8;
+		pos += 1;
+		if header == -128 {
+			continue;
+		}
+		if header >= 0 {
+			let count = header as usize + 1;
+			if pos + count > data.len() {
+				return Err(PsdError::malformed(format!(
+					"PackBits literal run of {} overflows data at {}",
+					count, pos
+				)));
+			}
+			if out.len() + count > size {
+				return Err(PsdError::malformed(format!(
+					"PackBits row exceeds {} bytes",
+					size
+				)));
+			}
+			out.extend_from_slice(&data[pos..pos + count]);
+			pos += count;
+		} else {
+			let count = 1 - header as isize;
+			if pos >= data.len() {
+				return Err(PsdError::malformed(format!(
+					"PackBits repeat run overflows data at {}",
+					pos
+				)));
+			}
+			if out.len() + count as usize > size {
+				return Err(PsdError::malformed(format!(
+					"PackBits row exceeds {} bytes",
+					size
+				)));
+			}
+			let value = data[pos];
+			pos += 1;
+			out.extend((0..count).map(|_| value));
+		}
+	}
+	if out.len() != size {
+		return Err(PsdError::malformed(format!(
+			"PackBits row has {} bytes instead of {}",
+			out.len(),
+			size
+		)));
+	}
+	Ok(out)
+}
+
+/// Decodes RLE compressed channel: byte counts of each row (2 bytes in psd, 4 in psb)
+/// followed by PackBits rows
+pub fn decode_rle(
+	data: &[u8],
+	size: ChannelSize,
+	file_type: PSDFileType,
+) -> Result<Vec<u8>, PsdError> {
+	let count_size = match file_type {
+		PSDFileType::PSD => 2,
+		PSDFileType::PSB => 4,
+	};
+	let row_size = size.row_size()?;
+	let rows = size.rows as usize;
+	let counts_end = rows
+		.checked_mul(count_size)
+		.filter(|x| *x <= data.len())
+		.ok_or_else(|| {
+			PsdError::malformed(format!("RLE row counts of {} rows overflow data", rows))
+		})?;
+
+	let mut out = Vec::with_capacity(
+		size.size()?
+			.min(data.len().saturating_mul(MAX_PACKBITS_RATIO)),
+	);
+	let mut pos = counts_end;
+	for row in 0..rows {
+		let count = data[row * count_size..(row + 1) * count_size]
+			.iter()
+			.fold(0usize, |c, x| c << 8 | *x as usize);
+		if count > data.len() - pos {
+			return Err(PsdError::malformed(format!(
+				"RLE row {} overflows data",
+				row
+			)));
+		}
+		let decoded = decode_packbits(&data[pos..pos + count], row_size)
+			.map_err(|err| PsdError::malformed(format!("RLE row {}: {}", row, err)))?;
+		out.extend_from_slice(&decoded);
+		pos += count;
+	}
+	Ok(out)
+}
+
+/// Inflates zlib stream, which must unpack to exactly `size` bytes
+pub fn decode_zip(data: &[u8], size: usize) -> Result<Vec<u8>, PsdError> {
+	let mut out = Vec::with_capacity(size.min(data.len().saturating_mul(MAX_DEFLATE_RATIO)));
+	ZlibDecoder::new(data)
+		.take(size as u64 + 1)
+		.read_to_end(&mut out)
+		.map_err(|err| PsdError::malformed(format!("ZIP: {}", err)))?;
+	if out.len() != size {
+		return Err(PsdError::malformed(format!(
+			"ZIP data has {} bytes instead of {}",
+			out.len(),
+			size
+		)));
+	}
+	Ok(out)
+}
+
+/// Reverts delta prediction of each row in place.
+/// 8 and 16 bit samples are deltas of the previous sample,
+/// rows of 32 bit samples are split to byte planes, which are deltas of the previous byte
+pub fn decode_prediction(data: &mut [u8], size: ChannelSize) -> Result<(), PsdError> {
+	let row_size = size.row_size()?;
+	if row_size == 0 {
+		return Ok(());
+	}
+	for row in data.chunks_mut(row_size) {
+		match size.depth {
+			8 => {
+				for i in 1..row.len() {
+					row[i] = row[i].wrapping_add(row[i - 1]);
+				}
+			}
+			16 => {
+				let mut prev: u16 = 0;
+				for sample in row.chunks_mut(2) {
+					let value =
+						(u16::from(sample[0]) << 8 | u16::from(sample[1])).wrapping_add(prev);
+					sample[0] = (value >> 8) as u8;
+					sample[1] = value as u8;
+					prev = value;
+				}
+			}
+			32 => {
+				for i in 1..row.len() {
+					row[i] = row[i].wrapping_add(row[i - 1]);
+				}
+				let width = row.len() / 4;
+				let planes = row.to_vec();
+				for i in 0..width {
+					for plane in 0..4 {
+						row[i * 4 + plane] = planes[plane * width + i];
+					}
+				}
+			}
+			x => {
+				return Err(PsdError::Unsupported(format!(
+					"prediction of {} bit channel",
+					x
+				)))
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Decodes channel data, not including compression method, into raw planar rows
+pub fn decode_channel(
+	data: &[u8],
+	compression: Compression,
+	size: ChannelSize,
+	file_type: PSDFileType,
+) -> Result<Vec<u8>, PsdError> {
+	match compression {
+		Compression::Raw => {
+			let expected = size.size()?;
+			if data.len() < expected {
+				return Err(PsdError::malformed(format!(
+					"Raw channel has {} bytes instead of {}",
+					data.len(),
+					expected
+				)));
+			}
+			Ok(data[..expected].to_vec())
+		}
+		Compression::Rle => decode_rle(data, size, file_type),
+		Compression::Zip => decode_zip(data, size.size()?),
+		Compression::ZipWithPrediction => {
+			let mut out = decode_zip(data, size.size()?)?;
+			decode_prediction(&mut out, size)?;
+			Ok(out)
+		}
+	}
+}
+
+#[cfg(test)]
+mod compression_tests {
+	use super::*;
+	use psd_file::PSDFile;
+	use std::fs::File;
+
+	#[test]
+	fn decode_packbits_test() {
+		let data = [
+			0xFE, 0xAA, 0x02, 0x80, 0x00, 0x2A, 0xFD, 0xAA, 0x03, 0x80, 0x00, 0x2A, 0x22, 0xF7,
+			0xAA,
+		];
+		let expected = [
+			0xAA, 0xAA, 0xAA, 0x80, 0x00, 0x2A, 0xAA, 0xAA, 0xAA, 0xAA, 0x80, 0x00, 0x2A, 0x22,
+			0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA,
+		];
+		assert_eq!(decode_packbits(&data, 24).unwrap(), expected.to_vec());
+		assert!(decode_packbits(&data, 23).is_err());
+		assert!(decode_packbits(&data, 25).is_err());
+		assert!(decode_packbits(&data[..14], 24).is_err());
+		assert!(decode_packbits(&[0x05, 0x01], 6).is_err());
+	}
+
+	#[test]
+	fn decode_rle_test() {
+		let size = ChannelSize {
+			width: 4,
+			rows: 2,
+			depth: 8,
+		};
+		let data = [0, 2, 0, 5, 0xFD, 7, 3, 1, 2, 3, 4];
+		assert_eq!(
+			decode_rle(&data, size, PSDFileType::PSD).unwrap(),
+			vec![7, 7, 7, 7, 1, 2, 3, 4]
+		);
+		let data = [0, 0, 0, 2, 0, 0, 0, 5, 0xFD, 7, 3, 1, 2, 3, 4];
+		assert_eq!(
+			decode_rle(&data, size, PSDFileType::PSB).unwrap(),
+			vec![7, 7, 7, 7, 1, 2, 3, 4]
+		);
+		assert!(decode_rle(&data[..3], size, PSDFileType::PSB).is_err());
+		assert!(decode_rle(&[0, 2, 0, 9, 0xFD, 7, 3, 1], size, PSDFileType::PSD).is_err());
+	}
+
+	#[test]
+	fn decode_prediction_test() {
+		let size = ChannelSize {
+			width: 3,
+			rows: 2,
+			depth: 16,
+		};
+		let mut data = vec![0, 1, 0, 1, 0xFF, 0xFF, 1, 0, 0, 0, 0, 2];
+		decode_prediction(&mut data, size).unwrap();
+		assert_eq!(data, vec![0, 1, 0, 2, 0, 1, 1, 0, 1, 0, 1, 2]);
+
+		let size = ChannelSize {
+			width: 2,
+			rows: 1,
+			depth: 32,
+		};
+		// planes of 1.0 (3F800000) and 2.0 (40000000)
+		let mut data = vec![0x3F, 0x01, 0x40, 0x80, 0x00, 0x00, 0x00, 0x00];
+		decode_prediction(&mut data, size).unwrap();
+		assert_eq!(data, vec![0x3F, 0x80, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00]);
+	}
+
+	fn to_f32(data: &[u8]) -> Vec<f32> {
+		data.chunks(4)
+			.map(|x| f32::from_bits(x.iter().fold(0u32, |c, b| c << 8 | u32::from(*b))))
+			.collect()
+	}
+
+	#[test]
+	fn decode_zip_test() {
+		let file = File::open("./test_data/16bit.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		// "Red" layer is ZIP with prediction
+		let red = file.get_layer_channel(0, 1).unwrap();
+		assert_eq!(red.len(), 4 * 3 * 2);
+		assert_eq!(&red[0..8], &[0, 0, 0x1A, 0x0A, 0x34, 0x15, 0x4E, 0x20]);
+		assert_eq!(&red[16..18], &[0x07, 0xD0]);
+		// "Blue" layer is ZIP without prediction
+		let alpha = file.get_layer_channel(1, 0).unwrap();
+		assert_eq!(alpha, vec![0xFF, 0xFF, 0x80, 0x00, 0x80, 0x00, 0x00, 0x00]);
+
+		let file = File::open("./test_data/32bit.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		let red = to_f32(&file.get_layer_channel(0, 1).unwrap());
+		assert_eq!(red.len(), 4 * 3);
+		assert_eq!(&red[0..4], &[0.0, 0.25, 0.5, 0.75]);
+		assert_eq!(red[4], 0.125);
+
+		let channels = file.get_image_data_channels().unwrap();
+		assert_eq!(channels.len(), 3);
+		assert_eq!(to_f32(&channels[0]), red);
+		assert_eq!(to_f32(&channels[1]), vec![0.5; 12]);
+	}
+
+	#[test]
+	fn get_layer_channel_test() {
+		let file = File::open("./test_data/a_a.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		for channel in 0..4 {
+			let data = file.get_layer_channel(0, channel).unwrap();
+			assert_eq!(data.len(), 16 * 21);
+		}
+		// transparency of "art" layer is opaque in the middle
+		let alpha = file.get_layer_channel(0, 0).unwrap();
+		assert_eq!(alpha[10 * 16 + 8], 255);
+		// empty user mask
+		assert!(file.get_layer_channel(0, 4).unwrap().is_empty());
+		// empty layer
+		assert!(file.get_layer_channel(1, 0).unwrap().is_empty());
+		assert!(file.get_layer_channel(0, 5).is_err());
+		assert!(file.get_layer_channel(2, 0).is_err());
+	}
+}
@@ -0,0 +1,800 @@
+//! Contains `Descriptor` struct, decoder and encoder of Photoshop action descriptors.
+//! Descriptors store payload of text layers, effects, smart objects, fill layers and
+//! many other tagged blocks and image resources
+
+use byte_reader::ByteReader;
+use error::PsdError;
+use std::fmt;
+
+/// Descriptor version which precedes descriptors in tagged blocks and image resources
+pub const DESCRIPTOR_VERSION: u32 = 16;
+
+/// Max nesting of descriptors and lists
+const MAX_DEPTH: usize = 64;
+
+/// Key, class id, type id or enum value of the descriptor
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Id {
+	/// 4 character code, e.g. `Txt `, stored with zero length
+	Code([u8; 4]),
+	/// Name stored with its length, e.g. `textGridding`
+	Name(Vec<u8>),
+}
+
+impl Id {
+	/// Creates 4 character code from 4 byte `id` and name from the rest
+	pub fn new(id: &str) -> Self {
+		let bytes = id.as_bytes();
+		if bytes.len() == 4 {
+			let mut code = [0; 4];
+			code.copy_from_slice(bytes);
+			Id::Code(code)
+		} else {
+			Id::Name(bytes.to_vec())
+		}
+	}
+
+	pub fn as_bytes(&self) -> &[u8] {
+		match self {
+			Id::Code(code) => code,
+			Id::Name(name) => name,
+		}
+	}
+
+	/// Checks that id is `id`, regardless of the way it's stored
+	pub fn is(&self, id: &str) -> bool {
+		self.as_bytes() == id.as_bytes()
+	}
+}
+
+impl fmt::Display for Id {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", String::from_utf8_lossy(self.as_bytes()))
+	}
+}
+
+/// UTF-16 string, stored with terminating nul which isn't part of `value`.
+/// Some writers omit the nul, such strings have `terminated` false,
+/// so they're written back the same way
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnicodeString {
+	pub value: String,
+	pub terminated: bool,
+}
+
+impl From<String> for UnicodeString {
+	fn from(value: String) -> Self {
+		Self {
+			value,
+			terminated: true,
+		}
+	}
+}
+
+impl From<&str> for UnicodeString {
+	fn from(value: &str) -> Self {
+		Self::from(value.to_string())
+	}
+}
+
+impl fmt::Display for UnicodeString {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.value)
+	}
+}
+
+/// Class of the object: display name and class id
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Class {
+	pub name: UnicodeString,
+	pub class_id: Id,
+}
+
+/// Item of the reference (`obj `) value
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReferenceItem {
+	/// `prop`
+	Property { class: Class, key: Id },
+	/// `Clss`
+	Class(Class),
+	/// `Enmr`
+	Enumerated {
+		class: Class,
+		type_id: Id,
+		value: Id,
+	},
+	/// `rele`
+	Offset { class: Class, value: i32 },
+	/// `Idnt`
+	Identifier(u32),
+	/// `indx`
+	Index(u32),
+	/// `name`
+	Name { class: Class, name: UnicodeString },
+}
+
+/// Value of the descriptor item or list
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+	/// `obj `
+	Reference(Vec<ReferenceItem>),
+	/// `Objc`
+	Descriptor(Descriptor),
+	/// `GlbO`
+	GlobalObject(Descriptor),
+	/// `VlLs`
+	List(Vec<Value>),
+	/// `ObAr`, array of `count` objects stored as descriptor of `UnFl` lists of their values,
+	/// e.g. horizontal and vertical coordinates of the points
+	ObjectArray { count: u32, descriptor: Descriptor },
+	/// `doub`
+	Double(f64),
+	/// `UntF`, unit is one of `#Ang`, `#Rsl`, `#Rlt`, `#Nne`, `#Prc`, `#Pxl`
+	UnitFloat { unit: [u8; 4], value: f64 },
+	/// `UnFl`
+	UnitFloats { unit: [u8; 4], values: Vec<f64> },
+	/// `TEXT`
+	Text(UnicodeString),
+	/// `enum`
+	Enumerated { type_id: Id, value: Id },
+	/// `long`
+	Integer(i32),
+	/// `comp`
+	LargeInteger(i64),
+	/// `bool`
+	Boolean(bool),
+	/// `type`
+	Class(Class),
+	/// `GlbC`
+	GlobalClass(Class),
+	/// `alis`
+	Alias(Vec<u8>),
+	/// `tdta`
+	RawData(Vec<u8>),
+	/// `Pth `
+	Path(Vec<u8>),
+}
+
+impl Value {
+	fn os_type(&self) -> &'static [u8; 4] {
+		match self {
+			Value::Reference(_) => b"obj ",
+			Value::Descriptor(_) => b"Objc",
+			Value::GlobalObject(_) => b"GlbO",
+			Value::List(_) => b"VlLs",
+			Value::ObjectArray { .. } => b"ObAr",
+			Value::Double(_) => b"doub",
+			Value::UnitFloat { .. } => b"UntF",
+			Value::UnitFloats { .. } => b"UnFl",
+			Value::Text(_) => b"TEXT",
+			Value::Enumerated { .. } => b"enum",
+			Value::Integer(_) => b"long",
+			Value::LargeInteger(_) => b"comp",
+			Value::Boolean(_) => b"bool",
+			Value::Class(_) => b"type",
+			Value::GlobalClass(_) => b"GlbC",
+			Value::Alias(_) => b"alis",
+			Value::RawData(_) => b"tdta",
+			Value::Path(_) => b"Pth ",
+		}
+	}
+
+	/// Gets number of `doub`, `UntF`, `long` and `comp` values
+	pub fn as_f64(&self) -> Option<f64> {
+		match self {
+			Value::Double(x) => Some(*x),
+			Value::UnitFloat { value, .. } => Some(*value),
+			Value::Integer(x) => Some(f64::from(*x)),
+			Value::LargeInteger(x) => Some(*x as f64),
+			_ => None,
+		}
+	}
+
+	pub fn as_i32(&self) -> Option<i32> {
+		match self {
+			Value::Integer(x) => Some(*x),
+			_ => None,
+		}
+	}
+
+	pub fn as_bool(&self) -> Option<bool> {
+		match self {
+			Value::Boolean(x) => Some(*x),
+			_ => None,
+		}
+	}
+
+	pub fn as_str(&self) -> Option<&str> {
+		match self {
+			Value::Text(x) => Some(&x.value),
+			_ => None,
+		}
+	}
+
+	/// Gets descriptor of `Objc` and `GlbO` values
+	pub fn as_descriptor(&self) -> Option<&Descriptor> {
+		match self {
+			Value::Descriptor(x) | Value::GlobalObject(x) => Some(x),
+			_ => None,
+		}
+	}
+
+	pub fn as_list(&self) -> Option<&[Value]> {
+		match self {
+			Value::List(x) => Some(x),
+			_ => None,
+		}
+	}
+
+	/// Gets value of `enum`
+	pub fn as_enum(&self) -> Option<&Id> {
+		match self {
+			Value::Enumerated { value, .. } => Some(value),
+			_ => None,
+		}
+	}
+
+	/// Gets data of `tdta` and `alis` values
+	pub fn as_data(&self) -> Option<&[u8]> {
+		match self {
+			Value::RawData(x) | Value::Alias(x) => Some(x),
+			_ => None,
+		}
+	}
+}
+
+/// Action descriptor: class and list of keyed values
+#[derive(Clone, Debug, PartialEq)]
+pub struct Descriptor {
+	pub class: Class,
+	pub items: Vec<(Id, Value)>,
+}
+
+impl Descriptor {
+	pub fn new(class_id: &str) -> Self {
+		Self {
+			class: Class {
+				name: "".into(),
+				class_id: Id::new(class_id),
+			},
+			items: vec![],
+		}
+	}
+
+	/// Gets value of the first item with `key`
+	pub fn get(&self, key: &str) -> Option<&Value> {
+		self.items.iter().find(|x| x.0.is(key)).map(|x| &x.1)
+	}
+
+	pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+		self.items
+			.iter_mut()
+			.find(|x| x.0.is(key))
+			.map(|x| &mut x.1)
+	}
+
+	/// Replaces value of the item with `key` or appends new item
+	pub fn set(&mut self, key: &str, value: Value) {
+		match self.get_mut(key) {
+			Some(x) => *x = value,
+			None => self.items.push((Id::new(key), value)),
+		}
+	}
+
+	/// Decodes descriptor at the start of `data`, data following it is ignored
+	pub fn decode(data: &[u8]) -> Result<Self, PsdError> {
+		read_descriptor(&mut ByteReader::new(data), 0)
+	}
+
+	/// Decodes descriptor preceded by its version
+	pub fn decode_versioned(data: &[u8]) -> Result<Self, PsdError> {
+		let mut r = ByteReader::new(data);
+		read_versioned(&mut r)
+	}
+
+	pub fn encode(&self) -> Vec<u8> {
+		let mut out = vec![];
+		write_descriptor(&mut out, self);
+		out
+	}
+
+	/// Encodes descriptor preceded by `DESCRIPTOR_VERSION`
+	pub fn encode_versioned(&self) -> Vec<u8> {
+		let mut out = DESCRIPTOR_VERSION.to_be_bytes().to_vec();
+		write_descriptor(&mut out, self);
+		out
+	}
+}
+
+/// Reads u32 length prefixed UTF-16BE string. Strings are stored with terminating nul,
+/// which is dropped and added back by `write_string` if it was there
+fn read_string(r: &mut ByteReader) -> Result<UnicodeString, PsdError> {
+	let len = r.read_u32()? as usize;
+	let units = r.read_utf16(len)?;
+	let (units, terminated) = match units.split_last() {
+		Some((0, rest)) => (rest, true),
+		_ => (&units[..], false),
+	};
+	Ok(UnicodeString {
+		value: String::from_utf16_lossy(units),
+		terminated,
+	})
+}
+
+fn read_code(r: &mut ByteReader) -> Result<[u8; 4], PsdError> {
+	let mut code = [0; 4];
+	code.copy_from_slice(r.read_bytes(4)?);
+	Ok(code)
+}
+
+fn read_id(r: &mut ByteReader) -> Result<Id, PsdError> {
+	match r.read_u32()? {
+		0 => Ok(Id::Code(read_code(r)?)),
+		len => Ok(Id::Name(r.read_bytes(len as usize)?.to_vec())),
+	}
+}
+
+fn read_class(r: &mut ByteReader) -> Result<Class, PsdError> {
+	Ok(Class {
+		name: read_string(r)?,
+		class_id: read_id(r)?,
+	})
+}
+
+fn read_data(r: &mut ByteReader) -> Result<Vec<u8>, PsdError> {
+	let len = r.read_u32()?;
+	Ok(r.read_bytes(len as usize)?.to_vec())
+}
+
+/// Reads descriptor version, which must be `DESCRIPTOR_VERSION`, and descriptor
+pub(crate) fn read_versioned(r: &mut ByteReader) -> Result<Descriptor, PsdError> {
+	let offset = r.position() as u64;
+	let version = r.read_u32()?;
+	if version != DESCRIPTOR_VERSION {
+		return Err(PsdError::InvalidValue {
+			label: String::new(),
+			offset,
+			value: u64::from(version),
+		});
+	}
+	read_descriptor(r, 0)
+}
+
+/// Reads descriptor nested in `depth` descriptors and lists
+pub(crate) fn read_descriptor(r: &mut ByteReader, depth: usize) -> Result<Descriptor, PsdError> {
+	let class = read_class(r)?;
+	let count = r.read_u32()?;
+	let mut items = vec![];
+	for _ in 0..count {
+		let key = read_id(r)?;
+		let value = read_value(r, depth)?;
+		items.push((key, value));
+	}
+	Ok(Descriptor { class, items })
+}
+
+fn read_reference(r: &mut ByteReader) -> Result<Vec<ReferenceItem>, PsdError> {
+	let count = r.read_u32()?;
+	let mut items = vec![];
+	for _ in 0..count {
+		let offset = r.position() as u64;
+		let item = match &read_code(r)? {
+			b"prop" => ReferenceItem::Property {
+				class: read_class(r)?,
+				key: read_id(r)?,
+			},
+			b"Clss" => ReferenceItem::Class(read_class(r)?),
+			b"Enmr" => ReferenceItem::Enumerated {
+				class: read_class(r)?,
+				type_id: read_id(r)?,
+				value: read_id(r)?,
+			},
+			b"rele" => ReferenceItem::Offset {
+				class: read_class(r)?,
+				value: r.read_i32()?,
+			},
+			b"Idnt" => ReferenceItem::Identifier(r.read_u32()?),
+			b"indx" => ReferenceItem::Index(r.read_u32()?),
+			b"name" => ReferenceItem::Name {
+				class: read_class(r)?,
+				name: read_string(r)?,
+			},
+			x => {
+				return Err(PsdError::Malformed {
+					label: String::new(),
+					offset,
+					message: format!("unknown reference type \"{}\"", String::from_utf8_lossy(x)),
+				})
+			}
+		};
+		items.push(item);
+	}
+	Ok(items)
+}
+
+/// Reads OSType and value of the descriptor item or list nested in `depth` descriptors and lists
+fn read_value(r: &mut ByteReader, depth: usize) -> Result<Value, PsdError> {
+	let offset = r.position() as u64;
+	if depth > MAX_DEPTH {
+		return Err(PsdError::LimitExceeded {
+			label: String::new(),
+			offset,
+			value: depth as u64,
+			limit: MAX_DEPTH as u64,
+		});
+	}
+	let value = match &read_code(r)? {
+		b"obj " => Value::Reference(read_reference(r)?),
+		b"Objc" => Value::Descriptor(read_descriptor(r, depth + 1)?),
+		b"GlbO" => Value::GlobalObject(read_descriptor(r, depth + 1)?),
+		b"VlLs" => {
+			let count = r.read_u32()?;
+			let mut values = vec![];
+			for _ in 0..count {
+				values.push(read_value(r, depth + 1)?);
+			}
+			Value::List(values)
+		}
+		b"ObAr" => Value::ObjectArray {
+			count: r.read_u32()?,
+			descriptor: read_descriptor(r, depth + 1)?,
+		},
+		b"doub" => Value::Double(r.read_f64()?),
+		b"UntF" => Value::UnitFloat {
+			unit: read_code(r)?,
+			value: r.read_f64()?,
+		},
+		b"UnFl" => {
+			let unit = read_code(r)?;
+			let count = r.read_u32()?;
+			let mut values = vec![];
+			for _ in 0..count {
+				values.push(r.read_f64()?);
+			}
+			Value::UnitFloats { unit, values }
+		}
+		b"TEXT" => Value::Text(read_string(r)?),
+		b"enum" => Value::Enumerated {
+			type_id: read_id(r)?,
+			value: read_id(r)?,
+		},
+		b"long" => Value::Integer(r.read_i32()?),
+		b"comp" => Value::LargeInteger(r.read_u64()? as i64),
+		b"bool" => Value::Boolean(r.read_u8()? != 0),
+		b"type" => Value::Class(read_class(r)?),
+		b"GlbC" => Value::GlobalClass(read_class(r)?),
+		b"alis" => Value::Alias(read_data(r)?),
+		b"tdta" => Value::RawData(read_data(r)?),
+		b"Pth " => Value::Path(read_data(r)?),
+		x => {
+			return Err(PsdError::Malformed {
+				label: String::new(),
+				offset,
+				message: format!(
+					"unknown descriptor value type \"{}\"",
+					String::from_utf8_lossy(x)
+				),
+			})
+		}
+	};
+	Ok(value)
+}
+
+fn write_string(out: &mut Vec<u8>, value: &UnicodeString) {
+	let nul = if value.terminated { Some(0) } else { None };
+	let units: Vec<u16> = value.value.encode_utf16().chain(nul).collect();
+	out.extend_from_slice(&(units.len() as u32).to_be_bytes());
+	for unit in units {
+		out.extend_from_slice(&unit.to_be_bytes());
+	}
+}
+
+fn write_id(out: &mut Vec<u8>, id: &Id) {
+	match id {
+		Id::Code(code) => {
+			out.extend_from_slice(&0u32.to_be_bytes());
+			out.extend_from_slice(code);
+		}
+		Id::Name(name) => write_data(out, name),
+	}
+}
+
+fn write_class(out: &mut Vec<u8>, class: &Class) {
+	write_string(out, &class.name);
+	write_id(out, &class.class_id);
+}
+
+fn write_data(out: &mut Vec<u8>, data: &[u8]) {
+	out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+	out.extend_from_slice(data);
+}
+
+pub(crate) fn write_descriptor(out: &mut Vec<u8>, descriptor: &Descriptor) {
+	write_class(out, &descriptor.class);
+	out.extend_from_slice(&(descriptor.items.len() as u32).to_be_bytes());
+	for (key, value) in &descriptor.items {
+		write_id(out, key);
+		write_value(out, value);
+	}
+}
+
+fn write_reference_item(out: &mut Vec<u8>, item: &ReferenceItem) {
+	match item {
+		ReferenceItem::Property { class, key } => {
+			out.extend_from_slice(b"prop");
+			write_class(out, class);
+			write_id(out, key);
+		}
+		ReferenceItem::Class(class) => {
+			out.extend_from_slice(b"Clss");
+			write_class(out, class);
+		}
+		ReferenceItem::Enumerated {
+			class,
+			type_id,
+			value,
+		} => {
+			out.extend_from_slice(b"Enmr");
+			write_class(out, class);
+			write_id(out, type_id);
+			write_id(out, value);
+		}
+		ReferenceItem::Offset { class, value } => {
+			out.extend_from_slice(b"rele");
+			write_class(out, class);
+			out.extend_from_slice(&value.to_be_bytes());
+		}
+		ReferenceItem::Identifier(value) => {
+			out.extend_from_slice(b"Idnt");
+			out.extend_from_slice(&value.to_be_bytes());
+		}
+		ReferenceItem::Index(value) => {
+			out.extend_from_slice(b"indx");
+			out.extend_from_slice(&value.to_be_bytes());
+		}
+		ReferenceItem::Name { class, name } => {
+			out.extend_from_slice(b"name");
+			write_class(out, class);
+			write_string(out, name);
+		}
+	}
+}
+
+/// Writes OSType and value
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+	out.extend_from_slice(value.os_type());
+	match value {
+		Value::Reference(items) => {
+			out.extend_from_slice(&(items.len() as u32).to_be_bytes());
+			for item in items {
+				write_reference_item(out, item);
+			}
+		}
+		Value::Descriptor(descriptor) | Value::GlobalObject(descriptor) => {
+			write_descriptor(out, descriptor)
+		}
+		Value::List(values) => {
+			out.extend_from_slice(&(values.len() as u32).to_be_bytes());
+			for value in values {
+				write_value(out, value);
+			}
+		}
+		Value::ObjectArray { count, descriptor } => {
+			out.extend_from_slice(&count.to_be_bytes());
+			write_descriptor(out, descriptor);
+		}
+		Value::Double(value) => out.extend_from_slice(&value.to_bits().to_be_bytes()),
+		Value::UnitFloat { unit, value } => {
+			out.extend_from_slice(unit);
+			out.extend_from_slice(&value.to_bits().to_be_bytes());
+		}
+		Value::UnitFloats { unit, values } => {
+			out.extend_from_slice(unit);
+			out.extend_from_slice(&(values.len() as u32).to_be_bytes());
+			for value in values {
+				out.extend_from_slice(&value.to_bits().to_be_bytes());
+			}
+		}
+		Value::Text(value) => write_string(out, value),
+		Value::Enumerated { type_id, value } => {
+			write_id(out, type_id);
+			write_id(out, value);
+		}
+		Value::Integer(value) => out.extend_from_slice(&value.to_be_bytes()),
+		Value::LargeInteger(value) => out.extend_from_slice(&value.to_be_bytes()),
+		Value::Boolean(value) => out.push(*value as u8),
+		Value::Class(class) | Value::GlobalClass(class) => write_class(out, class),
+		Value::Alias(data) | Value::RawData(data) | Value::Path(data) => write_data(out, data),
+	}
+}
+
+#[cfg(test)]
+mod descriptor_tests {
+	use super::*;
+	use psd_file::PSDFile;
+
+	#[test]
+	fn round_trip_test() {
+		let mut file = PSDFile::open("./test_data/a_a.psd").unwrap();
+		// print settings resources
+		for id in &[1082, 1083] {
+			let resource = file
+				.get_image_resources()
+				.unwrap()
+				.iter()
+				.find(|x| x.id == *id)
+				.cloned()
+				.unwrap();
+			let data = file
+				.read_range(resource.data_start, resource.data_size)
+				.unwrap();
+			let descriptor = Descriptor::decode_versioned(&data).unwrap();
+			assert_eq!(descriptor.encode_versioned(), data);
+			assert_eq!(Descriptor::decode(&data[4..]).unwrap(), descriptor);
+		}
+
+		let data = {
+			let resource = file
+				.get_image_resources()
+				.unwrap()
+				.iter()
+				.find(|x| x.id == 1082)
+				.cloned()
+				.unwrap();
+			file.read_range(resource.data_start, resource.data_size)
+				.unwrap()
+		};
+		let descriptor = Descriptor::decode_versioned(&data).unwrap();
+		assert!(descriptor.class.class_id.is("printOutput"));
+		assert_eq!(descriptor.get("PstS").and_then(|x| x.as_bool()), Some(true));
+
+		// solid color fill layer
+		let mut file = PSDFile::open("./test_data/b_a.psd").unwrap();
+		let block = file
+			.get_layers()
+			.unwrap()
+			.iter()
+			.flat_map(|x| x.tagged_blocks.clone())
+			.find(|x| x.key == "SoCo")
+			.unwrap();
+		let data = file.read_range(block.data_start, block.data_size).unwrap();
+		let descriptor = Descriptor::decode_versioned(&data).unwrap();
+		assert_eq!(descriptor.encode_versioned(), data);
+		let color = descriptor
+			.get("Clr ")
+			.and_then(|x| x.as_descriptor())
+			.unwrap();
+		assert!(color.class.class_id.is("RGBC"));
+		assert!(color.get("Rd  ").and_then(|x| x.as_f64()).is_some());
+	}
+
+	#[test]
+	fn encode_test() {
+		let mut descriptor = Descriptor::new("null");
+		descriptor.set("Nm  ", Value::Text("Layer 1".into()));
+		descriptor.set("textGridding", Value::Integer(-3));
+		descriptor.set(
+			"Ofst",
+			Value::List(vec![
+				Value::UnitFloat {
+					unit: *b"#Pxl",
+					value: 1.5,
+				},
+				Value::Reference(vec![
+					ReferenceItem::Identifier(7),
+					ReferenceItem::Name {
+						class: Class {
+							name: "Layer".into(),
+							class_id: Id::new("Lyr "),
+						},
+						name: "Background".into(),
+					},
+				]),
+			]),
+		);
+		descriptor.set("Nm  ", Value::Text("Layer 2".into()));
+
+		let data = descriptor.encode();
+		assert_eq!(&data[..6], &[0, 0, 0, 1, 0, 0]);
+		assert_eq!(&data[6..14], b"\0\0\0\0null");
+		assert_eq!(Descriptor::decode(&data).unwrap(), descriptor);
+		assert_eq!(descriptor.items.len(), 3);
+		assert_eq!(
+			descriptor.get("Nm  ").and_then(|x| x.as_str()),
+			Some("Layer 2")
+		);
+		assert_eq!(
+			descriptor.get("textGridding").and_then(|x| x.as_i32()),
+			Some(-3)
+		);
+	}
+
+	#[test]
+	fn errors_test() {
+		let mut data = Descriptor::new("null").encode();
+		data[17] = 1;
+		data.extend_from_slice(b"\0\0\0\0keyXwhat");
+		match Descriptor::decode(&data) {
+			Err(PsdError::Malformed { offset, .. }) => assert_eq!(offset, 26),
+			x => panic!("expected Malformed, got {:?}", x),
+		}
+		assert!(Descriptor::decode(&data[..8]).is_err());
+		match Descriptor::decode_versioned(&[0, 0, 0, 6]) {
+			Err(PsdError::InvalidValue { value: 6, .. }) => (),
+			x => panic!("expected InvalidValue, got {:?}", x),
+		}
+
+		// descriptors nested deeper than limit
+		let mut data = vec![];
+		for _ in 0..100 {
+			data.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+			data.extend_from_slice(b"null\0\0\0\x01\0\0\0\0nestObjc");
+		}
+		match Descriptor::decode(&data) {
+			Err(PsdError::LimitExceeded { limit, .. }) => assert_eq!(limit, MAX_DEPTH as u64),
+			x => panic!("expected LimitExceeded, got {:?}", x.map(|_| ())),
+		}
+
+		// lists nested deeper than limit
+		let mut data = Descriptor::new("null").encode();
+		data[17] = 1;
+		data.extend_from_slice(b"\0\0\0\0nest");
+		for _ in 0..100_000 {
+			data.extend_from_slice(b"VlLs\0\0\0\x01");
+		}
+		match Descriptor::decode(&data) {
+			Err(PsdError::LimitExceeded { offset, limit, .. }) => {
+				assert_eq!(offset, 26 + 8 * (MAX_DEPTH as u64 + 1));
+				assert_eq!(limit, MAX_DEPTH as u64);
+			}
+			x => panic!("expected LimitExceeded, got {:?}", x.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn exact_round_trip_test() {
+		let mut descriptor = Descriptor::new("null");
+		descriptor.class.name = UnicodeString {
+			value: "unterminated".to_string(),
+			terminated: false,
+		};
+		descriptor.set("Nm  ", Value::Text("Layer 1".into()));
+		descriptor.set(
+			"Pts ",
+			Value::ObjectArray {
+				count: 2,
+				descriptor: {
+					let mut points = Descriptor::new("rationalPoint");
+					for key in &["Hrzn", "Vrtc"] {
+						points.set(
+							key,
+							Value::UnitFloats {
+								unit: *b"#Pxl",
+								values: vec![1.0, 2.5],
+							},
+						);
+					}
+					points
+				},
+			},
+		);
+		let data = descriptor.encode();
+		// name length is 12 characters without nul
+		assert_eq!(&data[..4], &[0, 0, 0, 12]);
+		let decoded = Descriptor::decode(&data).unwrap();
+		assert_eq!(decoded, descriptor);
+		assert_eq!(decoded.encode(), data);
+		assert!(!decoded.class.name.terminated);
+		assert_eq!(
+			decoded.get("Nm  ").and_then(|x| x.as_str()),
+			Some("Layer 1")
+		);
+		match decoded.get("Pts ") {
+			Some(Value::ObjectArray { count, descriptor }) => {
+				assert_eq!(*count, 2);
+				assert!(descriptor.class.class_id.is("rationalPoint"));
+			}
+			x => panic!("expected ObjectArray, got {:?}", x),
+		}
+	}
+}
@@ -0,0 +1,925 @@
+//! Contains `LayerEffects` struct, decoded layer styles of `lmfx`, `lfx2` and legacy `lrFX`
+//! tagged blocks
+
+use byte_reader::ByteReader;
+use descriptor::{read_versioned, Descriptor, Id};
+use error::PsdError;
+use layer::BlendMode;
+
+/// Key of the tagged block of descriptor based effects
+pub const EFFECTS_KEY: &str = "lfx2";
+/// Key of the tagged block of descriptor based effects with several instances of the same effect,
+/// e.g. two drop shadows, written along with `lfx2` since CC 2015
+pub const MULTI_EFFECTS_KEY: &str = "lmfx";
+/// Key of the tagged block of legacy effects, written along with `lfx2` by old versions
+pub const LEGACY_EFFECTS_KEY: &str = "lrFX";
+
+/// Blend modes of the `BlnM` descriptor enum
+static DESCRIPTOR_BLEND_MODES: [(&str, BlendMode); 28] = [
+	("passThrough", BlendMode::PassThrough),
+	("Nrml", BlendMode::Normal),
+	("Dslv", BlendMode::Dissolve),
+	("Drkn", BlendMode::Darken),
+	("Mltp", BlendMode::Multiply),
+	("CBrn", BlendMode::ColorBurn),
+	("linearBurn", BlendMode::LinearBurn),
+	("darkerColor", BlendMode::DarkerColor),
+	("Lghn", BlendMode::Lighten),
+	("Scrn", BlendMode::Screen),
+	("CDdg", BlendMode::ColorDodge),
+	("linearDodge", BlendMode::LinearDodge),
+	("lighterColor", BlendMode::LighterColor),
+	("Ovrl", BlendMode::Overlay),
+	("SftL", BlendMode::SoftLight),
+	("HrdL", BlendMode::HardLight),
+	("vividLight", BlendMode::VividLight),
+	("linearLight", BlendMode::LinearLight),
+	("pinLight", BlendMode::PinLight),
+	("hardMix", BlendMode::HardMix),
+	("Dfrn", BlendMode::Difference),
+	("Xclu", BlendMode::Exclusion),
+	("blendSubtraction", BlendMode::Subtract),
+	("blendDivide", BlendMode::Divide),
+	("H   ", BlendMode::Hue),
+	("Strt", BlendMode::Saturation),
+	("Clr ", BlendMode::Color),
+	("Lmns", BlendMode::Luminosity),
+];
+
+/// RGB color, components are from 0 to 255
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+	pub red: f64,
+	pub green: f64,
+	pub blue: f64,
+}
+
+/// Drop shadow or inner shadow. Distances are in pixels, angles in degrees, opacity in percent
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shadow {
+	pub enabled: bool,
+	pub blend_mode: BlendMode,
+	pub color: Option<Color>,
+	pub opacity: f64,
+	pub angle: f64,
+	/// angle is taken from the global light resource instead of `angle`
+	pub use_global_light: bool,
+	pub distance: f64,
+	/// spread of drop shadow or choke of inner shadow in percent
+	pub spread: f64,
+	pub size: f64,
+}
+
+/// Outer glow or inner glow
+#[derive(Clone, Debug, PartialEq)]
+pub struct Glow {
+	pub enabled: bool,
+	pub blend_mode: BlendMode,
+	/// `None` for gradient glow
+	pub color: Option<Color>,
+	pub opacity: f64,
+	/// spread of outer glow or choke of inner glow in percent
+	pub spread: f64,
+	pub size: f64,
+	pub noise: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BevelStyle {
+	OuterBevel,
+	InnerBevel,
+	Emboss,
+	PillowEmboss,
+	StrokeEmboss,
+	Unknown,
+}
+
+/// Bevel and emboss
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bevel {
+	pub enabled: bool,
+	pub style: BevelStyle,
+	/// depth in percent
+	pub depth: f64,
+	/// direction is up, otherwise down
+	pub up: bool,
+	pub size: f64,
+	pub soften: f64,
+	pub angle: f64,
+	pub altitude: f64,
+	pub use_global_light: bool,
+	pub highlight_blend_mode: BlendMode,
+	pub highlight_color: Option<Color>,
+	pub highlight_opacity: f64,
+	pub shadow_blend_mode: BlendMode,
+	pub shadow_color: Option<Color>,
+	pub shadow_opacity: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Satin {
+	pub enabled: bool,
+	pub blend_mode: BlendMode,
+	pub color: Option<Color>,
+	pub opacity: f64,
+	pub angle: f64,
+	pub distance: f64,
+	pub size: f64,
+	pub invert: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorOverlay {
+	pub enabled: bool,
+	pub blend_mode: BlendMode,
+	pub color: Option<Color>,
+	pub opacity: f64,
+}
+
+/// Color of the gradient at `location` from 0 to 1
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorStop {
+	pub location: f64,
+	pub color: Option<Color>,
+}
+
+/// Opacity in percent of the gradient at `location` from 0 to 1
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpacityStop {
+	pub location: f64,
+	pub opacity: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gradient {
+	pub name: String,
+	pub colors: Vec<ColorStop>,
+	pub opacities: Vec<OpacityStop>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GradientStyle {
+	Linear,
+	Radial,
+	Angle,
+	Reflected,
+	Diamond,
+	Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GradientOverlay {
+	pub enabled: bool,
+	pub blend_mode: BlendMode,
+	pub opacity: f64,
+	pub gradient: Option<Gradient>,
+	pub style: GradientStyle,
+	pub angle: f64,
+	/// scale in percent
+	pub scale: f64,
+	pub reverse: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PatternOverlay {
+	pub enabled: bool,
+	pub blend_mode: BlendMode,
+	pub opacity: f64,
+	pub name: String,
+	/// id of the pattern in `Patt` tagged block
+	pub id: String,
+	/// scale in percent
+	pub scale: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StrokePosition {
+	Outside,
+	Inside,
+	Center,
+	Unknown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StrokeFill {
+	Color,
+	Gradient,
+	Pattern,
+	Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stroke {
+	pub enabled: bool,
+	pub blend_mode: BlendMode,
+	pub opacity: f64,
+	pub size: f64,
+	pub position: StrokePosition,
+	pub fill: StrokeFill,
+	/// color of the `StrokeFill::Color` stroke
+	pub color: Option<Color>,
+	/// gradient of the `StrokeFill::Gradient` stroke
+	pub gradient: Option<Gradient>,
+}
+
+/// Layer style. Effects which were never turned on aren't stored, so they're `None`.
+/// Drop shadow, inner shadow, color overlay, gradient overlay and stroke may be added several
+/// times, `_multi` fields hold all of them from top to bottom and the single field holds the first
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerEffects {
+	/// Effects are shown, each effect is also turned on by its own `enabled`
+	pub enabled: bool,
+	/// Scale of effects in percent
+	pub scale: f64,
+	pub drop_shadow: Option<Shadow>,
+	pub inner_shadow: Option<Shadow>,
+	pub outer_glow: Option<Glow>,
+	pub inner_glow: Option<Glow>,
+	pub bevel: Option<Bevel>,
+	pub satin: Option<Satin>,
+	pub color_overlay: Option<ColorOverlay>,
+	pub gradient_overlay: Option<GradientOverlay>,
+	pub pattern_overlay: Option<PatternOverlay>,
+	pub stroke: Option<Stroke>,
+	pub drop_shadow_multi: Vec<Shadow>,
+	pub inner_shadow_multi: Vec<Shadow>,
+	pub color_overlay_multi: Vec<ColorOverlay>,
+	pub gradient_overlay_multi: Vec<GradientOverlay>,
+	pub stroke_multi: Vec<Stroke>,
+	/// Descriptor of `lmfx` or `lfx2` block, `None` for legacy effects
+	pub descriptor: Option<Descriptor>,
+}
+
+impl LayerEffects {
+	fn new(enabled: bool) -> Self {
+		Self {
+			enabled,
+			scale: 100.0,
+			drop_shadow: None,
+			inner_shadow: None,
+			outer_glow: None,
+			inner_glow: None,
+			bevel: None,
+			satin: None,
+			color_overlay: None,
+			gradient_overlay: None,
+			pattern_overlay: None,
+			stroke: None,
+			drop_shadow_multi: vec![],
+			inner_shadow_multi: vec![],
+			color_overlay_multi: vec![],
+			gradient_overlay_multi: vec![],
+			stroke_multi: vec![],
+			descriptor: None,
+		}
+	}
+}
+
+fn number(descriptor: &Descriptor, key: &str) -> f64 {
+	descriptor.get(key).and_then(|x| x.as_f64()).unwrap_or(0.0)
+}
+
+fn flag(descriptor: &Descriptor, key: &str) -> bool {
+	descriptor
+		.get(key)
+		.and_then(|x| x.as_bool())
+		.unwrap_or(false)
+}
+
+fn text(descriptor: &Descriptor, key: &str) -> String {
+	descriptor
+		.get(key)
+		.and_then(|x| x.as_str())
+		.unwrap_or("")
+		.to_string()
+}
+
+fn enum_value<'a>(descriptor: &'a Descriptor, key: &str) -> Option<&'a Id> {
+	descriptor.get(key).and_then(|x| x.as_enum())
+}
+
+/// Gets color of `RGBC` descriptor, other color spaces give `None`
+fn color(descriptor: &Descriptor, key: &str) -> Option<Color> {
+	let color = descriptor.get(key)?.as_descriptor()?;
+	Some(Color {
+		red: color.get("Rd  ")?.as_f64()?,
+		green: color.get("Grn ")?.as_f64()?,
+		blue: color.get("Bl  ")?.as_f64()?,
+	})
+}
+
+fn blend_mode(descriptor: &Descriptor, key: &str) -> BlendMode {
+	let id = match enum_value(descriptor, key) {
+		Some(id) => id,
+		None => return BlendMode::Normal,
+	};
+	match DESCRIPTOR_BLEND_MODES.iter().find(|x| id.is(x.0)) {
+		Some(x) => x.1,
+		None => {
+			let mut key = [b' '; 4];
+			for (x, c) in key.iter_mut().zip(id.as_bytes()) {
+				*x = *c;
+			}
+			BlendMode::Unknown(key)
+		}
+	}
+}
+
+fn gradient(descriptor: &Descriptor, key: &str) -> Option<Gradient> {
+	let gradient = descriptor.get(key)?.as_descriptor()?;
+	let stops = |key: &str| {
+		gradient
+			.get(key)
+			.and_then(|x| x.as_list())
+			.unwrap_or(&[])
+			.iter()
+			.filter_map(|x| x.as_descriptor())
+			.collect::<Vec<&Descriptor>>()
+	};
+	Some(Gradient {
+		name: text(gradient, "Nm  "),
+		colors: stops("Clrs")
+			.into_iter()
+			.map(|x| ColorStop {
+				location: number(x, "Lctn") / 4096.0,
+				color: color(x, "Clr "),
+			})
+			.collect(),
+		opacities: stops("Trns")
+			.into_iter()
+			.map(|x| OpacityStop {
+				location: number(x, "Lctn") / 4096.0,
+				opacity: number(x, "Opct"),
+			})
+			.collect(),
+	})
+}
+
+fn decode_shadow(d: &Descriptor) -> Shadow {
+	Shadow {
+		enabled: flag(d, "enab"),
+		blend_mode: blend_mode(d, "Md  "),
+		color: color(d, "Clr "),
+		opacity: number(d, "Opct"),
+		angle: number(d, "lagl"),
+		use_global_light: flag(d, "uglg"),
+		distance: number(d, "Dstn"),
+		spread: number(d, "Ckmt"),
+		size: number(d, "blur"),
+	}
+}
+
+fn decode_glow(d: &Descriptor) -> Glow {
+	Glow {
+		enabled: flag(d, "enab"),
+		blend_mode: blend_mode(d, "Md  "),
+		color: color(d, "Clr "),
+		opacity: number(d, "Opct"),
+		spread: number(d, "Ckmt"),
+		size: number(d, "blur"),
+		noise: number(d, "Nose"),
+	}
+}
+
+fn decode_bevel(d: &Descriptor) -> Bevel {
+	let style = match enum_value(d, "bvlS") {
+		Some(x) if x.is("OtrB") => BevelStyle::OuterBevel,
+		Some(x) if x.is("InrB") => BevelStyle::InnerBevel,
+		Some(x) if x.is("Embs") => BevelStyle::Emboss,
+		Some(x) if x.is("PlEb") => BevelStyle::PillowEmboss,
+		Some(x) if x.is("strokeEmboss") => BevelStyle::StrokeEmboss,
+		_ => BevelStyle::Unknown,
+	};
+	Bevel {
+		enabled: flag(d, "enab"),
+		style,
+		depth: number(d, "srgR"),
+		up: !enum_value(d, "bvlD").is_some_and(|x| x.is("Out ")),
+		size: number(d, "blur"),
+		soften: number(d, "Sftn"),
+		angle: number(d, "lagl"),
+		altitude: number(d, "Lald"),
+		use_global_light: flag(d, "uglg"),
+		highlight_blend_mode: blend_mode(d, "hglM"),
+		highlight_color: color(d, "hglC"),
+		highlight_opacity: number(d, "hglO"),
+		shadow_blend_mode: blend_mode(d, "sdwM"),
+		shadow_color: color(d, "sdwC"),
+		shadow_opacity: number(d, "sdwO"),
+	}
+}
+
+fn decode_satin(d: &Descriptor) -> Satin {
+	Satin {
+		enabled: flag(d, "enab"),
+		blend_mode: blend_mode(d, "Md  "),
+		color: color(d, "Clr "),
+		opacity: number(d, "Opct"),
+		angle: number(d, "lagl"),
+		distance: number(d, "Dstn"),
+		size: number(d, "blur"),
+		invert: flag(d, "Invr"),
+	}
+}
+
+fn decode_color_overlay(d: &Descriptor) -> ColorOverlay {
+	ColorOverlay {
+		enabled: flag(d, "enab"),
+		blend_mode: blend_mode(d, "Md  "),
+		color: color(d, "Clr "),
+		opacity: number(d, "Opct"),
+	}
+}
+
+fn decode_gradient_overlay(d: &Descriptor) -> GradientOverlay {
+	let style = match enum_value(d, "Type") {
+		Some(x) if x.is("Lnr ") => GradientStyle::Linear,
+		Some(x) if x.is("Rdl ") => GradientStyle::Radial,
+		Some(x) if x.is("Angl") => GradientStyle::Angle,
+		Some(x) if x.is("Rflc") => GradientStyle::Reflected,
+		Some(x) if x.is("Dmnd") => GradientStyle::Diamond,
+		_ => GradientStyle::Unknown,
+	};
+	GradientOverlay {
+		enabled: flag(d, "enab"),
+		blend_mode: blend_mode(d, "Md  "),
+		opacity: number(d, "Opct"),
+		gradient: gradient(d, "Grad"),
+		style,
+		angle: number(d, "Angl"),
+		scale: number(d, "Scl "),
+		reverse: flag(d, "Rvrs"),
+	}
+}
+
+fn decode_pattern_overlay(d: &Descriptor) -> PatternOverlay {
+	let pattern = d.get("Ptrn").and_then(|x| x.as_descriptor());
+	PatternOverlay {
+		enabled: flag(d, "enab"),
+		blend_mode: blend_mode(d, "Md  "),
+		opacity: number(d, "Opct"),
+		name: pattern.map(|x| text(x, "Nm  ")).unwrap_or_default(),
+		id: pattern.map(|x| text(x, "Idnt")).unwrap_or_default(),
+		scale: number(d, "Scl "),
+	}
+}
+
+fn decode_stroke(d: &Descriptor) -> Stroke {
+	let position = match enum_value(d, "Styl") {
+		Some(x) if x.is("OutF") => StrokePosition::Outside,
+		Some(x) if x.is("InsF") => StrokePosition::Inside,
+		Some(x) if x.is("CtrF") => StrokePosition::Center,
+		_ => StrokePosition::Unknown,
+	};
+	let fill = match enum_value(d, "PntT") {
+		Some(x) if x.is("SClr") => StrokeFill::Color,
+		Some(x) if x.is("GrFl") => StrokeFill::Gradient,
+		Some(x) if x.is("Ptrn") => StrokeFill::Pattern,
+		_ => StrokeFill::Unknown,
+	};
+	Stroke {
+		enabled: flag(d, "enab"),
+		blend_mode: blend_mode(d, "Md  "),
+		opacity: number(d, "Opct"),
+		size: number(d, "Sz  "),
+		position,
+		fill,
+		color: color(d, "Clr "),
+		gradient: gradient(d, "Grad"),
+	}
+}
+
+/// Decodes instances of the effect of `multi` list, or of the `single` effect if there is no list
+fn decode_multi<T>(
+	descriptor: &Descriptor,
+	single: &str,
+	multi: &str,
+	decode: fn(&Descriptor) -> T,
+) -> Vec<T> {
+	match descriptor.get(multi).and_then(|x| x.as_list()) {
+		Some(list) => list
+			.iter()
+			.filter_map(|x| x.as_descriptor())
+			.map(decode)
+			.collect(),
+		None => descriptor
+			.get(single)
+			.and_then(|x| x.as_descriptor())
+			.map(decode)
+			.into_iter()
+			.collect(),
+	}
+}
+
+/// Decodes effects of descriptor, e.g. of `lmfx` or `lfx2` block
+pub fn decode_effects_descriptor(descriptor: Descriptor) -> LayerEffects {
+	let effect = |key: &str| descriptor.get(key).and_then(|x| x.as_descriptor());
+	let drop_shadow_multi = decode_multi(&descriptor, "DrSh", "dropShadowMulti", decode_shadow);
+	let inner_shadow_multi = decode_multi(&descriptor, "IrSh", "innerShadowMulti", decode_shadow);
+	let color_overlay_multi =
+		decode_multi(&descriptor, "SoFi", "solidFillMulti", decode_color_overlay);
+	let gradient_overlay_multi = decode_multi(
+		&descriptor,
+		"GrFl",
+		"gradientFillMulti",
+		decode_gradient_overlay,
+	);
+	let stroke_multi = decode_multi(&descriptor, "FrFX", "frameFXMulti", decode_stroke);
+	LayerEffects {
+		enabled: descriptor
+			.get("masterFXSwitch")
+			.and_then(|x| x.as_bool())
+			.unwrap_or(true),
+		scale: descriptor
+			.get("Scl ")
+			.and_then(|x| x.as_f64())
+			.unwrap_or(100.0),
+		drop_shadow: drop_shadow_multi.first().cloned(),
+		inner_shadow: inner_shadow_multi.first().cloned(),
+		outer_glow: effect("OrGl").map(decode_glow),
+		inner_glow: effect("IrGl").map(decode_glow),
+		bevel: effect("ebbl").map(decode_bevel),
+		satin: effect("ChFX").map(decode_satin),
+		color_overlay: color_overlay_multi.first().cloned(),
+		gradient_overlay: gradient_overlay_multi.first().cloned(),
+		pattern_overlay: effect("patternFill").map(decode_pattern_overlay),
+		stroke: stroke_multi.first().cloned(),
+		drop_shadow_multi,
+		inner_shadow_multi,
+		color_overlay_multi,
+		gradient_overlay_multi,
+		stroke_multi,
+		descriptor: Some(descriptor.clone()),
+	}
+}
+
+/// Decodes data of `lmfx` or `lfx2` tagged block
+pub fn decode_effects(data: &[u8]) -> Result<LayerEffects, PsdError> {
+	let mut r = ByteReader::new(data);
+	let version = r.read_u32()?;
+	if version != 0 {
+		return Err(PsdError::InvalidValue {
+			label: String::new(),
+			offset: 0,
+			value: u64::from(version),
+		});
+	}
+	let descriptor = read_versioned(&mut r)?;
+	Ok(decode_effects_descriptor(descriptor))
+}
+
+/// Reads legacy color: color space and four 16 bit components, only RGB is decoded
+fn read_legacy_color(r: &mut ByteReader) -> Result<Option<Color>, PsdError> {
+	let space = r.read_u16()?;
+	let mut components = [0.0; 4];
+	for x in components.iter_mut() {
+		*x = f64::from(r.read_u16()?) / 257.0;
+	}
+	Ok(match space {
+		0 => Some(Color {
+			red: components[0],
+			green: components[1],
+			blue: components[2],
+		}),
+		_ => None,
+	})
+}
+
+/// Reads `8BIM` signature and blend mode key
+fn read_legacy_blend_mode(r: &mut ByteReader) -> Result<BlendMode, PsdError> {
+	r.read_bytes(4)?;
+	let mut key = [0; 4];
+	key.copy_from_slice(r.read_bytes(4)?);
+	Ok(BlendMode::from_key(key))
+}
+
+fn read_legacy_shadow(r: &mut ByteReader) -> Result<Shadow, PsdError> {
+	let size = r.read_fixed()?;
+	// intensity
+	r.read_fixed()?;
+	let angle = r.read_fixed()?;
+	let distance = r.read_fixed()?;
+	let color = read_legacy_color(r)?;
+	let blend_mode = read_legacy_blend_mode(r)?;
+	Ok(Shadow {
+		enabled: r.read_u8()? != 0,
+		use_global_light: r.read_u8()? != 0,
+		opacity: f64::from(r.read_u8()?),
+		blend_mode,
+		color,
+		angle,
+		distance,
+		spread: 0.0,
+		size,
+	})
+}
+
+fn read_legacy_glow(r: &mut ByteReader) -> Result<Glow, PsdError> {
+	let size = r.read_fixed()?;
+	// intensity
+	r.read_fixed()?;
+	let color = read_legacy_color(r)?;
+	let blend_mode = read_legacy_blend_mode(r)?;
+	Ok(Glow {
+		enabled: r.read_u8()? != 0,
+		opacity: f64::from(r.read_u8()?),
+		blend_mode,
+		color,
+		spread: 0.0,
+		size,
+		noise: 0.0,
+	})
+}
+
+fn read_legacy_bevel(r: &mut ByteReader) -> Result<Bevel, PsdError> {
+	let angle = r.read_fixed()?;
+	let depth = r.read_fixed()?;
+	let size = r.read_fixed()?;
+	let highlight_blend_mode = read_legacy_blend_mode(r)?;
+	let shadow_blend_mode = read_legacy_blend_mode(r)?;
+	let highlight_color = read_legacy_color(r)?;
+	let shadow_color = read_legacy_color(r)?;
+	let style = match r.read_u8()? {
+		1 => BevelStyle::OuterBevel,
+		2 => BevelStyle::InnerBevel,
+		3 => BevelStyle::Emboss,
+		4 => BevelStyle::PillowEmboss,
+		5 => BevelStyle::StrokeEmboss,
+		_ => BevelStyle::Unknown,
+	};
+	Ok(Bevel {
+		highlight_opacity: f64::from(r.read_u8()?),
+		shadow_opacity: f64::from(r.read_u8()?),
+		enabled: r.read_u8()? != 0,
+		use_global_light: r.read_u8()? != 0,
+		up: r.read_u8()? == 0,
+		style,
+		depth,
+		size,
+		soften: 0.0,
+		angle,
+		altitude: 0.0,
+		highlight_blend_mode,
+		highlight_color,
+		shadow_blend_mode,
+		shadow_color,
+	})
+}
+
+fn read_legacy_color_overlay(r: &mut ByteReader) -> Result<ColorOverlay, PsdError> {
+	let blend_mode = read_legacy_blend_mode(r)?;
+	let color = read_legacy_color(r)?;
+	Ok(ColorOverlay {
+		opacity: f64::from(r.read_u8()?),
+		enabled: r.read_u8()? != 0,
+		blend_mode,
+		color,
+	})
+}
+
+/// Decodes data of legacy `lrFX` tagged block. Effects are records of signature, key, size,
+/// version and values, sizes, angles and distances are stored as 16.16 fixed point numbers
+pub fn decode_legacy_effects(data: &[u8]) -> Result<LayerEffects, PsdError> {
+	let mut r = ByteReader::new(data);
+	r.read_u16()?; // version
+	let count = r.read_u16()?;
+	let mut effects = LayerEffects::new(true);
+	for _ in 0..count {
+		r.read_bytes(4)?; // signature
+		let key = r.read_bytes(4)?;
+		let size = r.read_u32()? as usize;
+		let start = r.position() as u64;
+		let mut e = ByteReader::new(r.read_bytes(size)?);
+		let result = (|| {
+			e.read_u32()?; // version
+			match key {
+				b"cmnS" => effects.enabled = e.read_u8()? != 0,
+				b"dsdw" => effects.drop_shadow = Some(read_legacy_shadow(&mut e)?),
+				b"isdw" => effects.inner_shadow = Some(read_legacy_shadow(&mut e)?),
+				b"oglw" => effects.outer_glow = Some(read_legacy_glow(&mut e)?),
+				b"iglw" => effects.inner_glow = Some(read_legacy_glow(&mut e)?),
+				b"bevl" => effects.bevel = Some(read_legacy_bevel(&mut e)?),
+				b"sofi" => effects.color_overlay = Some(read_legacy_color_overlay(&mut e)?),
+				_ => (),
+			}
+			Ok(())
+		})();
+		result.map_err(|err: PsdError| err.within("", start))?;
+	}
+	effects.drop_shadow_multi = effects.drop_shadow.iter().cloned().collect();
+	effects.inner_shadow_multi = effects.inner_shadow.iter().cloned().collect();
+	effects.color_overlay_multi = effects.color_overlay.iter().cloned().collect();
+	Ok(effects)
+}
+
+#[cfg(test)]
+mod effects_tests {
+	use super::*;
+	use descriptor::Value;
+	use psd_file::PSDFile;
+	use std::io::Cursor;
+
+	fn rgb(red: f64, green: f64, blue: f64) -> Value {
+		let mut color = Descriptor::new("RGBC");
+		color.set("Rd  ", Value::Double(red));
+		color.set("Grn ", Value::Double(green));
+		color.set("Bl  ", Value::Double(blue));
+		Value::Descriptor(color)
+	}
+
+	fn unit(unit: &[u8; 4], value: f64) -> Value {
+		Value::UnitFloat { unit: *unit, value }
+	}
+
+	fn enumerated(type_id: &str, value: &str) -> Value {
+		Value::Enumerated {
+			type_id: Id::new(type_id),
+			value: Id::new(value),
+		}
+	}
+
+	/// Builds `lfx2` block with drop shadow and stroke
+	fn effects_block() -> Vec<u8> {
+		let mut shadow = Descriptor::new("DrSh");
+		shadow.set("enab", Value::Boolean(true));
+		shadow.set("Md  ", enumerated("BlnM", "Mltp"));
+		shadow.set("Clr ", rgb(0.0, 0.0, 0.0));
+		shadow.set("Opct", unit(b"#Prc", 75.0));
+		shadow.set("uglg", Value::Boolean(true));
+		shadow.set("lagl", unit(b"#Ang", 120.0));
+		shadow.set("Dstn", unit(b"#Pxl", 5.0));
+		shadow.set("Ckmt", unit(b"#Pxl", 0.0));
+		shadow.set("blur", unit(b"#Pxl", 8.0));
+
+		let mut stroke = Descriptor::new("FrFX");
+		stroke.set("enab", Value::Boolean(false));
+		stroke.set("Styl", enumerated("FStl", "OutF"));
+		stroke.set("PntT", enumerated("FrFl", "SClr"));
+		stroke.set("Md  ", enumerated("BlnM", "linearBurn"));
+		stroke.set("Opct", unit(b"#Prc", 100.0));
+		stroke.set("Sz  ", unit(b"#Pxl", 3.0));
+		stroke.set("Clr ", rgb(255.0, 0.0, 10.0));
+
+		let mut effects = Descriptor::new("null");
+		effects.set("Scl ", unit(b"#Prc", 100.0));
+		effects.set("masterFXSwitch", Value::Boolean(true));
+		effects.set("DrSh", Value::Descriptor(shadow));
+		effects.set("FrFX", Value::Descriptor(stroke));
+
+		let mut data = vec![0, 0, 0, 0];
+		data.extend(effects.encode_versioned());
+		data
+	}
+
+	#[test]
+	fn decode_effects_test() {
+		let effects = decode_effects(&effects_block()).unwrap();
+		assert!(effects.enabled);
+		assert_eq!(effects.scale, 100.0);
+		assert_eq!(
+			effects.drop_shadow,
+			Some(Shadow {
+				enabled: true,
+				blend_mode: BlendMode::Multiply,
+				color: Some(Color {
+					red: 0.0,
+					green: 0.0,
+					blue: 0.0
+				}),
+				opacity: 75.0,
+				angle: 120.0,
+				use_global_light: true,
+				distance: 5.0,
+				spread: 0.0,
+				size: 8.0,
+			})
+		);
+		let stroke = effects.stroke.unwrap();
+		assert!(!stroke.enabled);
+		assert_eq!(stroke.position, StrokePosition::Outside);
+		assert_eq!(stroke.fill, StrokeFill::Color);
+		assert_eq!(stroke.blend_mode, BlendMode::LinearBurn);
+		assert_eq!(stroke.size, 3.0);
+		assert_eq!(
+			stroke.color,
+			Some(Color {
+				red: 255.0,
+				green: 0.0,
+				blue: 10.0
+			})
+		);
+		assert_eq!(stroke.gradient, None);
+		assert!(effects.inner_shadow.is_none() && effects.bevel.is_none());
+
+		let mut data = effects_block();
+		data[3] = 1;
+		assert!(decode_effects(&data).is_err());
+	}
+
+	#[test]
+	fn decode_multi_effects_test() {
+		let shadow = |distance: f64| {
+			let mut shadow = Descriptor::new("DrSh");
+			shadow.set("enab", Value::Boolean(true));
+			shadow.set("Dstn", unit(b"#Pxl", distance));
+			Value::Descriptor(shadow)
+		};
+		let mut descriptor = Descriptor::new("null");
+		descriptor.set(
+			"dropShadowMulti",
+			Value::List(vec![shadow(5.0), shadow(10.0)]),
+		);
+		descriptor.set("frameFXMulti", Value::List(vec![]));
+		let effects = decode_effects_descriptor(descriptor);
+		assert_eq!(
+			effects
+				.drop_shadow_multi
+				.iter()
+				.map(|x| x.distance)
+				.collect::<Vec<f64>>(),
+			vec![5.0, 10.0]
+		);
+		assert_eq!(effects.drop_shadow.unwrap().distance, 5.0);
+		assert!(effects.stroke.is_none() && effects.stroke_multi.is_empty());
+		assert!(effects.inner_shadow_multi.is_empty());
+
+		let effects = decode_effects(&effects_block()).unwrap();
+		assert_eq!(effects.drop_shadow_multi.len(), 1);
+		assert_eq!(effects.stroke_multi, vec![effects.stroke.unwrap()]);
+	}
+
+	#[test]
+	fn decode_legacy_effects_test() {
+		let mut data = vec![0, 0, 0, 3];
+		data.extend_from_slice(b"8BIMcmnS\0\0\0\x07\0\0\0\0\x01\0\0");
+		data.extend_from_slice(b"8BIMdsdw\0\0\0\x29\0\0\0\0");
+		for x in &[5u16, 0, 75, 0, 120, 0, 4, 0] {
+			data.extend_from_slice(&x.to_be_bytes());
+		}
+		data.extend_from_slice(&[0, 0, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0]);
+		data.extend_from_slice(b"8BIMmul \x01\x00\x32");
+		data.extend_from_slice(b"8BIMsofi\0\0\0\x22\0\0\0\x02");
+		data.extend_from_slice(b"8BIMscrn");
+		data.extend_from_slice(&[0, 0, 0, 0, 0x80, 0x80, 0, 0, 0, 0, 100, 1]);
+		data.extend_from_slice(&[0; 10]);
+
+		let effects = decode_legacy_effects(&data).unwrap();
+		assert!(effects.enabled);
+		assert_eq!(effects.descriptor, None);
+		assert_eq!(
+			effects.drop_shadow,
+			Some(Shadow {
+				enabled: true,
+				blend_mode: BlendMode::Multiply,
+				color: Some(Color {
+					red: 255.0,
+					green: 0.0,
+					blue: 0.0
+				}),
+				opacity: 50.0,
+				angle: 120.0,
+				use_global_light: false,
+				distance: 4.0,
+				spread: 0.0,
+				size: 5.0,
+			})
+		);
+		let overlay = effects.color_overlay.unwrap();
+		assert!(overlay.enabled);
+		assert_eq!(overlay.blend_mode, BlendMode::Screen);
+		assert_eq!(overlay.color.unwrap().green, 128.0);
+		assert_eq!(overlay.opacity, 100.0);
+		assert_eq!(effects.color_overlay_multi, vec![overlay]);
+		assert!(effects.inner_shadow_multi.is_empty());
+
+		match decode_legacy_effects(&data[..40]) {
+			Err(PsdError::Truncated { offset, .. }) => assert_eq!(offset, 35),
+			x => panic!("expected Truncated, got {:?}", x.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn get_layer_effects_test() {
+		let mut file = PSDFile::open("./test_data/a_a.psd").unwrap();
+		assert_eq!(file.get_layer_effects(0).unwrap(), None);
+
+		let mut data = vec![];
+		file.write_tagged_blocks(&[(1, EFFECTS_KEY, effects_block())], &mut data)
+			.unwrap();
+		let mut file = PSDFile::new(Cursor::new(data));
+		assert_eq!(file.get_layer_effects(0).unwrap(), None);
+		let effects = file.get_layer_effects(1).unwrap().unwrap();
+		assert_eq!(effects.drop_shadow.unwrap().distance, 5.0);
+
+		let mut descriptor = Descriptor::new("null");
+		descriptor.set("dropShadowMulti", Value::List(vec![]));
+		let mut multi = vec![0, 0, 0, 0];
+		multi.extend(descriptor.encode_versioned());
+		let mut data = vec![];
+		file.write_tagged_blocks(&[(1, MULTI_EFFECTS_KEY, multi)], &mut data)
+			.unwrap();
+		let mut file = PSDFile::new(Cursor::new(data));
+		let effects = file.get_layer_effects(1).unwrap().unwrap();
+		assert_eq!(effects.drop_shadow, None);
+	}
+}
@@ -0,0 +1,397 @@
+//! Contains `EngineValue` enum and decoder of EngineData, PostScript-like text format
+//! which stores content and styles of text layers in `TySh` tagged blocks
+
+use error::PsdError;
+
+/// Max nesting of dictionaries and arrays
+const MAX_DEPTH: usize = 64;
+
+/// Value of EngineData
+#[derive(Clone, Debug, PartialEq)]
+pub enum EngineValue {
+	/// `<< /Key value ... >>`, keys are in file order
+	Dict(Vec<(String, EngineValue)>),
+	/// `[ value ... ]`
+	Array(Vec<EngineValue>),
+	/// Number without decimal point, e.g. `12`
+	Integer(i64),
+	/// Number with decimal point, e.g. `.5` or `12.0`
+	Number(f64),
+	/// `true` or `false`
+	Bool(bool),
+	/// `( ... )` string, UTF-16BE if it starts with `FE FF` byte order mark
+	Text(String),
+	/// `/Name` used as a value
+	Name(String),
+}
+
+impl EngineValue {
+	/// Gets value of the dictionary entry
+	pub fn get(&self, key: &str) -> Option<&EngineValue> {
+		match self {
+			EngineValue::Dict(entries) => entries.iter().find(|x| x.0 == key).map(|x| &x.1),
+			_ => None,
+		}
+	}
+
+	/// Gets value nested in dictionaries, e.g. `["EngineDict", "Editor", "Text"]`
+	pub fn get_path(&self, path: &[&str]) -> Option<&EngineValue> {
+		path.iter().try_fold(self, |value, key| value.get(key))
+	}
+
+	pub fn as_i64(&self) -> Option<i64> {
+		match self {
+			EngineValue::Integer(x) => Some(*x),
+			_ => None,
+		}
+	}
+
+	/// Gets number of `Integer` and `Number` values
+	pub fn as_f64(&self) -> Option<f64> {
+		match self {
+			EngineValue::Integer(x) => Some(*x as f64),
+			EngineValue::Number(x) => Some(*x),
+			_ => None,
+		}
+	}
+
+	pub fn as_bool(&self) -> Option<bool> {
+		match self {
+			EngineValue::Bool(x) => Some(*x),
+			_ => None,
+		}
+	}
+
+	/// Gets string of `Text` and `Name` values
+	pub fn as_str(&self) -> Option<&str> {
+		match self {
+			EngineValue::Text(x) | EngineValue::Name(x) => Some(x),
+			_ => None,
+		}
+	}
+
+	pub fn as_array(&self) -> Option<&[EngineValue]> {
+		match self {
+			EngineValue::Array(x) => Some(x),
+			_ => None,
+		}
+	}
+
+	pub fn get_mut(&mut self, key: &str) -> Option<&mut EngineValue> {
+		match self {
+			EngineValue::Dict(entries) => entries.iter_mut().find(|x| x.0 == key).map(|x| &mut x.1),
+			_ => None,
+		}
+	}
+
+	pub fn get_path_mut(&mut self, path: &[&str]) -> Option<&mut EngineValue> {
+		path.iter().try_fold(self, |value, key| value.get_mut(key))
+	}
+}
+
+fn is_space(c: u8) -> bool {
+	c == b' ' || c == b'\t' || c == b'\r' || c == b'\n' || c == 0
+}
+
+fn is_delimiter(c: u8) -> bool {
+	is_space(c) || b"/[]()<>".contains(&c)
+}
+
+struct Parser<'a> {
+	data: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> Parser<'a> {
+	fn error<S: Into<String>>(&self, message: S) -> PsdError {
+		PsdError::Malformed {
+			label: String::new(),
+			offset: self.pos as u64,
+			message: message.into(),
+		}
+	}
+
+	fn skip_space(&mut self) {
+		while self.pos < self.data.len() && is_space(self.data[self.pos]) {
+			self.pos += 1;
+		}
+	}
+
+	fn peek(&self) -> Option<u8> {
+		self.data.get(self.pos).cloned()
+	}
+
+	fn starts_with(&self, token: &[u8]) -> bool {
+		self.data[self.pos..].starts_with(token)
+	}
+
+	/// Reads characters up to the next delimiter
+	fn read_word(&mut self) -> &'a [u8] {
+		let start = self.pos;
+		while self.pos < self.data.len() && !is_delimiter(self.data[self.pos]) {
+			self.pos += 1;
+		}
+		&self.data[start..self.pos]
+	}
+
+	/// Reads `/Name` and returns it without slash
+	fn read_name(&mut self) -> Result<String, PsdError> {
+		if self.peek() != Some(b'/') {
+			return Err(self.error("expected /Name"));
+		}
+		self.pos += 1;
+		Ok(String::from_utf8_lossy(self.read_word()).to_string())
+	}
+
+	/// Reads `( ... )` string, backslash escapes the following byte
+	fn read_string(&mut self) -> Result<String, PsdError> {
+		let start = self.pos;
+		self.pos += 1;
+		let mut bytes = vec![];
+		loop {
+			match self.peek() {
+				Some(b')') => break,
+				Some(b'\\') => {
+					self.pos += 1;
+					match self.peek() {
+						Some(c) => bytes.push(c),
+						None => break,
+					}
+				}
+				Some(c) => bytes.push(c),
+				None => break,
+			}
+			self.pos += 1;
+		}
+		if self.peek() != Some(b')') {
+			self.pos = start;
+			return Err(self.error("string isn't closed"));
+		}
+		self.pos += 1;
+		if bytes.starts_with(&[0xFE, 0xFF]) {
+			let units: Vec<u16> = bytes[2..]
+				.chunks(2)
+				.filter(|x| x.len() == 2)
+				.map(|x| u16::from(x[0]) << 8 | u16::from(x[1]))
+				.collect();
+			Ok(String::from_utf16_lossy(&units))
+		} else {
+			Ok(String::from_utf8_lossy(&bytes).to_string())
+		}
+	}
+
+	fn read_value(&mut self, depth: usize) -> Result<EngineValue, PsdError> {
+		if depth > MAX_DEPTH {
+			return Err(PsdError::LimitExceeded {
+				label: String::new(),
+				offset: self.pos as u64,
+				value: depth as u64,
+				limit: MAX_DEPTH as u64,
+			});
+		}
+		self.skip_space();
+		if self.starts_with(b"<<") {
+			self.pos += 2;
+			let mut entries = vec![];
+			loop {
+				self.skip_space();
+				if self.starts_with(b">>") {
+					self.pos += 2;
+					return Ok(EngineValue::Dict(entries));
+				}
+				if self.peek().is_none() {
+					return Err(self.error("dictionary isn't closed"));
+				}
+				let key = self.read_name()?;
+				let value = self.read_value(depth + 1)?;
+				entries.push((key, value));
+			}
+		}
+		match self.peek() {
+			Some(b'[') => {
+				self.pos += 1;
+				let mut values = vec![];
+				loop {
+					self.skip_space();
+					match self.peek() {
+						Some(b']') => {
+							self.pos += 1;
+							return Ok(EngineValue::Array(values));
+						}
+						Some(_) => values.push(self.read_value(depth + 1)?),
+						None => return Err(self.error("array isn't closed")),
+					}
+				}
+			}
+			Some(b'(') => self.read_string().map(EngineValue::Text),
+			Some(b'/') => self.read_name().map(EngineValue::Name),
+			Some(_) => {
+				let start = self.pos;
+				let word = String::from_utf8_lossy(self.read_word()).to_string();
+				let value = match word.as_ref() {
+					"true" => Some(EngineValue::Bool(true)),
+					"false" => Some(EngineValue::Bool(false)),
+					x if x.contains('.') => x.parse().ok().map(EngineValue::Number),
+					x => x.parse().ok().map(EngineValue::Integer),
+				};
+				value.ok_or_else(|| {
+					self.pos = start;
+					self.error(format!("unexpected token \"{}\"", word))
+				})
+			}
+			None => Err(self.error("unexpected end of data")),
+		}
+	}
+}
+
+/// Decodes EngineData, which is a single dictionary
+pub fn decode_engine_data(data: &[u8]) -> Result<EngineValue, PsdError> {
+	let mut parser = Parser { data, pos: 0 };
+	parser.skip_space();
+	if !parser.starts_with(b"<<") {
+		return Err(parser.error("EngineData must start with dictionary"));
+	}
+	parser.read_value(0)
+}
+
+fn write_indent(out: &mut Vec<u8>, depth: usize) {
+	for _ in 0..depth {
+		out.push(b'\t');
+	}
+}
+
+fn write_value(out: &mut Vec<u8>, value: &EngineValue, depth: usize) {
+	match value {
+		EngineValue::Dict(entries) => {
+			out.extend_from_slice(b"<<\n");
+			for (key, value) in entries {
+				write_indent(out, depth + 1);
+				out.push(b'/');
+				out.extend_from_slice(key.as_bytes());
+				out.push(b' ');
+				write_value(out, value, depth + 1);
+				out.push(b'\n');
+			}
+			write_indent(out, depth);
+			out.extend_from_slice(b">>");
+		}
+		EngineValue::Array(values) => {
+			out.push(b'[');
+			for value in values {
+				out.push(b' ');
+				write_value(out, value, depth);
+			}
+			out.extend_from_slice(b" ]");
+		}
+		EngineValue::Integer(x) => out.extend_from_slice(x.to_string().as_bytes()),
+		EngineValue::Number(x) => {
+			let mut number = x.to_string();
+			// number without decimal point would be read back as integer
+			if !number.contains('.') {
+				number.push_str(".0");
+			}
+			out.extend_from_slice(number.as_bytes());
+		}
+		EngineValue::Bool(x) => out.extend_from_slice(x.to_string().as_bytes()),
+		EngineValue::Text(x) => {
+			out.extend_from_slice(b"(\xFE\xFF");
+			for unit in x.encode_utf16() {
+				for byte in &unit.to_be_bytes() {
+					if b"()\\".contains(byte) {
+						out.push(b'\\');
+					}
+					out.push(*byte);
+				}
+			}
+			out.push(b')');
+		}
+		EngineValue::Name(x) => {
+			out.push(b'/');
+			out.extend_from_slice(x.as_bytes());
+		}
+	}
+}
+
+/// Encodes EngineData the way Photoshop lays it out: dictionary entry per line,
+/// strings as UTF-16BE with byte order mark
+pub fn encode_engine_data(value: &EngineValue) -> Vec<u8> {
+	let mut out = b"\n\n".to_vec();
+	write_value(&mut out, value, 0);
+	out
+}
+
+#[cfg(test)]
+mod engine_data_tests {
+	use super::*;
+
+	#[test]
+	fn decode_test() {
+		let mut data = b"\n\n<<\n\t/EngineDict\n\t<<\n\t\t/Editor\n\t\t<<\n\t\t\t/Text (\xFE\xFF\x00H\x00i\x00\\)\x00\r)\n\t\t>>\n\t\t/Runs [ 3 -1.5 .25 true ]\n\t>>\n\t/Type /Name\n>>".to_vec();
+		let value = decode_engine_data(&data).unwrap();
+		assert_eq!(
+			value
+				.get_path(&["EngineDict", "Editor", "Text"])
+				.and_then(|x| x.as_str()),
+			Some("Hi)\r")
+		);
+		assert_eq!(
+			value.get_path(&["EngineDict", "Runs"]).unwrap(),
+			&EngineValue::Array(vec![
+				EngineValue::Integer(3),
+				EngineValue::Number(-1.5),
+				EngineValue::Number(0.25),
+				EngineValue::Bool(true),
+			])
+		);
+		assert_eq!(
+			value.get("Type"),
+			Some(&EngineValue::Name("Name".to_string()))
+		);
+
+		data.truncate(data.len() - 2);
+		match decode_engine_data(&data) {
+			Err(PsdError::Malformed { message, .. }) => {
+				assert_eq!(message, "dictionary isn't closed")
+			}
+			x => panic!("expected Malformed, got {:?}", x),
+		}
+		assert!(decode_engine_data(b"<< /Key what >>").is_err());
+		assert!(decode_engine_data(b"[ 1 ]").is_err());
+	}
+
+	#[test]
+	fn encode_test() {
+		let value = EngineValue::Dict(vec![
+			(
+				"Editor".to_string(),
+				EngineValue::Dict(vec![(
+					"Text".to_string(),
+					EngineValue::Text("a(\\)\u{5c28}\r".to_string()),
+				)]),
+			),
+			(
+				"Values".to_string(),
+				EngineValue::Array(vec![
+					EngineValue::Number(1.0),
+					EngineValue::Number(-0.25),
+					EngineValue::Integer(-7),
+					EngineValue::Bool(false),
+					EngineValue::Name("Name".to_string()),
+					EngineValue::Array(vec![]),
+				]),
+			),
+		]);
+		let data = encode_engine_data(&value);
+		assert!(data.starts_with(b"\n\n<<\n\t/Editor <<\n\t\t/Text (\xFE\xFF\x00a\x00\\("));
+		assert!(data.ends_with(b"\t/Values [ 1.0 -0.25 -7 false /Name [ ] ]\n>>"));
+		assert_eq!(decode_engine_data(&data).unwrap(), value);
+
+		let mut value = value;
+		*value.get_path_mut(&["Editor", "Text"]).unwrap() = EngineValue::Integer(1);
+		assert_eq!(
+			value.get_path(&["Editor", "Text"]),
+			Some(&EngineValue::Integer(1))
+		);
+		assert!(value.get_path_mut(&["Editor", "Text", "Nothing"]).is_none());
+	}
+}
@@ -0,0 +1,371 @@
+//! Contains `PsdError` returned by reading and decoding functions
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Error of reading or decoding psd file.
+/// Labels follow `Indexes` labels and offsets are absolute positions in file,
+/// except errors of decoders called on a detached slice, which have empty label
+/// and offset relative to the slice start
+#[derive(Debug)]
+pub enum PsdError {
+	/// File doesn't start with `8BPS` signature
+	NotPsd,
+	/// Header version is neither 1 (psd) nor 2 (psb)
+	UnsupportedVersion(u16),
+	/// Data ended before `expected` bytes of `label` could be read, only `actual` were left
+	Truncated {
+		label: String,
+		offset: u64,
+		expected: u64,
+		actual: u64,
+	},
+	/// Bytes of `label` don't match any of the expected values, e.g. block signature
+	Mismatch {
+		label: String,
+		offset: u64,
+		expected: Vec<Vec<u8>>,
+		actual: Vec<u8>,
+	},
+	/// Value of `label` is out of its range, e.g. unknown color mode or compression method
+	InvalidValue {
+		label: String,
+		offset: u64,
+		value: u64,
+	},
+	/// Block `label` starting at `offset` ends at `end`, past the end of its section at `bound`
+	Overflow {
+		label: String,
+		offset: u64,
+		end: u64,
+		bound: u64,
+	},
+	/// Value of `label` exceeds configured `Limits`
+	LimitExceeded {
+		label: String,
+		offset: u64,
+		value: u64,
+		limit: u64,
+	},
+	/// Data of `label` starting at `offset` can't be decoded
+	Malformed {
+		label: String,
+		offset: u64,
+		message: String,
+	},
+	/// Valid data which isn't supported by decoders, e.g. prediction of 1 bit channel
+	Unsupported(String),
+	/// Requested layer, channel or label doesn't exist
+	NotFound(String),
+	Io(io::Error),
+}
+
+impl PsdError {
+	pub fn malformed<S: Into<String>>(message: S) -> Self {
+		PsdError::Malformed {
+			label: String::new(),
+			offset: 0,
+			message: message.into(),
+		}
+	}
+
+	/// Attaches error of a detached slice to the block `block` starting at `start` in file:
+	/// fills empty label and turns offsets relative to the slice into absolute ones
+	pub fn within(self, block: &str, start: u64) -> Self {
+		match self {
+			PsdError::Truncated {
+				label,
+				offset,
+				expected,
+				actual,
+			} => PsdError::Truncated {
+				label: Self::label_or(label, block),
+				offset: offset + start,
+				expected,
+				actual,
+			},
+			PsdError::Mismatch {
+				label,
+				offset,
+				expected,
+				actual,
+			} => PsdError::Mismatch {
+				label: Self::label_or(label, block),
+				offset: offset + start,
+				expected,
+				actual,
+			},
+			PsdError::InvalidValue {
+				label,
+				offset,
+				value,
+			} => PsdError::InvalidValue {
+				label: Self::label_or(label, block),
+				offset: offset + start,
+				value,
+			},
+			PsdError::Overflow {
+				label,
+				offset,
+				end,
+				bound,
+			} => PsdError::Overflow {
+				label: Self::label_or(label, block),
+				offset: offset + start,
+				end: end + start,
+				bound: bound + start,
+			},
+			PsdError::LimitExceeded {
+				label,
+				offset,
+				value,
+				limit,
+			} => PsdError::LimitExceeded {
+				label: Self::label_or(label, block),
+				offset: offset + start,
+				value,
+				limit,
+			},
+			PsdError::Malformed {
+				label,
+				offset,
+				message,
+			} => PsdError::Malformed {
+				label: Self::label_or(label, block),
+				offset: offset + start,
+				message,
+			},
+			x => x,
+		}
+	}
+
+	/// Gets label and offset of the block the error occurred in, if any
+	pub fn location(&self) -> Option<(&str, u64)> {
+		match self {
+			PsdError::Truncated { label, offset, .. }
+			| PsdError::Mismatch { label, offset, .. }
+			| PsdError::InvalidValue { label, offset, .. }
+			| PsdError::Overflow { label, offset, .. }
+			| PsdError::LimitExceeded { label, offset, .. }
+			| PsdError::Malformed { label, offset, .. } => Some((label, *offset)),
+			_ => None,
+		}
+	}
+
+	fn label_or(label: String, block: &str) -> String {
+		if label.is_empty() {
+			block.to_string()
+		} else {
+			label
+		}
+	}
+}
+
+/// Formats bytes as text if they're printable ascii and as hex otherwise
+fn format_bytes(data: &[u8]) -> String {
+	if data.iter().all(|x| x.is_ascii_graphic() || *x == b' ') {
+		format!("\"{}\"", String::from_utf8_lossy(data))
+	} else {
+		let hex: Vec<String> = data.iter().map(|x| format!("{:02x}", x)).collect();
+		format!("0x{}", hex.join(""))
+	}
+}
+
+/// Formats block position as `"label" (offset n)` or `offset n` for detached data
+fn format_location(label: &str, offset: u64) -> String {
+	if label.is_empty() {
+		format!("offset {}", offset)
+	} else {
+		format!("\"{}\" (offset {})", label, offset)
+	}
+}
+
+impl fmt::Display for PsdError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			PsdError::NotPsd => write!(f, "Not a psd file: \"8BPS\" signature wasn't found"),
+			PsdError::UnsupportedVersion(version) => write!(
+				f,
+				"Unsupported version {}, expected 1 (psd) or 2 (psb)",
+				version
+			),
+			PsdError::Truncated {
+				label,
+				offset,
+				expected,
+				actual,
+			} => write!(
+				f,
+				"Unexpected end of data in {}: wanted {} bytes, {} left",
+				format_location(label, *offset),
+				expected,
+				actual
+			),
+			PsdError::Mismatch {
+				label,
+				offset,
+				expected,
+				actual,
+			} => {
+				let expected: Vec<String> = expected.iter().map(|x| format_bytes(x)).collect();
+				write!(
+					f,
+					"Check failed on {}: expected {}, found {}",
+					format_location(label, *offset),
+					expected.join(" or "),
+					format_bytes(actual)
+				)
+			}
+			PsdError::InvalidValue {
+				label,
+				offset,
+				value,
+			} => write!(
+				f,
+				"Invalid value {} in {}",
+				value,
+				format_location(label, *offset)
+			),
+			PsdError::Overflow {
+				label,
+				offset,
+				end,
+				bound,
+			} => write!(
+				f,
+				"Block {} ends at {}, past the end of its section at {}",
+				format_location(label, *offset),
+				end,
+				bound
+			),
+			PsdError::LimitExceeded {
+				label,
+				offset,
+				value,
+				limit,
+			} => write!(
+				f,
+				"Value {} of {} exceeds limit {}",
+				value,
+				format_location(label, *offset),
+				limit
+			),
+			PsdError::Malformed {
+				label,
+				offset,
+				message,
+			} => {
+				if label.is_empty() && *offset == 0 {
+					write!(f, "{}", message)
+				} else {
+					write!(f, "{}: {}", format_location(label, *offset), message)
+				}
+			}
+			PsdError::Unsupported(message) => write!(f, "Unsupported {}", message),
+			PsdError::NotFound(what) => write!(f, "{} wasn't found", what),
+			PsdError::Io(err) => write!(f, "I/O error: {}", err),
+		}
+	}
+}
+
+impl Error for PsdError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			PsdError::Io(err) => Some(err),
+			_ => None,
+		}
+	}
+}
+
+impl From<io::Error> for PsdError {
+	fn from(err: io::Error) -> Self {
+		PsdError::Io(err)
+	}
+}
+
+#[cfg(test)]
+mod error_tests {
+	use super::*;
+	use psd_file::PSDFile;
+	use std::fs::read;
+	use std::io::Cursor;
+
+	#[test]
+	fn open_test() {
+		match PSDFile::open("./test_data/missing.psd") {
+			Err(PsdError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::NotFound),
+			_ => panic!("missing file must be I/O error"),
+		}
+
+		let mut file = PSDFile::open("./Cargo.toml").unwrap();
+		match file.get_header() {
+			Err(PsdError::NotPsd) => (),
+			x => panic!("expected NotPsd, got {:?}", x),
+		}
+	}
+
+	#[test]
+	fn header_errors_test() {
+		let data = read("./test_data/a_a.psd").unwrap();
+
+		let mut version = data.clone();
+		version[5] = 3;
+		let mut file = PSDFile::new(Cursor::new(version));
+		match file.get_header() {
+			Err(PsdError::UnsupportedVersion(3)) => (),
+			x => panic!("expected UnsupportedVersion, got {:?}", x),
+		}
+
+		let mut file = PSDFile::new(Cursor::new(data[..20].to_vec()));
+		match file.get_header() {
+			Err(PsdError::Truncated {
+				label,
+				offset,
+				expected,
+				actual,
+			}) => {
+				assert_eq!(label, "header/width");
+				assert_eq!((offset, expected, actual), (18, 4, 2));
+			}
+			x => panic!("expected Truncated, got {:?}", x),
+		}
+
+		// first image resource signature
+		let mut signature = data.clone();
+		signature[34] = b'X';
+		let mut file = PSDFile::new(Cursor::new(signature));
+		match file.get_indexes() {
+			Err(PsdError::Mismatch {
+				label,
+				offset,
+				actual,
+				..
+			}) => {
+				assert_eq!(label, "image_resources/image_resource_0/signature");
+				assert_eq!(offset, 34);
+				assert_eq!(actual, b"XBIM".to_vec());
+			}
+			x => panic!("expected Mismatch, got {:?}", x),
+		}
+	}
+
+	#[test]
+	fn within_test() {
+		let err = PsdError::Truncated {
+			label: String::new(),
+			offset: 2,
+			expected: 4,
+			actual: 1,
+		}
+		.within("block", 100);
+		assert_eq!(
+			err.to_string(),
+			"Unexpected end of data in \"block\" (offset 102): wanted 4 bytes, 1 left"
+		);
+		assert_eq!(PsdError::malformed("bad data").to_string(), "bad data");
+		let err = PsdError::malformed("bad data").within("block", 10);
+		assert_eq!(err.to_string(), "\"block\" (offset 10): bad data");
+		assert!(PsdError::NotPsd.within("block", 10).source().is_none());
+	}
+}
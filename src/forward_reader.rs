@@ -0,0 +1,146 @@
+//! Contains `ForwardReader` struct which lets `PSDReader` read input without seeking
+
+use std::cmp::min;
+use std::io::{Error, ErrorKind, Read, Result as IOResult, Seek, SeekFrom};
+
+/// Bytes kept behind the current position, so reader can step back after peeking ahead
+const WINDOW: u64 = 64 * 1024;
+const CHUNK: u64 = 64 * 1024;
+
+/// Adapts plain `Read`, e.g. stdin or a pipe, to `Read + Seek` for `PSDReader` in forward only mode.
+/// Seeking forward skips the data, seeking back is possible only within last 64 KiB
+/// before the current position, seeking from the end isn't supported
+pub struct ForwardReader<R: Read> {
+	inner: R,
+	/// data read from `inner`, starting at `buffer_start`
+	buffer: Vec<u8>,
+	buffer_start: u64,
+	pos: u64,
+}
+
+impl<R: Read> ForwardReader<R> {
+	pub fn new(inner: R) -> Self {
+		Self {
+			inner,
+			buffer: vec![],
+			buffer_start: 0,
+			pos: 0,
+		}
+	}
+
+	fn buffer_end(&self) -> u64 {
+		self.buffer_start + self.buffer.len() as u64
+	}
+
+	/// Drops data which can't be reached by seeking back anymore
+	fn trim(&mut self) {
+		let keep_from = self.pos.saturating_sub(WINDOW);
+		if keep_from > self.buffer_start + WINDOW {
+			let drop = min(keep_from - self.buffer_start, self.buffer.len() as u64);
+			self.buffer.drain(..drop as usize);
+			self.buffer_start += drop;
+		}
+	}
+
+	/// Reads `inner` until data up to `end` is buffered or input ends
+	fn fill(&mut self, end: u64) -> IOResult<()> {
+		let mut chunk = vec![0; CHUNK as usize];
+		while self.buffer_end() < end {
+			let size = min(end - self.buffer_end(), CHUNK) as usize;
+			let read = match self.inner.read(&mut chunk[..size]) {
+				Ok(0) => break,
+				Ok(read) => read,
+				Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+				Err(err) => return Err(err),
+			};
+			self.buffer.extend_from_slice(&chunk[..read]);
+			self.trim();
+		}
+		Ok(())
+	}
+}
+
+impl<R: Read> Read for ForwardReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+		if self.pos < self.buffer_start {
+			return Err(Error::new(
+				ErrorKind::InvalidInput,
+				format!("position {} of stream was already dropped", self.pos),
+			));
+		}
+		let end = self.pos + buf.len() as u64;
+		self.fill(end)?;
+		let from = self.pos - self.buffer_start;
+		if from >= self.buffer.len() as u64 {
+			return Ok(0);
+		}
+		let from = from as usize;
+		let size = min(buf.len(), self.buffer.len() - from);
+		buf[..size].copy_from_slice(&self.buffer[from..from + size]);
+		self.pos += size as u64;
+		self.trim();
+		Ok(size)
+	}
+}
+
+impl<R: Read> Seek for ForwardReader<R> {
+	fn seek(&mut self, from: SeekFrom) -> IOResult<u64> {
+		let pos = match from {
+			SeekFrom::Start(pos) => Some(pos),
+			SeekFrom::Current(offset) => {
+				if offset < 0 {
+					self.pos.checked_sub(offset.unsigned_abs())
+				} else {
+					self.pos.checked_add(offset as u64)
+				}
+			}
+			SeekFrom::End(_) => {
+				return Err(Error::new(
+					ErrorKind::Unsupported,
+					"size of the stream isn't known",
+				))
+			}
+		};
+		match pos {
+			Some(pos) if pos >= self.buffer_start => {
+				self.pos = pos;
+				Ok(pos)
+			}
+			_ => Err(Error::new(
+				ErrorKind::InvalidInput,
+				"can't seek back in stream past the kept data",
+			)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod forward_reader_tests {
+	use super::*;
+
+	#[test]
+	fn seek_test() {
+		let data: Vec<u8> = (0..300_000u32).map(|x| x as u8).collect();
+		let mut reader = ForwardReader::new(&data[..]);
+		let mut buf = [0; 4];
+
+		reader.seek(SeekFrom::Start(10)).unwrap();
+		reader.read_exact(&mut buf).unwrap();
+		assert_eq!(buf, [10, 11, 12, 13]);
+		reader.seek(SeekFrom::Current(-8)).unwrap();
+		reader.read_exact(&mut buf).unwrap();
+		assert_eq!(buf, [6, 7, 8, 9]);
+
+		reader.seek(SeekFrom::Start(200_000)).unwrap();
+		reader.read_exact(&mut buf).unwrap();
+		assert_eq!(&buf[..], &data[200_000..200_004]);
+		reader.seek(SeekFrom::Start(200_000 - WINDOW)).unwrap();
+		reader.read_exact(&mut buf).unwrap();
+		assert!(reader.seek(SeekFrom::Start(10)).is_err());
+		assert!(reader.seek(SeekFrom::End(0)).is_err());
+
+		reader.seek(SeekFrom::Start(299_998)).unwrap();
+		assert_eq!(reader.read(&mut buf).unwrap(), 2);
+		assert_eq!(reader.read(&mut buf).unwrap(), 0);
+	}
+}
@@ -0,0 +1,374 @@
+//! Contains `Image` struct and conversion of planar channels into RGBA pixels
+
+use error::PsdError;
+use psd_header::{ColorMode, PSDHeader};
+
+/// Interleaved RGBA image, 8 bits per sample for 1 and 8 bit documents
+/// and 16 bits big endian samples for 16 and 32 bit documents
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+	pub width: u32,
+	pub height: u32,
+	/// bits per sample: 8 or 16
+	pub depth: u16,
+	pub data: Vec<u8>,
+}
+
+impl Image {
+	/// Gets RGBA samples of the pixel scaled to 0..=1
+	pub fn get_pixel(&self, x: u32, y: u32) -> [f32; 4] {
+		let bytes = usize::from(self.depth / 8);
+		let start = (y as usize * self.width as usize + x as usize) * 4 * bytes;
+		let mut out = [0.0; 4];
+		for (i, sample) in out.iter_mut().enumerate() {
+			let pos = start + i * bytes;
+			*sample = match self.depth {
+				8 => f32::from(self.data[pos]) / 255.0,
+				_ => {
+					f32::from(u16::from(self.data[pos]) << 8 | u16::from(self.data[pos + 1]))
+						/ 65535.0
+				}
+			};
+		}
+		out
+	}
+
+	/// Places image with its top left corner at `left` and `top` on transparent canvas,
+	/// parts outside of canvas are cropped
+	pub fn place(&self, width: u32, height: u32, left: i32, top: i32) -> Image {
+		let bytes = 4 * usize::from(self.depth / 8);
+		let mut data = vec![0; width as usize * height as usize * bytes];
+		let x_from = i64::from(left).max(0);
+		let x_to = (i64::from(left) + i64::from(self.width)).min(i64::from(width));
+		if x_from < x_to {
+			let size = (x_to - x_from) as usize * bytes;
+			let skip = (x_from - i64::from(left)) as usize * bytes;
+			for y in 0..self.height {
+				let canvas_y = i64::from(top) + i64::from(y);
+				if canvas_y < 0 || canvas_y >= i64::from(height) {
+					continue;
+				}
+				let source = y as usize * self.width as usize * bytes + skip;
+				let target = (canvas_y as usize * width as usize + x_from as usize) * bytes;
+				data[target..target + size].copy_from_slice(&self.data[source..source + size]);
+			}
+		}
+		Image {
+			width,
+			height,
+			depth: self.depth,
+			data,
+		}
+	}
+}
+
+/// Values needed to convert planar channels besides the header
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompositeOptions {
+	/// `color_mode_section` of indexed documents: 256 red, 256 green and 256 blue values
+	pub palette: Vec<u8>,
+	/// Index of the transparent palette color, resource 1047
+	pub transparent_index: Option<u16>,
+	/// First channel after color channels is transparency
+	pub has_alpha: bool,
+}
+
+/// Number of color channels of the color mode, unknown mode can't be decoded
+pub fn color_channels(mode: ColorMode) -> Result<usize, PsdError> {
+	Ok(match mode {
+		ColorMode::Bitmap
+		| ColorMode::Grayscale
+		| ColorMode::Indexed
+		| ColorMode::Duotone
+		| ColorMode::Multichannel => 1,
+		ColorMode::Rgb | ColorMode::Lab => 3,
+		ColorMode::Cmyk => 4,
+		ColorMode::Unknown(x) => return Err(PsdError::Unsupported(format!("color mode {}", x))),
+	})
+}
+
+/// Reads sample `index` of the planar channel scaled to 0..=1
+fn sample(channel: &[u8], index: usize, width: usize, depth: u16) -> Result<f32, PsdError> {
+	Ok(match depth {
+		1 => {
+			let row_size = width.div_ceil(8);
+			let (y, x) = (index / width, index % width);
+			// 1 is black
+			let bit = channel[y * row_size + x / 8] >> (7 - x % 8) & 1;
+			f32::from(1 - bit)
+		}
+		8 => f32::from(channel[index]) / 255.0,
+		16 => {
+			f32::from(u16::from(channel[index * 2]) << 8 | u16::from(channel[index * 2 + 1]))
+				/ 65535.0
+		}
+		32 => {
+			let bits = channel[index * 4..index * 4 + 4]
+				.iter()
+				.fold(0u32, |c, x| c << 8 | u32::from(*x));
+			linear_to_srgb(f32::from_bits(bits))
+		}
+		_ => return Err(PsdError::Unsupported(format!("{} bit depth", depth))),
+	})
+}
+
+/// 32 bit documents are stored in linear gamma
+fn linear_to_srgb(value: f32) -> f32 {
+	let value = if value.is_nan() {
+		0.0
+	} else {
+		value.clamp(0.0, 1.0)
+	};
+	if value <= 0.003_130_8 {
+		value * 12.92
+	} else {
+		1.055 * value.powf(1.0 / 2.4) - 0.055
+	}
+}
+
+/// Converts CIE Lab with samples scaled to 0..=1 into sRGB
+fn lab_to_rgb(l: f32, a: f32, b: f32) -> [f32; 3] {
+	let l = l * 100.0;
+	let a = a * 255.0 - 128.0;
+	let b = b * 255.0 - 128.0;
+	let fy = (l + 16.0) / 116.0;
+	let fx = fy + a / 500.0;
+	let fz = fy - b / 200.0;
+	let f = |t: f32| {
+		if t > 6.0 / 29.0 {
+			t * t * t
+		} else {
+			3.0 * (6.0f32 / 29.0).powi(2) * (t - 4.0 / 29.0)
+		}
+	};
+	// D50 white point
+	let x = 0.9642 * f(fx);
+	let y = f(fy);
+	let z = 0.8249 * f(fz);
+	// Bradford adapted D50 XYZ to linear sRGB
+	let r = 3.134_059 * x - 1.617_385 * y - 0.490_663 * z;
+	let g = -0.978_795 * x + 1.916_254 * y + 0.033_449 * z;
+	let bl = 0.071_955 * x - 0.228_977 * y + 1.405_386 * z;
+	[linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(bl)]
+}
+
+/// Interleaves planar channels of merged image data into RGBA image
+pub fn composite(
+	header: &PSDHeader,
+	channels: &[Vec<u8>],
+	options: &CompositeOptions,
+) -> Result<Image, PsdError> {
+	let color = color_channels(header.color_mode)?;
+	if channels.len() < color {
+		return Err(PsdError::malformed(format!(
+			"{:?} image needs {} channels, found {}",
+			header.color_mode,
+			color,
+			channels.len()
+		)));
+	}
+	if header.color_mode == ColorMode::Indexed && options.palette.len() < 768 {
+		return Err(PsdError::malformed("Indexed image doesn't have palette"));
+	}
+	let width = header.width as usize;
+	let pixels = width
+		.checked_mul(header.height as usize)
+		.ok_or_else(|| PsdError::malformed("Image size overflow"))?;
+	let channel_size = (width * header.depth as usize).div_ceil(8) * header.height as usize;
+	if let Some(channel) = channels.iter().find(|x| x.len() < channel_size) {
+		return Err(PsdError::malformed(format!(
+			"Channel has {} bytes instead of {}",
+			channel.len(),
+			channel_size
+		)));
+	}
+	let alpha = if options.has_alpha {
+		channels.get(color)
+	} else {
+		None
+	};
+
+	let depth = if header.depth > 8 { 16 } else { 8 };
+	let mut data = Vec::with_capacity(pixels * 4 * depth / 8);
+	for i in 0..pixels {
+		let get = |channel: usize| sample(&channels[channel], i, width, header.depth);
+		let mut pixel = match header.color_mode {
+			ColorMode::Rgb => [get(0)?, get(1)?, get(2)?, 1.0],
+			ColorMode::Cmyk => {
+				// stored inverted: 1 is no ink
+				let k = get(3)?;
+				[get(0)? * k, get(1)? * k, get(2)? * k, 1.0]
+			}
+			ColorMode::Lab => {
+				let [r, g, b] = lab_to_rgb(get(0)?, get(1)?, get(2)?);
+				[r, g, b, 1.0]
+			}
+			ColorMode::Indexed => {
+				let index = channels[0][i] as usize;
+				let transparent = options.transparent_index == Some(index as u16);
+				[
+					f32::from(options.palette[index]) / 255.0,
+					f32::from(options.palette[256 + index]) / 255.0,
+					f32::from(options.palette[512 + index]) / 255.0,
+					if transparent { 0.0 } else { 1.0 },
+				]
+			}
+			_ => {
+				let gray = get(0)?;
+				[gray, gray, gray, 1.0]
+			}
+		};
+		if let Some(alpha) = alpha {
+			pixel[3] = sample(alpha, i, width, header.depth)?;
+		}
+		for value in &pixel {
+			if depth == 8 {
+				data.push((value * 255.0).round() as u8);
+			} else {
+				let value = (value * 65535.0).round() as u16;
+				data.push((value >> 8) as u8);
+				data.push(value as u8);
+			}
+		}
+	}
+
+	Ok(Image {
+		width: header.width,
+		height: header.height,
+		depth: depth as u16,
+		data,
+	})
+}
+
+#[cfg(test)]
+mod image_tests {
+	use super::*;
+	use psd_file::{PSDFile, PSDFileType};
+	use std::fs::File;
+
+	fn header(color_mode: ColorMode, depth: u16, number_of_channels: u16) -> PSDHeader {
+		PSDHeader {
+			version: PSDFileType::PSD,
+			number_of_channels,
+			height: 1,
+			width: 2,
+			depth,
+			color_mode,
+		}
+	}
+
+	#[test]
+	fn composite_image_test() {
+		let file = File::open("./test_data/a_a.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		let image = file.composite_image().unwrap();
+		assert_eq!((image.width, image.height, image.depth), (20, 27, 8));
+		assert_eq!(image.data.len(), 20 * 27 * 4);
+		// outside of "art" layer is transparent
+		assert_eq!(image.get_pixel(0, 0)[3], 0.0);
+		assert_eq!(image.get_pixel(10, 12)[3], 1.0);
+
+		let file = File::open("./test_data/indexed-color.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		let image = file.composite_image().unwrap();
+		assert_eq!((image.width, image.height), (20, 27));
+
+		let file = File::open("./test_data/16bit.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		let image = file.composite_image().unwrap();
+		assert_eq!(image.depth, 16);
+		assert_eq!(&image.data[0..8], &[0, 0, 0, 0, 0, 0, 0xFF, 0xFF]);
+		assert_eq!(&image.data[8..10], &[0x1A, 0x0A]);
+
+		let file = File::open("./test_data/32bit.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		let image = file.composite_image().unwrap();
+		assert_eq!(image.depth, 16);
+		let [r, g, b, a] = image.get_pixel(3, 0);
+		assert!((r - linear_to_srgb(0.75)).abs() < 0.001);
+		assert!((g - linear_to_srgb(0.5)).abs() < 0.001);
+		assert_eq!((b, a), (0.0, 1.0));
+	}
+
+	#[test]
+	fn layer_image_test() {
+		let file = File::open("./test_data/16bit.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		// "Blue" layer at 1,1 of size 2x2
+		let image = file.layer_image(1).unwrap();
+		assert_eq!((image.width, image.height, image.depth), (2, 2, 16));
+		assert_eq!(image.get_pixel(0, 0), [0.0, 0.0, 1.0, 1.0]);
+		assert_eq!(image.get_pixel(1, 1), [0.0, 0.0, 1.0, 0.0]);
+
+		let placed = image.place(4, 3, 1, 1);
+		assert_eq!((placed.width, placed.height), (4, 3));
+		assert_eq!(placed.get_pixel(0, 0), [0.0; 4]);
+		assert_eq!(placed.get_pixel(1, 1), [0.0, 0.0, 1.0, 1.0]);
+		assert_eq!(placed.get_pixel(2, 1)[3], image.get_pixel(1, 0)[3]);
+
+		let placed = image.place(2, 2, -1, 1);
+		assert_eq!(placed.get_pixel(0, 1), image.get_pixel(1, 0));
+		assert_eq!(placed.get_pixel(0, 0), [0.0; 4]);
+		assert_eq!(image.place(2, 2, 5, 5).data, vec![0; 2 * 2 * 8]);
+
+		let file = File::open("./test_data/a_a.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		let image = file.layer_image(0).unwrap();
+		assert_eq!((image.width, image.height, image.depth), (16, 21, 8));
+		assert!(file.layer_image(1).is_err());
+	}
+
+	#[test]
+	fn color_modes_test() {
+		let options = CompositeOptions::default();
+
+		let image = composite(
+			&header(ColorMode::Bitmap, 1, 1),
+			&[vec![0b0100_0000]],
+			&options,
+		)
+		.unwrap();
+		assert_eq!(image.data, vec![255, 255, 255, 255, 0, 0, 0, 255]);
+
+		let gray = header(ColorMode::Grayscale, 8, 2);
+		let image = composite(&gray, &[vec![10, 20], vec![255, 0]], &options).unwrap();
+		assert_eq!(image.data, vec![10, 10, 10, 255, 20, 20, 20, 255]);
+		let with_alpha = CompositeOptions {
+			has_alpha: true,
+			..CompositeOptions::default()
+		};
+		let image = composite(&gray, &[vec![10, 20], vec![255, 0]], &with_alpha).unwrap();
+		assert_eq!(image.data, vec![10, 10, 10, 255, 20, 20, 20, 0]);
+
+		let cmyk = header(ColorMode::Cmyk, 8, 4);
+		let channels = [vec![255, 0], vec![255, 255], vec![255, 255], vec![255, 255]];
+		let image = composite(&cmyk, &channels, &options).unwrap();
+		assert_eq!(image.data, vec![255, 255, 255, 255, 0, 255, 255, 255]);
+
+		let lab = header(ColorMode::Lab, 8, 3);
+		let channels = [vec![255, 0], vec![128, 128], vec![128, 128]];
+		let image = composite(&lab, &channels, &options).unwrap();
+		assert_eq!(image.data, vec![255, 255, 255, 255, 0, 0, 0, 255]);
+
+		let indexed = header(ColorMode::Indexed, 8, 1);
+		let mut palette = vec![0; 768];
+		palette[1] = 255;
+		palette[256 + 2] = 255;
+		let options = CompositeOptions {
+			palette,
+			transparent_index: Some(2),
+			has_alpha: false,
+		};
+		let image = composite(&indexed, &[vec![1, 2]], &options).unwrap();
+		assert_eq!(image.data, vec![255, 0, 0, 255, 0, 255, 0, 0]);
+		assert!(composite(&indexed, &[vec![1]], &options).is_err());
+		assert!(composite(&lab, &[vec![1, 2]], &options).is_err());
+		let rgb = header(ColorMode::Rgb, 24, 3);
+		assert!(composite(&rgb, &[vec![0; 6], vec![0; 6], vec![0; 6]], &options).is_err());
+		let unknown = header(ColorMode::Unknown(5), 8, 1);
+		match composite(&unknown, &[vec![0; 2]], &options) {
+			Err(PsdError::Unsupported(_)) => (),
+			x => panic!("expected Unsupported, got {:?}", x),
+		}
+	}
+}
@@ -0,0 +1,352 @@
+//! Contains `ImageResource` struct and decoders of the common image resource blocks
+
+use byte_reader::ByteReader;
+use error::PsdError;
+
+pub const RESOLUTION_INFO_ID: u16 = 1005;
+pub const IPTC_ID: u16 = 1028;
+pub const THUMBNAIL_BGR_ID: u16 = 1033;
+pub const GRID_GUIDES_ID: u16 = 1032;
+pub const THUMBNAIL_ID: u16 = 1036;
+pub const ICC_PROFILE_ID: u16 = 1039;
+pub const TRANSPARENCY_INDEX_ID: u16 = 1047;
+pub const VERSION_INFO_ID: u16 = 1057;
+pub const XMP_ID: u16 = 1060;
+
+/// Image resource block as found in `image_resources` section
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageResource {
+	pub id: u16,
+	pub name: String,
+	/// start of the data in file
+	pub data_start: u64,
+	/// size of the data without padding
+	pub data_size: u64,
+}
+
+/// Unit used to display resolution
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResolutionUnit {
+	PixelsPerInch,
+	PixelsPerCentimeter,
+	Unknown(u16),
+}
+
+impl From<u16> for ResolutionUnit {
+	fn from(value: u16) -> Self {
+		match value {
+			1 => ResolutionUnit::PixelsPerInch,
+			2 => ResolutionUnit::PixelsPerCentimeter,
+			x => ResolutionUnit::Unknown(x),
+		}
+	}
+}
+
+/// ResolutionInfo resource (1005)
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResolutionInfo {
+	pub horizontal_resolution: f64,
+	pub horizontal_unit: ResolutionUnit,
+	/// 1 = in, 2 = cm, 3 = pt, 4 = picas, 5 = columns
+	pub width_unit: u16,
+	pub vertical_resolution: f64,
+	pub vertical_unit: ResolutionUnit,
+	pub height_unit: u16,
+}
+
+/// Thumbnail resource (1036), or (1033) of Photoshop 4.0
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Thumbnail {
+	/// Resource 1033 stores channels in BGR order
+	pub is_bgr: bool,
+	/// 1 = kJpegRGB, 0 = kRawRGB
+	pub format: u32,
+	pub width: u32,
+	pub height: u32,
+	pub width_bytes: u32,
+	pub total_size: u32,
+	pub compressed_size: u32,
+	pub bits_per_pixel: u16,
+	pub planes: u16,
+	/// JFIF data
+	pub data: Vec<u8>,
+}
+
+/// IPTC-NAA dataset (1028)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IptcRecord {
+	pub record: u8,
+	pub dataset: u8,
+	pub data: Vec<u8>,
+}
+
+/// VersionInfo resource (1057)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionInfo {
+	pub version: u32,
+	pub has_real_merged_data: bool,
+	pub writer_name: String,
+	pub reader_name: String,
+	pub file_version: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GuideDirection {
+	Vertical,
+	Horizontal,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Guide {
+	/// position in pixels
+	pub location: f64,
+	pub direction: GuideDirection,
+}
+
+/// Grid and guides resource (1032)
+#[derive(Clone, Debug, PartialEq)]
+pub struct GridGuides {
+	pub version: u32,
+	pub grid_horizontal: u32,
+	pub grid_vertical: u32,
+	pub guides: Vec<Guide>,
+}
+
+/// Decoded image resource data
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImageResourceData {
+	ResolutionInfo(ResolutionInfo),
+	IccProfile(Vec<u8>),
+	Thumbnail(Thumbnail),
+	Xmp(String),
+	Iptc(Vec<IptcRecord>),
+	VersionInfo(VersionInfo),
+	GridGuides(GridGuides),
+	/// Index of the transparent color of indexed image
+	TransparencyIndex(u16),
+	/// Resource without decoder, contains raw data
+	Unknown(Vec<u8>),
+}
+
+fn decode_resolution_info(data: &[u8]) -> Result<ResolutionInfo, PsdError> {
+	let mut r = ByteReader::new(data);
+	Ok(ResolutionInfo {
+		horizontal_resolution: r.read_fixed()?,
+		horizontal_unit: ResolutionUnit::from(r.read_u16()?),
+		width_unit: r.read_u16()?,
+		vertical_resolution: r.read_fixed()?,
+		vertical_unit: ResolutionUnit::from(r.read_u16()?),
+		height_unit: r.read_u16()?,
+	})
+}
+
+fn decode_thumbnail(data: &[u8], is_bgr: bool) -> Result<Thumbnail, PsdError> {
+	let mut r = ByteReader::new(data);
+	let format = r.read_u32()?;
+	let width = r.read_u32()?;
+	let height = r.read_u32()?;
+	let width_bytes = r.read_u32()?;
+	let total_size = r.read_u32()?;
+	let compressed_size = r.read_u32()?;
+	let bits_per_pixel = r.read_u16()?;
+	let planes = r.read_u16()?;
+	let size = (compressed_size as usize).min(r.remaining());
+	Ok(Thumbnail {
+		is_bgr,
+		format,
+		width,
+		height,
+		width_bytes,
+		total_size,
+		compressed_size,
+		bits_per_pixel,
+		planes,
+		data: r.read_bytes(size)?.to_vec(),
+	})
+}
+
+fn decode_iptc(data: &[u8]) -> Result<Vec<IptcRecord>, PsdError> {
+	let mut r = ByteReader::new(data);
+	let mut out = vec![];
+	while !r.is_empty() {
+		let marker = r.read_u8()?;
+		if marker != 0x1C {
+			// trailing padding
+			if marker == 0 {
+				break;
+			}
+			return Err(PsdError::InvalidValue {
+				label: String::new(),
+				offset: r.position() as u64 - 1,
+				value: u64::from(marker),
+			});
+		}
+		let record = r.read_u8()?;
+		let dataset = r.read_u8()?;
+		let mut size = r.read_u16()? as usize;
+		if size & 0x8000 != 0 {
+			// extended dataset: low bits hold the count of length bytes
+			let count = size & 0x7FFF;
+			size = r
+				.read_bytes(count)?
+				.iter()
+				.fold(0usize, |c, x| c << 8 | *x as usize);
+		}
+		out.push(IptcRecord {
+			record,
+			dataset,
+			data: r.read_bytes(size)?.to_vec(),
+		});
+	}
+	Ok(out)
+}
+
+fn decode_version_info(data: &[u8]) -> Result<VersionInfo, PsdError> {
+	let mut r = ByteReader::new(data);
+	Ok(VersionInfo {
+		version: r.read_u32()?,
+		has_real_merged_data: r.read_u8()? != 0,
+		writer_name: r.read_unicode_string()?,
+		reader_name: r.read_unicode_string()?,
+		file_version: r.read_u32()?,
+	})
+}
+
+fn decode_grid_guides(data: &[u8]) -> Result<GridGuides, PsdError> {
+	let mut r = ByteReader::new(data);
+	let version = r.read_u32()?;
+	let grid_horizontal = r.read_u32()?;
+	let grid_vertical = r.read_u32()?;
+	let count = r.read_u32()?;
+	let mut guides = vec![];
+	for _ in 0..count {
+		let location = f64::from(r.read_i32()?) / 32.0;
+		let direction = match r.read_u8()? {
+			0 => GuideDirection::Vertical,
+			_ => GuideDirection::Horizontal,
+		};
+		guides.push(Guide {
+			location,
+			direction,
+		});
+	}
+	Ok(GridGuides {
+		version,
+		grid_horizontal,
+		grid_vertical,
+		guides,
+	})
+}
+
+/// Decodes resource data according to its id
+pub fn decode_image_resource(id: u16, data: &[u8]) -> Result<ImageResourceData, PsdError> {
+	let res = match id {
+		RESOLUTION_INFO_ID => ImageResourceData::ResolutionInfo(decode_resolution_info(data)?),
+		IPTC_ID => ImageResourceData::Iptc(decode_iptc(data)?),
+		GRID_GUIDES_ID => ImageResourceData::GridGuides(decode_grid_guides(data)?),
+		THUMBNAIL_BGR_ID => ImageResourceData::Thumbnail(decode_thumbnail(data, true)?),
+		THUMBNAIL_ID => ImageResourceData::Thumbnail(decode_thumbnail(data, false)?),
+		ICC_PROFILE_ID => ImageResourceData::IccProfile(data.to_vec()),
+		TRANSPARENCY_INDEX_ID => {
+			ImageResourceData::TransparencyIndex(ByteReader::new(data).read_u16()?)
+		}
+		VERSION_INFO_ID => ImageResourceData::VersionInfo(decode_version_info(data)?),
+		XMP_ID => ImageResourceData::Xmp(String::from_utf8_lossy(data).to_string()),
+		_ => ImageResourceData::Unknown(data.to_vec()),
+	};
+	Ok(res)
+}
+
+#[cfg(test)]
+mod image_resources_tests {
+	use super::*;
+	use psd_file::PSDFile;
+	use std::fs::File;
+
+	#[test]
+	fn decode_test() {
+		let file = File::open("./test_data/a_a.psd").unwrap();
+		let mut file = PSDFile::new(file);
+
+		assert_eq!(file.get_image_resources().unwrap().len(), 31);
+
+		match file.get_image_resource_data(RESOLUTION_INFO_ID).unwrap() {
+			Some(ImageResourceData::ResolutionInfo(info)) => {
+				assert_eq!(info.horizontal_resolution, 300.0);
+				assert_eq!(info.horizontal_unit, ResolutionUnit::PixelsPerInch);
+				assert_eq!(info.vertical_resolution, 300.0);
+			}
+			_ => panic!("resolution info wasn't decoded"),
+		}
+
+		match file.get_image_resource_data(VERSION_INFO_ID).unwrap() {
+			Some(ImageResourceData::VersionInfo(info)) => {
+				assert!(info.has_real_merged_data);
+				assert_eq!(info.writer_name, "Adobe Photoshop");
+				assert_eq!(info.reader_name, "Adobe Photoshop CC 2018");
+			}
+			_ => panic!("version info wasn't decoded"),
+		}
+
+		match file.get_image_resource_data(GRID_GUIDES_ID).unwrap() {
+			Some(ImageResourceData::GridGuides(grid)) => {
+				assert_eq!(grid.guides.len(), 2);
+				assert_eq!(grid.guides[0].location, 10.0);
+				assert_eq!(grid.guides[0].direction, GuideDirection::Vertical);
+				assert_eq!(grid.guides[1].location, 13.5);
+				assert_eq!(grid.guides[1].direction, GuideDirection::Horizontal);
+			}
+			_ => panic!("guides weren't decoded"),
+		}
+
+		match file.get_image_resource_data(THUMBNAIL_ID).unwrap() {
+			Some(ImageResourceData::Thumbnail(thumb)) => {
+				assert_eq!(thumb.format, 1);
+				assert_eq!((thumb.width, thumb.height), (4, 6));
+				assert_eq!(thumb.data.len(), thumb.compressed_size as usize);
+				assert_eq!(&thumb.data[0..2], &[0xFF, 0xD8]);
+			}
+			_ => panic!("thumbnail wasn't decoded"),
+		}
+
+		match file.get_image_resource_data(IPTC_ID).unwrap() {
+			Some(ImageResourceData::Iptc(records)) => {
+				assert_eq!(records[0].record, 1);
+				assert_eq!(records[0].dataset, 90);
+			}
+			_ => panic!("iptc wasn't decoded"),
+		}
+
+		match file.get_image_resource_data(XMP_ID).unwrap() {
+			Some(ImageResourceData::Xmp(xmp)) => assert!(xmp.starts_with("<?xpacket")),
+			_ => panic!("xmp wasn't decoded"),
+		}
+
+		assert!(file.get_image_resource_data(1).unwrap().is_none());
+	}
+
+	#[test]
+	fn thumbnail_test() {
+		let file = File::open("./test_data/large.psb").unwrap();
+		let mut file = PSDFile::new(file);
+		let thumb = file.get_thumbnail().unwrap().unwrap();
+		assert!(!thumb.is_bgr);
+		assert_eq!(thumb.format, 1);
+		assert_eq!(&thumb.data[0..2], &[0xFF, 0xD8]);
+
+		let file = File::open("./test_data/16bit.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		assert!(file.get_thumbnail().unwrap().is_none());
+
+		let mut data = vec![0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 8];
+		data.extend_from_slice(&[0, 0, 0, 6, 0, 0, 0, 2, 0, 24, 0, 1, 0xFF, 0xD8]);
+		match decode_image_resource(THUMBNAIL_BGR_ID, &data).unwrap() {
+			ImageResourceData::Thumbnail(thumb) => {
+				assert!(thumb.is_bgr);
+				assert_eq!((thumb.width, thumb.height), (2, 1));
+				assert_eq!(thumb.data, vec![0xFF, 0xD8]);
+			}
+			_ => panic!("thumbnail wasn't decoded"),
+		}
+	}
+}
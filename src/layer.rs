@@ -0,0 +1,405 @@
+//! Contains `Layer` struct and types of the decoded layer record values
+
+use psd_reader::TaggedBlock;
+
+/// Rectangle as stored in file: top, left, bottom, right
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+	pub top: i32,
+	pub left: i32,
+	pub bottom: i32,
+	pub right: i32,
+}
+
+impl Rect {
+	pub fn width(&self) -> u32 {
+		(i64::from(self.right) - i64::from(self.left)).max(0) as u32
+	}
+
+	pub fn height(&self) -> u32 {
+		(i64::from(self.bottom) - i64::from(self.top)).max(0) as u32
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.width() == 0 || self.height() == 0
+	}
+}
+
+/// Channel id of the layer record
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelId {
+	/// Color channel by its index: 0 = red, 1 = green, 2 = blue etc.
+	Color(u16),
+	/// -1
+	Transparency,
+	/// -2
+	UserMask,
+	/// -3, when both user and vector masks are present
+	RealUserMask,
+	/// Any other negative id
+	Unknown(i16),
+}
+
+impl From<i16> for ChannelId {
+	fn from(value: i16) -> Self {
+		match value {
+			-1 => ChannelId::Transparency,
+			-2 => ChannelId::UserMask,
+			-3 => ChannelId::RealUserMask,
+			x if x >= 0 => ChannelId::Color(x as u16),
+			x => ChannelId::Unknown(x),
+		}
+	}
+}
+
+impl ChannelId {
+	pub fn to_i16(self) -> i16 {
+		match self {
+			ChannelId::Color(x) => x as i16,
+			ChannelId::Transparency => -1,
+			ChannelId::UserMask => -2,
+			ChannelId::RealUserMask => -3,
+			ChannelId::Unknown(x) => x,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Channel {
+	pub id: ChannelId,
+	/// length of the channel data including 2 bytes of compression method
+	pub length: u64,
+	/// start of the channel data in file, at compression method
+	pub data_start: u64,
+}
+
+/// Blend mode of the layer, stored in `blend_mode_key`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+	PassThrough,
+	Normal,
+	Dissolve,
+	Darken,
+	Multiply,
+	ColorBurn,
+	LinearBurn,
+	DarkerColor,
+	Lighten,
+	Screen,
+	ColorDodge,
+	LinearDodge,
+	LighterColor,
+	Overlay,
+	SoftLight,
+	HardLight,
+	VividLight,
+	LinearLight,
+	PinLight,
+	HardMix,
+	Difference,
+	Exclusion,
+	Subtract,
+	Divide,
+	Hue,
+	Saturation,
+	Color,
+	Luminosity,
+	Unknown([u8; 4]),
+}
+
+impl BlendMode {
+	pub fn from_key(key: [u8; 4]) -> Self {
+		match &key {
+			b"pass" => BlendMode::PassThrough,
+			b"norm" => BlendMode::Normal,
+			b"diss" => BlendMode::Dissolve,
+			b"dark" => BlendMode::Darken,
+			b"mul " => BlendMode::Multiply,
+			b"idiv" => BlendMode::ColorBurn,
+			b"lbrn" => BlendMode::LinearBurn,
+			b"dkCl" => BlendMode::DarkerColor,
+			b"lite" => BlendMode::Lighten,
+			b"scrn" => BlendMode::Screen,
+			b"div " => BlendMode::ColorDodge,
+			b"lddg" => BlendMode::LinearDodge,
+			b"lgCl" => BlendMode::LighterColor,
+			b"over" => BlendMode::Overlay,
+			b"sLit" => BlendMode::SoftLight,
+			b"hLit" => BlendMode::HardLight,
+			b"vLit" => BlendMode::VividLight,
+			b"lLit" => BlendMode::LinearLight,
+			b"pLit" => BlendMode::PinLight,
+			b"hMix" => BlendMode::HardMix,
+			b"diff" => BlendMode::Difference,
+			b"smud" => BlendMode::Exclusion,
+			b"fsub" => BlendMode::Subtract,
+			b"fdiv" => BlendMode::Divide,
+			b"hue " => BlendMode::Hue,
+			b"sat " => BlendMode::Saturation,
+			b"colr" => BlendMode::Color,
+			b"lum " => BlendMode::Luminosity,
+			_ => BlendMode::Unknown(key),
+		}
+	}
+
+	pub fn to_key(self) -> [u8; 4] {
+		let key = match self {
+			BlendMode::PassThrough => b"pass",
+			BlendMode::Normal => b"norm",
+			BlendMode::Dissolve => b"diss",
+			BlendMode::Darken => b"dark",
+			BlendMode::Multiply => b"mul ",
+			BlendMode::ColorBurn => b"idiv",
+			BlendMode::LinearBurn => b"lbrn",
+			BlendMode::DarkerColor => b"dkCl",
+			BlendMode::Lighten => b"lite",
+			BlendMode::Screen => b"scrn",
+			BlendMode::ColorDodge => b"div ",
+			BlendMode::LinearDodge => b"lddg",
+			BlendMode::LighterColor => b"lgCl",
+			BlendMode::Overlay => b"over",
+			BlendMode::SoftLight => b"sLit",
+			BlendMode::HardLight => b"hLit",
+			BlendMode::VividLight => b"vLit",
+			BlendMode::LinearLight => b"lLit",
+			BlendMode::PinLight => b"pLit",
+			BlendMode::HardMix => b"hMix",
+			BlendMode::Difference => b"diff",
+			BlendMode::Exclusion => b"smud",
+			BlendMode::Subtract => b"fsub",
+			BlendMode::Divide => b"fdiv",
+			BlendMode::Hue => b"hue ",
+			BlendMode::Saturation => b"sat ",
+			BlendMode::Color => b"colr",
+			BlendMode::Luminosity => b"lum ",
+			BlendMode::Unknown(key) => return key,
+		};
+		*key
+	}
+}
+
+/// Optional parameters of the layer mask, present if bit 4 of mask flags is set
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MaskParameters {
+	pub user_mask_density: Option<u8>,
+	pub user_mask_feather: Option<f64>,
+	pub vector_mask_density: Option<u8>,
+	pub vector_mask_feather: Option<f64>,
+}
+
+/// Real user mask, present when both user and vector masks are set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RealMask {
+	pub flags: u8,
+	pub background: u8,
+	pub rect: Rect,
+}
+
+/// Decoded `mask_data` of the layer record
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerMask {
+	pub rect: Rect,
+	/// 0 or 255
+	pub default_color: u8,
+	/// bit 0 = position relative to layer, bit 1 = disabled, bit 2 = invert,
+	/// bit 3 = from rendering other data, bit 4 = has parameters
+	pub flags: u8,
+	pub parameters: Option<MaskParameters>,
+	pub real: Option<RealMask>,
+}
+
+impl LayerMask {
+	pub fn is_relative(&self) -> bool {
+		self.flags & 0b0000_0001 != 0
+	}
+
+	pub fn is_disabled(&self) -> bool {
+		self.flags & 0b0000_0010 != 0
+	}
+}
+
+/// Blending range: two black values followed by two white values for source and destination
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlendingRange {
+	pub source: [u8; 4],
+	pub destination: [u8; 4],
+}
+
+/// Section divider type from `lsct` tagged block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectionDivider {
+	/// Any other type of layer
+	Other,
+	OpenFolder,
+	ClosedFolder,
+	/// Hidden layer closing the group, stored below its content
+	BoundingDivider,
+}
+
+impl SectionDivider {
+	pub fn from_u32(value: u32) -> Option<Self> {
+		match value {
+			0 => Some(SectionDivider::Other),
+			1 => Some(SectionDivider::OpenFolder),
+			2 => Some(SectionDivider::ClosedFolder),
+			3 => Some(SectionDivider::BoundingDivider),
+			_ => None,
+		}
+	}
+
+	pub fn is_folder(self) -> bool {
+		self == SectionDivider::OpenFolder || self == SectionDivider::ClosedFolder
+	}
+}
+
+/// Decoded layer record
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layer {
+	pub rect: Rect,
+	pub channels: Vec<Channel>,
+	pub blend_mode: BlendMode,
+	pub opacity: u8,
+	/// false = base, true = non-base
+	pub clipping: bool,
+	/// raw `flags` byte
+	pub flags: u8,
+	/// bit 1 of flags is unset
+	pub visible: bool,
+	/// transparency protected, bit 0 of flags
+	pub locked: bool,
+	pub mask: Option<LayerMask>,
+	/// Composite gray range followed by range of each channel
+	pub blending_ranges: Vec<BlendingRange>,
+	/// Unicode name from `luni` tagged block, or `pascal_name` if there is no such block
+	pub name: String,
+	/// Legacy MacRoman name, truncated to 255 bytes
+	pub pascal_name: String,
+	/// Id from `lyid` tagged block
+	pub id: Option<u32>,
+	/// Section divider type from `lsct` or `lsdk` tagged block
+	pub section_divider: Option<SectionDivider>,
+	/// Tagged blocks of `additional_data`
+	pub tagged_blocks: Vec<TaggedBlock>,
+}
+
+impl Layer {
+	/// Gets rect covered by channel: mask channels have their own rect
+	pub fn channel_rect(&self, id: ChannelId) -> Rect {
+		match (id, &self.mask) {
+			(ChannelId::UserMask, Some(mask)) => mask.rect,
+			(ChannelId::RealUserMask, Some(mask)) => mask.real.map(|x| x.rect).unwrap_or(mask.rect),
+			(ChannelId::UserMask, None) | (ChannelId::RealUserMask, None) => Rect::default(),
+			_ => self.rect,
+		}
+	}
+
+	/// Finds first tagged block with given key
+	pub fn get_tagged_block(&self, key: &str) -> Option<&TaggedBlock> {
+		self.tagged_blocks.iter().find(|x| x.key == key)
+	}
+}
+
+#[cfg(test)]
+mod layer_tests {
+	use super::*;
+	use psd_file::PSDFile;
+	use std::fs::{read, File};
+	use std::io::Cursor;
+
+	#[test]
+	fn get_layers_test() {
+		let file = File::open("./test_data/a_a.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		let layers = file.get_layers().unwrap();
+		assert_eq!(layers.len(), 2);
+
+		let layer = &layers[0];
+		assert_eq!(
+			layer.rect,
+			Rect {
+				top: 3,
+				left: 2,
+				bottom: 24,
+				right: 18
+			}
+		);
+		assert_eq!((layer.rect.width(), layer.rect.height()), (16, 21));
+		let ids: Vec<ChannelId> = layer.channels.iter().map(|x| x.id).collect();
+		assert_eq!(
+			ids,
+			vec![
+				ChannelId::Transparency,
+				ChannelId::Color(0),
+				ChannelId::Color(1),
+				ChannelId::Color(2),
+				ChannelId::UserMask,
+			]
+		);
+		assert_eq!(layer.channels[0].length, 258);
+		assert_eq!(layer.blend_mode, BlendMode::Multiply);
+		assert_eq!(layer.blend_mode.to_key(), *b"mul ");
+		assert_eq!(layer.opacity, 255);
+		assert!(!layer.clipping);
+		assert!(layer.visible);
+		assert!(!layer.locked);
+		let mask = layer.mask.as_ref().unwrap();
+		assert!(mask.rect.is_empty());
+		assert_eq!(mask.default_color, 255);
+		assert!(mask.parameters.is_none());
+		assert!(mask.real.is_none());
+		assert_eq!(layer.blending_ranges.len(), 5);
+		assert_eq!(layer.blending_ranges[0].source, [0, 0, 255, 255]);
+		assert_eq!(layer.name, "art");
+		assert_eq!(layer.pascal_name, "art");
+		assert_eq!(layer.id, Some(14));
+		assert_eq!(layer.tagged_blocks.len(), 9);
+		assert_eq!(layer.get_tagged_block("lyid").unwrap().data_size, 4);
+
+		let layer = &layers[1];
+		assert_eq!(layer.name, "be art");
+		assert_eq!(layer.blend_mode, BlendMode::Normal);
+		assert!(layer.rect.is_empty());
+		assert!(layer.mask.is_none());
+		assert_eq!(layer.id, Some(50));
+		assert!(layer.section_divider.is_none());
+	}
+
+	#[test]
+	fn unicode_name_test() {
+		let file = File::open("./test_data/unicode_names.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		let layers = file.get_layers().unwrap();
+		let names: Vec<&str> = layers.iter().map(|x| x.name.as_str()).collect();
+		assert_eq!(names, vec!["Café", "Кнопка", "按钮"]);
+		assert_eq!(layers[0].pascal_name, "Café");
+		assert_eq!(layers[1].pascal_name, "??????");
+
+		let mut data = read("./test_data/unicode_names.psd").unwrap();
+		// string length of `luni` block of "Кнопка"
+		assert_eq!(&data[208..216], b"8BIMluni");
+		data[220] = 0x7F;
+		let mut file = PSDFile::new(Cursor::new(data));
+		assert!(file.get_indexes().is_ok());
+		assert_eq!(file.get_layers().unwrap()[1].name, "??????");
+	}
+
+	#[test]
+	fn unknown_divider_test() {
+		let mut data = read("./test_data/groups.psd").unwrap();
+		// value of the first `lsct` block, bounding divider of "UI" group
+		assert_eq!(&data[344..356], b"8BIMlsct\0\0\0\x04");
+		data[359] = 9;
+		let mut file = PSDFile::new(Cursor::new(data));
+		assert!(file.get_indexes().is_ok());
+		let layers = file.get_layers().unwrap();
+		assert_eq!(layers[1].section_divider, Some(SectionDivider::Other));
+	}
+
+	#[test]
+	fn channel_id_test() {
+		for id in -4..3 {
+			assert_eq!(ChannelId::from(id).to_i16(), id);
+		}
+		assert_eq!(BlendMode::from_key(*b"abcd"), BlendMode::Unknown(*b"abcd"));
+	}
+}
@@ -0,0 +1,211 @@
+//! Contains `LayerTree` struct which reconstructs layer groups from section dividers
+
+use error::PsdError;
+use layer::{Layer, SectionDivider};
+
+/// Layer or group of the `LayerTree`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LayerNode {
+	/// Index of the layer record in file order
+	pub layer: usize,
+	pub name: String,
+	/// Names of the parent groups and the node joined with "/", e.g. `UI/Buttons/OK`
+	pub path: String,
+	/// `OpenFolder` or `ClosedFolder` for groups, `Other` for the rest
+	pub kind: SectionDivider,
+	/// Index of the parent node
+	pub parent: Option<usize>,
+	/// Indexes of the child nodes in file order, bottom to top
+	pub children: Vec<usize>,
+	/// Index of the layer record of the bounding divider closing the group
+	pub divider: Option<usize>,
+}
+
+impl LayerNode {
+	pub fn is_group(&self) -> bool {
+		self.kind.is_folder()
+	}
+}
+
+/// Hierarchy of layers. Groups are stored in file as flat layer records:
+/// bounding divider, group content and then the group record itself
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LayerTree {
+	nodes: Vec<LayerNode>,
+	roots: Vec<usize>,
+}
+
+impl LayerTree {
+	pub fn new(layers: &[Layer]) -> Result<Self, PsdError> {
+		let mut nodes: Vec<LayerNode> = vec![];
+		// open groups: index of bounding divider and child nodes collected so far
+		let mut stack: Vec<(Option<usize>, Vec<usize>)> = vec![(None, vec![])];
+
+		for (index, layer) in layers.iter().enumerate() {
+			let kind = layer.section_divider.unwrap_or(SectionDivider::Other);
+			if kind == SectionDivider::BoundingDivider {
+				stack.push((Some(index), vec![]));
+				continue;
+			}
+
+			let (divider, children) = if kind.is_folder() {
+				if stack.len() < 2 {
+					return Err(PsdError::malformed(format!(
+						"layer_{} closes group which wasn't opened",
+						index
+					)));
+				}
+				stack.pop().unwrap()
+			} else {
+				(None, vec![])
+			};
+
+			let node = nodes.len();
+			for child in &children {
+				nodes[*child].parent = Some(node);
+			}
+			nodes.push(LayerNode {
+				layer: index,
+				name: layer.name.clone(),
+				path: String::new(),
+				kind,
+				parent: None,
+				children,
+				divider,
+			});
+			stack.last_mut().unwrap().1.push(node);
+		}
+
+		if stack.len() > 1 {
+			return Err(PsdError::malformed(format!(
+				"layer_{} opens group which wasn't closed",
+				stack.last().unwrap().0.unwrap()
+			)));
+		}
+
+		// parents always follow their children, so fill paths top down
+		for node in (0..nodes.len()).rev() {
+			let path = match nodes[node].parent {
+				Some(parent) => format!("{}/{}", nodes[parent].path, nodes[node].name),
+				None => nodes[node].name.clone(),
+			};
+			nodes[node].path = path;
+		}
+
+		Ok(Self {
+			nodes,
+			roots: stack.pop().unwrap().1,
+		})
+	}
+
+	/// Gets all nodes, children always precede their parent
+	pub fn nodes(&self) -> &[LayerNode] {
+		&self.nodes
+	}
+
+	/// Gets indexes of top level nodes in file order
+	pub fn roots(&self) -> &[usize] {
+		&self.roots
+	}
+
+	pub fn get(&self, node: usize) -> Option<&LayerNode> {
+		self.nodes.get(node)
+	}
+
+	/// Finds first node by its path
+	pub fn find(&self, path: &str) -> Option<&LayerNode> {
+		self.nodes.iter().find(|x| x.path == path)
+	}
+
+	/// Gets parent node
+	pub fn parent(&self, node: &LayerNode) -> Option<&LayerNode> {
+		node.parent.map(|x| &self.nodes[x])
+	}
+
+	/// Gets child nodes in file order
+	pub fn children<'a>(&'a self, node: &'a LayerNode) -> impl Iterator<Item = &'a LayerNode> + 'a {
+		node.children.iter().map(move |x| &self.nodes[*x])
+	}
+}
+
+#[cfg(test)]
+mod layer_tree_tests {
+	use super::*;
+	use psd_file::PSDFile;
+	use std::fs::File;
+
+	#[test]
+	fn get_layer_tree_test() {
+		let file = File::open("./test_data/groups.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		let tree = file.get_layer_tree().unwrap();
+
+		let paths: Vec<&str> = tree.nodes().iter().map(|x| x.path.as_str()).collect();
+		assert_eq!(
+			paths,
+			vec![
+				"Background",
+				"UI/Buttons/OK",
+				"UI/Buttons/Cancel",
+				"UI/Buttons",
+				"UI/Title",
+				"UI",
+				"Top"
+			]
+		);
+		let roots: Vec<&str> = tree
+			.roots()
+			.iter()
+			.map(|x| tree.get(*x).unwrap().name.as_str())
+			.collect();
+		assert_eq!(roots, vec!["Background", "UI", "Top"]);
+
+		let ui = tree.find("UI").unwrap();
+		assert_eq!(ui.kind, SectionDivider::ClosedFolder);
+		assert_eq!((ui.layer, ui.divider), (7, Some(1)));
+		let children: Vec<&str> = tree.children(ui).map(|x| x.name.as_str()).collect();
+		assert_eq!(children, vec!["Buttons", "Title"]);
+
+		let buttons = tree.find("UI/Buttons").unwrap();
+		assert!(buttons.is_group());
+		assert_eq!(buttons.kind, SectionDivider::OpenFolder);
+		assert_eq!(buttons.divider, Some(2));
+
+		let ok = tree.find("UI/Buttons/OK").unwrap();
+		assert!(!ok.is_group());
+		assert_eq!(ok.layer, 3);
+		assert_eq!(tree.parent(ok).unwrap().path, "UI/Buttons");
+		assert!(tree.parent(ui).is_none());
+
+		let file = File::open("./test_data/a_a.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		let tree = file.get_layer_tree().unwrap();
+		assert_eq!(tree.roots().len(), 2);
+		assert_eq!(tree.find("be art").unwrap().layer, 1);
+	}
+
+	#[test]
+	fn short_names_test() {
+		let file = File::open("./test_data/short_names.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		let layers = file.get_layers().unwrap().to_vec();
+		assert_eq!(layers.len(), 4);
+		assert_eq!(layers[3].pascal_name, "G");
+		assert!(layers[1].get_tagged_block("lsct").is_some());
+		assert!(layers[3].get_tagged_block("lsct").is_some());
+		assert!(layers[0].get_tagged_block("lyid").is_some());
+
+		let tree = file.get_layer_tree().unwrap();
+		let paths: Vec<&str> = tree.nodes().iter().map(|x| x.path.as_str()).collect();
+		assert_eq!(paths, vec!["", "G/X", "G"]);
+	}
+
+	#[test]
+	fn unbalanced_test() {
+		let file = File::open("./test_data/groups.psd").unwrap();
+		let mut file = PSDFile::new(file);
+		let layers = file.get_layers().unwrap();
+		assert!(LayerTree::new(&layers[2..]).is_err());
+		assert!(LayerTree::new(&layers[..7]).is_err());
+	}
+}
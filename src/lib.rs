@@ -2,7 +2,26 @@
 //!
 
 extern crate bin_diff;
+extern crate flate2;
 
+mod byte_reader;
+pub mod block_tree;
+pub mod compression;
+pub mod descriptor;
 pub mod diff;
+pub mod effects;
+pub mod engine_data;
+pub mod error;
+pub mod forward_reader;
+pub mod image;
+pub mod image_resources;
+pub mod layer;
+pub mod layer_tree;
+pub mod png;
 pub mod psd_file;
+pub mod psd_header;
 pub mod psd_reader;
+pub mod query;
+pub mod text;
+pub mod validate;
+pub mod vector_path;
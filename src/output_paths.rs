@@ -0,0 +1,126 @@
+use psd_lib::layer_tree::{LayerNode, LayerTree};
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Replaces characters which aren't allowed in file names, path separators included,
+/// so the name is always a single component
+fn sanitize(name: &str) -> String {
+	let name: String = name
+		.chars()
+		.map(|c| match c {
+			'/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+			c if c.is_control() => '_',
+			c => c,
+		})
+		.collect();
+	match name.trim() {
+		"" | "." | ".." => "_".to_string(),
+		name => name.to_string(),
+	}
+}
+
+/// Unique paths of files of the layers inside of the output directory,
+/// named by their group path, e.g. `UI/Buttons/OK.png`
+pub struct OutputPaths {
+	root: PathBuf,
+	extension: String,
+	used: HashSet<Vec<String>>,
+}
+
+impl OutputPaths {
+	pub fn new<P: Into<PathBuf>>(root: P, extension: &str) -> Self {
+		Self {
+			root: root.into(),
+			extension: extension.to_string(),
+			used: HashSet::new(),
+		}
+	}
+
+	/// Reserves path of the file `name` in the output directory, so no layer gets it
+	pub fn reserve(&mut self, name: &str) -> PathBuf {
+		self.used.insert(vec![name.to_string()]);
+		self.root.join(format!("{}.{}", name, self.extension))
+	}
+
+	/// Gets path of the layer `node`, names of the same path get number suffix, e.g. `OK 2.png`
+	pub fn layer_path(&mut self, tree: &LayerTree, node: &LayerNode) -> PathBuf {
+		let mut names: Vec<String> = vec![];
+		let mut current = Some(node);
+		while let Some(node) = current {
+			names.insert(0, sanitize(&node.name));
+			current = tree.parent(node);
+		}
+		let name = names.pop().unwrap();
+		let mut unique = name.clone();
+		let mut n = 1;
+		loop {
+			let mut key = names.clone();
+			key.push(unique.clone());
+			if self.used.insert(key) {
+				break;
+			}
+			n += 1;
+			unique = format!("{} {}", name, n);
+		}
+
+		let mut path = self.root.clone();
+		for name in names {
+			path.push(name);
+		}
+		path.push(format!("{}.{}", unique, self.extension));
+		path
+	}
+}
+
+#[cfg(test)]
+mod output_paths_tests {
+	use super::*;
+	use psd_lib::psd_file::PSDFile;
+	use std::path::Path;
+
+	#[test]
+	fn sanitize_test() {
+		assert_eq!(sanitize("../../x"), ".._.._x");
+		assert_eq!(sanitize("/tmp/x"), "_tmp_x");
+		assert_eq!(sanitize("a\\b:c"), "a_b_c");
+		assert_eq!(sanitize(" .. "), "_");
+		assert_eq!(sanitize(""), "_");
+		assert_eq!(sanitize("OK"), "OK");
+	}
+
+	#[test]
+	fn layer_path_test() {
+		let mut file = PSDFile::open("./test_data/groups.psd").unwrap();
+		let tree = file.get_layer_tree().unwrap();
+		let mut paths = OutputPaths::new("out", "png");
+		assert_eq!(paths.reserve("composite"), Path::new("out/composite.png"));
+		let ok = tree.find("UI/Buttons/OK").unwrap();
+		assert_eq!(
+			paths.layer_path(&tree, ok),
+			Path::new("out/UI/Buttons/OK.png")
+		);
+		assert_eq!(
+			paths.layer_path(&tree, ok),
+			Path::new("out/UI/Buttons/OK 2.png")
+		);
+		assert_eq!(
+			paths.layer_path(&tree, tree.find("UI").unwrap()),
+			Path::new("out/UI.png")
+		);
+
+		let mut file = PSDFile::open("./test_data/a_a.psd").unwrap();
+		let mut layers = file.get_layers().unwrap().to_vec();
+		layers[0].name = "composite".to_string();
+		layers[1].name = "/tmp/../x".to_string();
+		let tree = LayerTree::new(&layers).unwrap();
+		let nodes = tree.nodes();
+		assert_eq!(
+			paths.layer_path(&tree, &nodes[0]),
+			Path::new("out/composite 2.png")
+		);
+		assert_eq!(
+			paths.layer_path(&tree, &nodes[1]),
+			Path::new("out/_tmp_.._x.png")
+		);
+	}
+}
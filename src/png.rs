@@ -0,0 +1,115 @@
+//! Contains PNG encoder of `Image`
+
+use error::PsdError;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::Image;
+use std::io::Write;
+
+static PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+fn crc32(data: &[&[u8]]) -> u32 {
+	let mut crc = 0xFFFF_FFFFu32;
+	for byte in data.iter().flat_map(|x| x.iter()) {
+		crc ^= u32::from(*byte);
+		for _ in 0..8 {
+			crc = if crc & 1 != 0 {
+				0xEDB8_8320 ^ (crc >> 1)
+			} else {
+				crc >> 1
+			};
+		}
+	}
+	!crc
+}
+
+fn write_chunk<W: Write>(output: &mut W, kind: &[u8; 4], data: &[u8]) -> Result<(), PsdError> {
+	output
+		.write_all(&(data.len() as u32).to_be_bytes())
+		.and_then(|_| output.write_all(kind))
+		.and_then(|_| output.write_all(data))
+		.and_then(|_| output.write_all(&crc32(&[kind, data]).to_be_bytes()))?;
+	Ok(())
+}
+
+/// Writes image as RGBA PNG with bit depth of the image
+pub fn write_png<W: Write>(image: &Image, output: &mut W) -> Result<(), PsdError> {
+	if image.depth != 8 && image.depth != 16 {
+		return Err(PsdError::Unsupported(format!("{} bit PNG", image.depth)));
+	}
+	if image.width == 0 || image.height == 0 {
+		return Err(PsdError::malformed("PNG can't be empty"));
+	}
+	let row_size = image.width as usize * 4 * usize::from(image.depth / 8);
+	if image.data.len() != row_size * image.height as usize {
+		return Err(PsdError::malformed("Image data doesn't match its size"));
+	}
+
+	let mut header = vec![];
+	header.extend_from_slice(&image.width.to_be_bytes());
+	header.extend_from_slice(&image.height.to_be_bytes());
+	// bit depth, color type 6 (RGBA), compression, filter, interlace
+	header.extend_from_slice(&[image.depth as u8, 6, 0, 0, 0]);
+
+	let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+	for row in image.data.chunks(row_size) {
+		// filter type none
+		encoder
+			.write_all(&[0])
+			.and_then(|_| encoder.write_all(row))?;
+	}
+	let data = encoder.finish()?;
+
+	output.write_all(&PNG_SIGNATURE)?;
+	write_chunk(output, b"IHDR", &header)?;
+	write_chunk(output, b"IDAT", &data)?;
+	write_chunk(output, b"IEND", &[])?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod png_tests {
+	use super::*;
+	use flate2::read::ZlibDecoder;
+	use std::io::Read;
+
+	#[test]
+	fn crc32_test() {
+		assert_eq!(crc32(&[b"IEND"]), 0xAE42_6082);
+		assert_eq!(crc32(&[b"1234", b"56789"]), 0xCBF4_3926);
+	}
+
+	#[test]
+	fn write_png_test() {
+		let image = Image {
+			width: 2,
+			height: 1,
+			depth: 16,
+			data: vec![
+				0xFF, 0xFF, 0, 0, 0, 0, 0xFF, 0xFF, 0, 0, 0x80, 0, 0, 0, 0, 0,
+			],
+		};
+		let mut out = vec![];
+		write_png(&image, &mut out).unwrap();
+		assert_eq!(&out[0..8], &PNG_SIGNATURE);
+		assert_eq!(&out[12..16], b"IHDR");
+		assert_eq!(&out[16..29], &[0, 0, 0, 2, 0, 0, 0, 1, 16, 6, 0, 0, 0]);
+		let idat_size = u32::from_be_bytes([out[33], out[34], out[35], out[36]]) as usize;
+		assert_eq!(&out[37..41], b"IDAT");
+		let mut raw = vec![];
+		ZlibDecoder::new(&out[41..41 + idat_size])
+			.read_to_end(&mut raw)
+			.unwrap();
+		assert_eq!(raw[0], 0);
+		assert_eq!(&raw[1..], &image.data[..]);
+		assert_eq!(&out[out.len() - 8..out.len() - 4], b"IEND");
+
+		let empty = Image {
+			width: 0,
+			height: 0,
+			depth: 8,
+			data: vec![],
+		};
+		assert!(write_png(&empty, &mut vec![]).is_err());
+	}
+}
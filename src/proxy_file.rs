@@ -25,14 +25,14 @@ pub struct ProxyFile {
 }
 
 impl ProxyFile {
-	pub fn end(mut self) -> Result<(), String> {
+	pub fn end(mut self) -> IOResult<()> {
 		if self.original_path == "-" {
-			(&mut self.writer).flush().map_err(|x| x.to_string())?;
+			(&mut self.writer).flush()?;
 		} else if self.err.is_some() {
-			remove_file(&self.temp_path).map_err(|x| x.to_string())?;
+			remove_file(&self.temp_path)?;
 		} else {
-			(&mut self.writer).flush().map_err(|x| x.to_string())?;
-			rename(&self.temp_path, &self.original_path).map_err(|x| x.to_string())?;
+			(&mut self.writer).flush()?;
+			rename(&self.temp_path, &self.original_path)?;
 		};
 		Ok(())
 	}
@@ -2,11 +2,30 @@
 
 use bin_diff::functions::{u32_to_u8_be_vec, u64_to_u8_be_vec};
 use bin_diff::indexes::{Indexes, WithIndexes};
-use psd_reader::PSDReader;
-use std::convert::From;
+use block_tree::{Block, BlockKind, BlockTree};
+use compression::{decode_channel, ChannelSize, Compression};
+use effects::{
+	decode_effects, decode_legacy_effects, LayerEffects, EFFECTS_KEY, LEGACY_EFFECTS_KEY,
+	MULTI_EFFECTS_KEY,
+};
+use error::PsdError;
+use image::{color_channels, composite, CompositeOptions, Image};
+use image_resources::{
+	decode_image_resource, ImageResource, ImageResourceData, Thumbnail, THUMBNAIL_BGR_ID,
+	THUMBNAIL_ID, TRANSPARENCY_INDEX_ID,
+};
+use layer::{ChannelId, Layer, SectionDivider};
+use layer_tree::LayerTree;
+use psd_header::PSDHeader;
+use psd_reader::{LabelMode, Limits, PSDReader, Recovery, LAYERS_KEYS, PSB_LONG_KEYS};
+use query::Pattern;
+use std::cmp::min;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{copy, Read, Result as IOResult, Seek, SeekFrom, Write};
 use std::path::Path;
+use text::{decode_text_layer, encode_text_layer, TextLayer, TYPE_TOOL_KEY};
+use vector_path::{decode_vector_mask, VectorPath, VECTOR_MASK_KEY, VECTOR_MASK_SETTING_KEY};
 
 const LINES: [&str; 15] = [
 	"header",
@@ -22,11 +41,294 @@ const LINES: [&str; 15] = [
 	"layers_resources/layers_info/padding",
 	"layers_resources/global_mask_length",
 	"layers_resources/global_mask",
-	"layers_resources/additional_layer_information",
+	"layers_resources/additional_layer_information/{key}",
 	"image_data",
 ];
 
-#[derive(Clone, Copy)]
+/// Lines of layers info nested in `Lr16`, `Lr32` and `Layr` tagged blocks
+const LAYERS_INFO_LINES: [&str; 4] = [
+	"layer_count",
+	"layer_{n}",
+	"channel_data/layer_{n}/channel_{n}",
+	"padding",
+];
+
+/// Checks that label is a direct child of `parent`
+fn is_child(label: &str, parent: &str) -> bool {
+	label.starts_with(parent)
+		&& label[parent.len()..].starts_with('/')
+		&& !label[parent.len() + 1..].contains(['/', ':'])
+}
+
+/// Pushes layer record up to its additional data as `layer_{n}` line
+/// followed by a line for each tagged block of additional data
+fn push_layer_lines(all: &[(String, u64, u64)], index: usize, out: &mut Indexes) {
+	let (label, start, size) = &all[index];
+	let additional_data = format!("{}/additional_data", label);
+	// children always follow their parent
+	let children = all[index + 1..].iter().take_while(|x| {
+		x.0.starts_with(label.as_str()) && x.0[label.len()..].starts_with(['/', ':'])
+	});
+
+	let mut head = *size;
+	let mut blocks = vec![];
+	for (child, child_start, child_size) in children {
+		if *child == additional_data {
+			head = child_start - start;
+		} else if is_child(child, &additional_data) {
+			blocks.push((child.clone(), *child_start, *child_size));
+		}
+	}
+
+	out.insert(label.clone(), *start, head);
+	for (child, child_start, child_size) in blocks {
+		out.insert(child, child_start, child_size);
+	}
+}
+
+/// Expands line template into lines: `{n}` stands for repeated blocks, `{key}` for tagged blocks
+fn push_lines(
+	all: &[(String, u64, u64)],
+	indexes: &Indexes,
+	line: &str,
+	out: &mut Indexes,
+) -> Result<(), PsdError> {
+	let pattern = Pattern::new(&line.replace("{key}", "*"));
+	let mut found = false;
+	for (index, (label, start, size)) in all.iter().enumerate() {
+		if !pattern.matches(label) {
+			continue;
+		}
+		found = true;
+		let data = format!("{}/data", label);
+		if line.ends_with("/layer_{n}") {
+			push_layer_lines(all, index, out);
+		} else if line.ends_with("/{key}") && indexes.has(&format!("{}/layer_count", data)) {
+			for part in &["signature", "key", "length"] {
+				push_lines(all, indexes, &format!("{}/{}", label, part), out)?;
+			}
+			for part in &LAYERS_INFO_LINES {
+				push_lines(all, indexes, &format!("{}/{}", data, part), out)?;
+			}
+		} else {
+			out.insert(label.clone(), *start, *size);
+		}
+	}
+	if !found && pattern.is_literal() {
+		return Err(PsdError::NotFound(format!("line \"{}\"", line)));
+	}
+
+	Ok(())
+}
+
+/// Follows blocks passed to start and end listeners of `PSDReader` in `LabelMode::Position`
+/// and tells which line of `PSDFile::get_lines` the bytes after each block boundary belong to,
+/// so lines of input which can't seek back are known while it's read
+pub struct LineTracker {
+	patterns: Vec<Pattern>,
+	/// label and start of the lines found so far
+	lines: Vec<(String, u64)>,
+	/// line of the bytes directly in each open block, innermost last,
+	/// and whether its nested blocks are tagged blocks of a layer record
+	open: Vec<(Option<usize>, bool)>,
+}
+
+impl LineTracker {
+	pub fn new() -> Self {
+		let mut lines = vec![];
+		for line in &LINES {
+			lines.push(line.replace("{key}", "*"));
+			// tagged blocks with layers are split as in `push_lines`
+			if let Some(block) = line.strip_suffix("/{key}") {
+				for part in &["signature", "key", "length"] {
+					lines.push(format!("{}/*/{}", block, part));
+				}
+				for part in &LAYERS_INFO_LINES {
+					lines.push(format!("{}/*/data/{}", block, part));
+				}
+			}
+		}
+		Self {
+			patterns: lines.iter().map(|x| Pattern::new(x)).collect(),
+			lines: vec![],
+			open: vec![],
+		}
+	}
+
+	/// Checks that `block` labeled `label`, which isn't inside of any line, is a line
+	fn is_line(&self, block: &Block, label: &str) -> bool {
+		if let BlockKind::TaggedBlock { key, .. } = block.kind {
+			if LAYERS_KEYS.iter().any(|x| **x == key) {
+				return false;
+			}
+		}
+		self.patterns.iter().any(|x| x.matches(label))
+	}
+
+	/// Handles start of `block` labeled `label`, returns index of the line
+	/// of the bytes after its start
+	pub fn start(&mut self, block: &Block, label: &str) -> Option<usize> {
+		let (parent, layer_blocks) = self.open.last().cloned().unwrap_or((None, false));
+		let entry = match parent {
+			// layer record is a line up to its additional data
+			Some(line)
+				if label.strip_prefix(self.lines[line].0.as_str()) == Some("/additional_data") =>
+			{
+				(None, true)
+			}
+			Some(line) => (Some(line), false),
+			None if layer_blocks || self.is_line(block, label) => {
+				self.lines.push((label.to_string(), block.start));
+				(Some(self.lines.len() - 1), false)
+			}
+			None => (None, false),
+		};
+		self.open.push(entry);
+		entry.0
+	}
+
+	/// Handles end of the innermost block, returns index of the line of the bytes after its end
+	pub fn end(&mut self) -> Option<usize> {
+		self.open.pop();
+		self.open.last().and_then(|x| x.0)
+	}
+
+	/// Gets label and start of the lines found so far
+	pub fn lines(&self) -> &[(String, u64)] {
+		&self.lines
+	}
+}
+
+impl Default for LineTracker {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Part of the file written by `write_repaired`
+enum Part {
+	/// Block of the source file: start and size
+	Copy(u64, u64),
+	Bytes(Vec<u8>),
+}
+
+/// Tagged block of the layer record written in place of `start..end` by `write_tagged_blocks`,
+/// empty range appends the block
+struct BlockEdit {
+	start: u64,
+	end: u64,
+	/// `8BIM` or `8B64`
+	signature: [u8; 4],
+	key: [u8; 4],
+	/// size of the length field
+	length_size: u64,
+	data: Vec<u8>,
+}
+
+impl BlockEdit {
+	/// Encodes tagged block, data is padded to even size
+	fn encode(&self) -> Vec<u8> {
+		let mut data = self.data.clone();
+		data.resize(data.len() + data.len() % 2, 0);
+		let mut out = self.signature.to_vec();
+		out.extend_from_slice(&self.key);
+		out.extend(match self.length_size {
+			8 => u64_to_u8_be_vec(data.len() as u64),
+			_ => u32_to_u8_be_vec(data.len() as u32),
+		});
+		out.extend(data);
+		out
+	}
+}
+
+/// Copies layer record, writing `edits` in place of its tagged blocks
+/// and recomputing length of its extra data
+fn record_parts(
+	indexes: &Indexes,
+	record: &str,
+	edits: &[BlockEdit],
+) -> Result<Vec<Part>, PsdError> {
+	let get = |label: String| {
+		indexes
+			.get(&label)
+			.ok_or_else(|| PsdError::NotFound(format!("block \"{}\"", label)))
+	};
+	let (start, size) = get(record.to_string())?;
+	let (length_start, _) = get(format!("{}/extra_data_length", record))?;
+	let (extra_start, extra_size) = get(format!("{}/extra_data", record))?;
+	let extra_end = extra_start + extra_size;
+
+	let mut edits: Vec<&BlockEdit> = edits.iter().collect();
+	edits.sort_by_key(|x| x.start);
+	let mut extra = vec![];
+	let mut pos = extra_start;
+	for edit in edits {
+		extra.push(Part::Copy(pos, edit.start - pos));
+		extra.push(Part::Bytes(edit.encode()));
+		pos = edit.end;
+	}
+	extra.push(Part::Copy(pos, extra_end - pos));
+
+	let mut parts = vec![
+		Part::Copy(start, length_start - start),
+		Part::Bytes(u32_to_u8_be_vec(parts_size(&extra) as u32)),
+	];
+	parts.extend(extra);
+	parts.push(Part::Copy(extra_end, start + size - extra_end));
+	Ok(parts)
+}
+
+fn parts_size(parts: &[Part]) -> u64 {
+	parts
+		.iter()
+		.map(|x| match x {
+			Part::Copy(_, size) => *size,
+			Part::Bytes(data) => data.len() as u64,
+		})
+		.sum()
+}
+
+/// Appends zero bytes, so size of the parts is a multiple of `pad`
+fn pad_parts(parts: &mut Vec<Part>, pad: u64) {
+	let rem = parts_size(parts) % pad;
+	if rem != 0 {
+		parts.push(Part::Bytes(vec![0; (pad - rem) as usize]));
+	}
+}
+
+/// Encodes merged image of zero samples with RLE compression
+fn blank_image_data(header: &PSDHeader) -> Result<Vec<u8>, PsdError> {
+	let row_size = ChannelSize {
+		width: header.width,
+		rows: 1,
+		depth: header.depth,
+	}
+	.row_size()?;
+	let mut row = vec![];
+	let mut left = row_size;
+	while left > 0 {
+		let run = min(left, 128);
+		// repeat run, single byte is stored as literal
+		row.push(if run == 1 { 0 } else { (257 - run) as u8 });
+		row.push(0);
+		left -= run;
+	}
+	let count = match header.version {
+		PSDFileType::PSD => (row.len() as u16).to_be_bytes().to_vec(),
+		PSDFileType::PSB => u32_to_u8_be_vec(row.len() as u32),
+	};
+	let rows = header.height as usize * usize::from(header.number_of_channels);
+	let mut out = Compression::Rle.to_u16().to_be_bytes().to_vec();
+	for _ in 0..rows {
+		out.extend_from_slice(&count);
+	}
+	for _ in 0..rows {
+		out.extend_from_slice(&row);
+	}
+	Ok(out)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PSDFileType {
 	PSD,
 	PSB,
@@ -36,6 +338,14 @@ pub enum PSDFileType {
 pub struct PSDFile<T: Read + Seek> {
 	file: T,
 	indexes: Option<Indexes>,
+	tree: Option<BlockTree>,
+	header: Option<PSDHeader>,
+	image_resources: Vec<ImageResource>,
+	layers: Vec<Layer>,
+	label_mode: LabelMode,
+	limits: Limits,
+	lenient: bool,
+	recovery: Option<Recovery>,
 }
 
 impl<T: Read + Seek> PSDFile<T> {
@@ -43,102 +353,479 @@ impl<T: Read + Seek> PSDFile<T> {
 		Self {
 			file,
 			indexes: None,
+			tree: None,
+			header: None,
+			image_resources: vec![],
+			layers: vec![],
+			label_mode: LabelMode::Position,
+			limits: Limits::default(),
+			lenient: false,
+			recovery: None,
 		}
 	}
 
-	pub fn get_indexes(&mut self) -> Result<&Indexes, String> {
-		if self.indexes.is_none() {
+	/// Sets labeling mode of indexes and lines, default is `LabelMode::Position`
+	pub fn set_label_mode(&mut self, mode: LabelMode) {
+		if self.label_mode != mode {
+			self.label_mode = mode;
+			self.tree = None;
+		}
+	}
+
+	/// Sets limits of the values read from file, default is `Limits::default()`
+	pub fn set_limits(&mut self, limits: Limits) {
+		if self.limits != limits {
+			self.limits = limits;
+			self.tree = None;
+			self.header = None;
+		}
+	}
+
+	/// Sets lenient mode, in which damaged file is read up to the first error
+	/// instead of failing, see `PSDReader::set_lenient`. Default is false
+	pub fn set_lenient(&mut self, lenient: bool) {
+		if self.lenient != lenient {
+			self.lenient = lenient;
+			self.tree = None;
+		}
+	}
+
+	pub fn is_lenient(&self) -> bool {
+		self.lenient
+	}
+
+	fn read(&mut self) -> Result<(), PsdError> {
+		if self.tree.is_none() {
 			let mut reader = PSDReader::new(&mut self.file);
-			self.indexes = Some(reader.get_indexes()?.clone());
+			reader.set_label_mode(self.label_mode);
+			reader.set_limits(self.limits);
+			reader.set_lenient(self.lenient);
+			self.indexes = None;
+			self.tree = Some(reader.get_block_tree()?.clone());
+			self.header = Some(reader.get_psd_header()?.clone());
+			self.image_resources = reader.get_image_resources()?.to_vec();
+			self.layers = reader.get_layers()?.to_vec();
+			self.recovery = reader.take_recovery()?;
+		}
+		Ok(())
+	}
+
+	/// Gets blocks which were skipped in lenient mode, `None` if file was read completely
+	pub fn get_recovery(&mut self) -> Result<Option<&Recovery>, PsdError> {
+		self.read()?;
+		Ok(self.recovery.as_ref())
+	}
+
+	/// Gets `Indexes`, which are derived from `BlockTree` on the first call
+	pub fn get_indexes(&mut self) -> Result<&Indexes, PsdError> {
+		self.read()?;
+		if self.indexes.is_none() {
+			self.indexes = Some(self.tree.as_ref().unwrap().to_indexes());
 		}
 		Ok(self.indexes.as_ref().unwrap())
 	}
 
-	pub fn get_lines(&mut self) -> Result<Indexes, String> {
-		let mut out: Indexes = Indexes::new();
-		let indexes = self.get_indexes()?;
+	/// Gets `BlockTree`, hierarchy of the blocks of `Indexes`
+	pub fn get_block_tree(&mut self) -> Result<&BlockTree, PsdError> {
+		self.read()?;
+		Ok(self.tree.as_ref().unwrap())
+	}
 
-		let findline = |line: String, out: &mut Indexes| -> Result<(), String> {
-			let val = indexes
-				.get(&line)
-				.ok_or_else(|| format!("line \"{}\" wasn't found", line))?;
-			out.insert(line, val.0, val.1);
+	/// Gets decoded `header` section, only the header is read if the file wasn't read yet
+	pub fn get_header(&mut self) -> Result<&PSDHeader, PsdError> {
+		if self.header.is_none() {
+			let mut reader = PSDReader::new(&mut self.file);
+			reader.set_limits(self.limits);
+			self.header = Some(reader.get_psd_header()?.clone());
+		}
+		Ok(self.header.as_ref().unwrap())
+	}
+
+	/// Gets image resource blocks in file order
+	pub fn get_image_resources(&mut self) -> Result<&[ImageResource], PsdError> {
+		self.read()?;
+		Ok(&self.image_resources)
+	}
+
+	/// Gets decoded layer records in file order
+	pub fn get_layers(&mut self) -> Result<&[Layer], PsdError> {
+		self.read()?;
+		Ok(&self.layers)
+	}
 
-			Ok(())
+	/// Reads and decompresses channel of the layer into raw planar rows covering channel rect
+	pub fn get_layer_channel(&mut self, layer: usize, channel: usize) -> Result<Vec<u8>, PsdError> {
+		let label = format!("layer_{}/channel_{}", layer, channel);
+		let (version, depth) = {
+			let header = self.get_header()?;
+			(header.version, header.depth)
 		};
+		let (rect, channel) = {
+			let layer = self
+				.get_layers()?
+				.get(layer)
+				.ok_or_else(|| PsdError::NotFound(format!("layer {}", layer)))?;
+			let channel = layer
+				.channels
+				.get(channel)
+				.ok_or_else(|| PsdError::NotFound(format!("channel {}", channel)))?;
+			(layer.channel_rect(channel.id), *channel)
+		};
+		if channel.length < 2 {
+			return Err(PsdError::Malformed {
+				label,
+				offset: channel.data_start,
+				message: "channel is shorter than compression method".to_string(),
+			});
+		}
+		let data = self.read_block(&label, channel.data_start, channel.length)?;
+		let size = ChannelSize {
+			width: rect.width(),
+			rows: rect.height(),
+			depth,
+		};
+		Self::decode_compressed(&data, size, version)
+			.map_err(|err| err.within(&label, channel.data_start))
+	}
 
-		for line in &LINES {
-			let line = line.to_string();
-			if line == "image_resources/image_resource_{n}" {
-				let mut i = 0;
-				while let Ok(()) =
-					findline(format!("image_resources/image_resource_{}", i), &mut out)
-				{
-					i += 1;
-				}
-				continue;
-			}
-			if line == "layers_resources/layers_info/layer_{n}" {
-				let mut i = 0;
-				while let Ok(()) = findline(
-					format!("layers_resources/layers_info/layer_{}", i),
-					&mut out,
-				) {
-					i += 1;
-				}
-				continue;
-			}
-			if line == "layers_resources/layers_info/channel_data/layer_{n}/channel_{n}" {
-				let mut layer = 0;
-				loop {
-					let mut channel = 0;
-					while let Ok(()) = findline(
-						format!(
-							"layers_resources/layers_info/channel_data/layer_{}/channel_{}",
-							layer, channel
-						),
-						&mut out,
-					) {
-						channel += 1;
-					}
-					if channel == 0 {
-						break;
-					}
-					layer += 1;
-				}
-				continue;
+	/// Reads and decompresses merged `image_data` into raw planar rows of each channel
+	pub fn get_image_data_channels(&mut self) -> Result<Vec<Vec<u8>>, PsdError> {
+		let header = self.get_header()?.clone();
+		let (start, size) = self
+			.get_indexes()?
+			.get("image_data")
+			.ok_or_else(|| PsdError::NotFound("image_data".to_string()))?;
+		if size < 2 {
+			return Err(PsdError::Malformed {
+				label: "image_data".to_string(),
+				offset: start,
+				message: "image_data is shorter than compression method".to_string(),
+			});
+		}
+		let data = self.read_block("image_data", start, size)?;
+		let channels = u32::from(header.number_of_channels);
+		let size = ChannelSize {
+			width: header.width,
+			rows: header.height.checked_mul(channels).ok_or_else(|| {
+				PsdError::malformed("image_data size overflow").within("image_data", start)
+			})?,
+			depth: header.depth,
+		};
+		let channel_size = size.row_size()? * header.height as usize;
+		let data = Self::decode_compressed(&data, size, header.version)
+			.map_err(|err| err.within("image_data", start))?;
+		Ok((0..channels as usize)
+			.map(|i| data[i * channel_size..(i + 1) * channel_size].to_vec())
+			.collect())
+	}
+
+	/// Decodes merged `image_data` into RGBA image
+	pub fn composite_image(&mut self) -> Result<Image, PsdError> {
+		let header = self.get_header()?.clone();
+		let channels = self.get_image_data_channels()?;
+
+		let (start, size) = self
+			.get_indexes()?
+			.get("color_mode_section")
+			.ok_or_else(|| PsdError::NotFound("color_mode_section".to_string()))?;
+		let palette = self.read_block("color_mode_section", start, size)?;
+
+		let transparent_index = match self.get_image_resource_data(TRANSPARENCY_INDEX_ID)? {
+			Some(ImageResourceData::TransparencyIndex(index)) => Some(index),
+			_ => None,
+		};
+
+		// negative layer count means that first extra channel is transparency of merged image
+		let layer_count = self
+			.get_indexes()?
+			.clone()
+			.into_iter()
+			.find(|x| x.0.ends_with("/layer_count") && x.2 == 2);
+		let color = color_channels(header.color_mode)?;
+		let has_alpha = match layer_count {
+			Some((_, start, _)) if channels.len() > color => {
+				self.read_range(start, 1)?[0] & 0x80 != 0
 			}
-			findline(line, &mut out)?;
+			_ => false,
+		};
+
+		composite(
+			&header,
+			&channels,
+			&CompositeOptions {
+				palette,
+				transparent_index,
+				has_alpha,
+			},
+		)
+	}
+
+	/// Decodes layer pixels into RGBA image of layer rect size, layer mask isn't applied
+	pub fn layer_image(&mut self, index: usize) -> Result<Image, PsdError> {
+		let mut header = self.get_header()?.clone();
+		let layer = self
+			.get_layers()?
+			.get(index)
+			.ok_or_else(|| PsdError::NotFound(format!("layer {}", index)))?
+			.clone();
+		if layer.rect.is_empty() {
+			return Err(PsdError::Unsupported(format!(
+				"image of empty layer {}",
+				index
+			)));
 		}
 
-		Ok(out)
+		let color = color_channels(header.color_mode)?;
+		let mut channels = vec![];
+		for id in 0..color {
+			let channel = layer
+				.channels
+				.iter()
+				.position(|x| x.id == ChannelId::Color(id as u16))
+				.ok_or_else(|| PsdError::NotFound(format!("channel {} of layer {}", id, index)))?;
+			channels.push(self.get_layer_channel(index, channel)?);
+		}
+		let alpha = layer
+			.channels
+			.iter()
+			.position(|x| x.id == ChannelId::Transparency);
+		if let Some(channel) = alpha {
+			channels.push(self.get_layer_channel(index, channel)?);
+		}
+
+		let (start, size) = self
+			.get_indexes()?
+			.get("color_mode_section")
+			.ok_or_else(|| PsdError::NotFound("color_mode_section".to_string()))?;
+		let palette = self.read_block("color_mode_section", start, size)?;
+
+		header.width = layer.rect.width();
+		header.height = layer.rect.height();
+		composite(
+			&header,
+			&channels,
+			&CompositeOptions {
+				palette,
+				transparent_index: None,
+				has_alpha: alpha.is_some(),
+			},
+		)
 	}
 
-	/// writes composite (merged) psd file
-	pub fn write_composite<W: Write>(&mut self, output: &mut W) -> Result<(), String> {
-		let indexes = self.get_indexes()?.clone();
-		let psd_type = {
-			self.seek(SeekFrom::Start(4)).map_err(|x| x.to_string())?;
-			let mut buf = [0; 2];
-			self.read_exact(&mut buf).map_err(|x| x.to_string())?;
-			match buf {
-				[0, 1] => PSDFileType::PSD,
-				[0, 2] => PSDFileType::PSB,
-				_ => {
-					return Err("Unknown PSD type".to_string());
-				}
+	/// Decodes data starting with 2 bytes of compression method
+	fn decode_compressed(
+		data: &[u8],
+		size: ChannelSize,
+		version: PSDFileType,
+	) -> Result<Vec<u8>, PsdError> {
+		let compression = u16::from(data[0]) << 8 | u16::from(data[1]);
+		let compression =
+			Compression::from_u16(compression).ok_or_else(|| PsdError::InvalidValue {
+				label: String::new(),
+				offset: 0,
+				value: u64::from(compression),
+			})?;
+		decode_channel(&data[2..], compression, size, version)
+	}
+
+	/// Builds hierarchy of layer groups
+	pub fn get_layer_tree(&mut self) -> Result<LayerTree, PsdError> {
+		LayerTree::new(self.get_layers()?)
+	}
+
+	/// Reads `size` bytes starting at `start`
+	pub fn read_range(&mut self, start: u64, size: u64) -> Result<Vec<u8>, PsdError> {
+		self.file.seek(SeekFrom::Start(start))?;
+		let mut buf = vec![];
+		Read::by_ref(&mut self.file)
+			.take(size)
+			.read_to_end(&mut buf)?;
+		if (buf.len() as u64) < size {
+			return Err(PsdError::Truncated {
+				label: String::new(),
+				offset: start,
+				expected: size,
+				actual: buf.len() as u64,
+			});
+		}
+		Ok(buf)
+	}
+
+	/// Reads block `label`, so errors carry its label
+	fn read_block(&mut self, label: &str, start: u64, size: u64) -> Result<Vec<u8>, PsdError> {
+		self.read_range(start, size)
+			.map_err(|err| err.within(label, 0))
+	}
+
+	/// Gets label of the image resource `index` in `Indexes`, which depends on `LabelMode`
+	fn image_resource_label(&mut self, index: usize) -> Result<String, PsdError> {
+		self.get_block_tree()?
+			.get(&["image_resources"])
+			.and_then(|x| {
+				x.children.iter().find(|x| match x.kind {
+					BlockKind::ImageResource { index: i, .. } => i == index,
+					_ => false,
+				})
+			})
+			.map(|x| format!("image_resources/{}", x.name))
+			.ok_or_else(|| PsdError::NotFound(format!("image resource {}", index)))
+	}
+
+	/// Reads and decodes data of the image resource `index` of `resources`
+	fn read_image_resource(
+		&mut self,
+		resources: &[ImageResource],
+		index: usize,
+	) -> Result<ImageResourceData, PsdError> {
+		let resource = &resources[index];
+		let result = match self.read_range(resource.data_start, resource.data_size) {
+			Ok(data) => {
+				decode_image_resource(resource.id, &data).map_err(|err| (err, resource.data_start))
 			}
+			Err(err) => Err((err, 0)),
 		};
-		let write_chunk = |label: &str, s: &mut PSDFile<T>, output: &mut W| -> Result<(), String> {
-			let chunk = indexes
-				.get(label)
-				.ok_or_else(|| "cannot get label".to_string())?;
-			s.seek(SeekFrom::Start(chunk.0))
-				.map_err(|x| x.to_string())?;
-			let mut taken = Read::by_ref(s).take(chunk.1);
-			copy(&mut taken, output).map_err(|x| x.to_string())?;
-			Ok(())
+		// label is looked up only on error, as thumbnail is read without reading the whole file
+		result.map_err(|(err, start)| match self.image_resource_label(index) {
+			Ok(label) => err.within(&format!("{}/data", label), start),
+			Err(_) => err,
+		})
+	}
+
+	/// Reads and decodes data of the first image resource with given id
+	pub fn get_image_resource_data(
+		&mut self,
+		id: u16,
+	) -> Result<Option<ImageResourceData>, PsdError> {
+		let resources = self.get_image_resources()?.to_vec();
+		match resources.iter().position(|x| x.id == id) {
+			Some(index) => self.read_image_resource(&resources, index).map(Some),
+			None => Ok(None),
+		}
+	}
+
+	/// Reads data of the first tagged block of the layer with the `key`, along with its label and start
+	fn read_layer_block(
+		&mut self,
+		layer: usize,
+		key: &str,
+	) -> Result<Option<(String, u64, Vec<u8>)>, PsdError> {
+		let block = self
+			.get_layers()?
+			.get(layer)
+			.ok_or_else(|| PsdError::NotFound(format!("layer {}", layer)))?
+			.get_tagged_block(key)
+			.cloned();
+		let block = match block {
+			Some(block) => block,
+			None => return Ok(None),
+		};
+		let label = format!("layer_{}/{}", layer, key);
+		let data = self.read_block(&label, block.data_start, block.data_size)?;
+		Ok(Some((label, block.data_start, data)))
+	}
+
+	/// Reads and decodes `TySh` tagged block of the layer, `None` if layer isn't a text layer
+	pub fn get_layer_text(&mut self, layer: usize) -> Result<Option<TextLayer>, PsdError> {
+		match self.read_layer_block(layer, TYPE_TOOL_KEY)? {
+			Some((label, start, data)) => decode_text_layer(&data)
+				.map(Some)
+				.map_err(|err| err.within(&label, start)),
+			None => Ok(None),
+		}
+	}
+
+	/// Reads and decodes `lmfx` tagged block of the layer, `lfx2` if there is no `lmfx`,
+	/// or legacy `lrFX` if there are neither. `None` if layer has no effects
+	pub fn get_layer_effects(&mut self, layer: usize) -> Result<Option<LayerEffects>, PsdError> {
+		for key in &[MULTI_EFFECTS_KEY, EFFECTS_KEY] {
+			if let Some((label, start, data)) = self.read_layer_block(layer, key)? {
+				return decode_effects(&data)
+					.map(Some)
+					.map_err(|err| err.within(&label, start));
+			}
+		}
+		match self.read_layer_block(layer, LEGACY_EFFECTS_KEY)? {
+			Some((label, start, data)) => decode_legacy_effects(&data)
+				.map(Some)
+				.map_err(|err| err.within(&label, start)),
+			None => Ok(None),
+		}
+	}
+
+	/// Reads and decodes `vmsk` tagged block of the layer, or `vsms` if there is no `vmsk`.
+	/// `None` if layer has no vector mask
+	pub fn get_layer_vector_path(&mut self, layer: usize) -> Result<Option<VectorPath>, PsdError> {
+		for key in &[VECTOR_MASK_KEY, VECTOR_MASK_SETTING_KEY] {
+			if let Some((label, start, data)) = self.read_layer_block(layer, key)? {
+				return decode_vector_mask(&data)
+					.map(Some)
+					.map_err(|err| err.within(&label, start));
+			}
+		}
+		Ok(None)
+	}
+
+	/// Gets embedded thumbnail from resource 1036 or 1033 of the old files.
+	/// Reads file only up to the end of `image_resources` if indexes weren't read yet
+	pub fn get_thumbnail(&mut self) -> Result<Option<Thumbnail>, PsdError> {
+		let resources = if self.tree.is_some() {
+			self.image_resources.clone()
+		} else {
+			let mut reader = PSDReader::new(&mut self.file);
+			reader.set_limits(self.limits);
+			reader.get_image_resources()?.to_vec()
 		};
+		let index = resources
+			.iter()
+			.position(|x| x.id == THUMBNAIL_ID)
+			.or_else(|| resources.iter().position(|x| x.id == THUMBNAIL_BGR_ID));
+		let index = match index {
+			Some(index) => index,
+			None => return Ok(None),
+		};
+		match self.read_image_resource(&resources, index)? {
+			ImageResourceData::Thumbnail(thumb) => Ok(Some(thumb)),
+			_ => Ok(None),
+		}
+	}
+
+	/// Gets blocks which labels match glob-style `pattern` in file order, see `Pattern`
+	pub fn query(&mut self, pattern: &str) -> Result<Vec<(String, u64, u64)>, PsdError> {
+		let pattern = Pattern::new(pattern);
+		Ok(self
+			.get_indexes()?
+			.clone()
+			.into_iter()
+			.filter(|x| pattern.matches(&x.0))
+			.collect())
+	}
+
+	pub fn get_lines(&mut self) -> Result<Indexes, PsdError> {
+		let mut out: Indexes = Indexes::new();
+		let indexes = self.get_indexes()?;
+		let all: Vec<(String, u64, u64)> = indexes.clone().into_iter().collect();
+
+		for line in &LINES {
+			push_lines(&all, indexes, line, &mut out)?;
+		}
+
+		Ok(out)
+	}
+
+	/// writes composite (merged) psd file
+	pub fn write_composite<W: Write>(&mut self, output: &mut W) -> Result<(), PsdError> {
+		let indexes = self.get_indexes()?.clone();
+		let psd_type = self.get_header()?.version;
+		let write_chunk =
+			|label: &str, s: &mut PSDFile<T>, output: &mut W| -> Result<(), PsdError> {
+				let chunk = indexes
+					.get(label)
+					.ok_or_else(|| PsdError::NotFound(format!("label \"{}\"", label)))?;
+				s.seek(SeekFrom::Start(chunk.0))?;
+				let mut taken = Read::by_ref(s).take(chunk.1);
+				copy(&mut taken, output)?;
+				Ok(())
+			};
 		let layers_length = vec![
 			"layers_resources/layers_info_length",
 			"layers_resources/global_mask_length",
@@ -151,21 +838,15 @@ impl<T: Read + Seek> PSDFile<T> {
 		write_chunk("header", self, output)?;
 		write_chunk("color_mode_section_length", self, output)?;
 		write_chunk("color_mode_section", self, output)?;
-		output.write(&[0, 0, 0, 0]).map_err(|x| x.to_string())?; // image_resources_length
+		output.write_all(&[0, 0, 0, 0])?; // image_resources_length
 		match psd_type {
 			PSDFileType::PSD => {
-				output
-					.write(&u32_to_u8_be_vec(layers_length as u32))
-					.map_err(|x| x.to_string())?; // layers_resources_length
-				output.write(&[0, 0, 0, 0]).map_err(|x| x.to_string())?; // layers_resources/layers_info_length
+				output.write_all(&u32_to_u8_be_vec(layers_length as u32))?; // layers_resources_length
+				output.write_all(&[0, 0, 0, 0])?; // layers_resources/layers_info_length
 			}
 			PSDFileType::PSB => {
-				output
-					.write(&u64_to_u8_be_vec(layers_length))
-					.map_err(|x| x.to_string())?; // layers_resources_length
-				output
-					.write(&[0, 0, 0, 0, 0, 0, 0, 0])
-					.map_err(|x| x.to_string())?; // layers_resources/layers_info_length
+				output.write_all(&u64_to_u8_be_vec(layers_length))?; // layers_resources_length
+				output.write_all(&[0, 0, 0, 0, 0, 0, 0, 0])?; // layers_resources/layers_info_length
 			}
 		};
 		write_chunk("layers_resources/global_mask_length", self, output)?;
@@ -178,15 +859,335 @@ impl<T: Read + Seek> PSDFile<T> {
 		write_chunk("image_data", self, output)?;
 		Ok(())
 	}
+
+	/// Writes valid psd file of the blocks read completely in lenient mode: header,
+	/// color mode section, image resources, global mask, tagged blocks and layers with
+	/// complete channel data, except layers of groups which weren't read to the end.
+	/// Section lengths are recomputed, merged image which can't be decoded is replaced
+	/// with blank one. Switches label mode to `LabelMode::Position`
+	pub fn write_repaired<W: Write>(&mut self, output: &mut W) -> Result<(), PsdError> {
+		self.set_label_mode(LabelMode::Position);
+		self.write_rebuilt(output, &HashMap::new(), true)
+	}
+
+	/// Writes file with tagged blocks of the layers replaced, `blocks` are layer index, key
+	/// and data of the block. First block of the layer with the key is replaced, keeping
+	/// its signature, or, if there is none, the block is appended to the layer's additional
+	/// data with `8BIM` signature. Lengths of layer records
+	/// and sections are recomputed, the rest is copied as is. Layer which wasn't read completely
+	/// or unbalanced group is an error. Switches label mode to `LabelMode::Position`
+	pub fn write_tagged_blocks<W: Write>(
+		&mut self,
+		blocks: &[(usize, &str, Vec<u8>)],
+		output: &mut W,
+	) -> Result<(), PsdError> {
+		self.set_label_mode(LabelMode::Position);
+		let is_psb = self.get_header()?.version == PSDFileType::PSB;
+		let mut records = vec![];
+		self.get_block_tree()?.walk(|block, label, _| {
+			if let BlockKind::Layer { .. } = block.kind {
+				records.push((block.clone(), label.to_string()));
+			}
+		});
+
+		let mut edits: HashMap<String, Vec<BlockEdit>> = HashMap::new();
+		for (layer, key, data) in blocks {
+			let (record, label) = records
+				.get(*layer)
+				.ok_or_else(|| PsdError::NotFound(format!("layer {}", layer)))?;
+			if key.len() != 4 {
+				return Err(PsdError::malformed(format!(
+					"tagged block key \"{}\" isn't 4 bytes long",
+					key
+				)));
+			}
+			let mut block_key = [0; 4];
+			block_key.copy_from_slice(key.as_bytes());
+			let additional_data = record
+				.child("extra_data")
+				.and_then(|x| x.child("additional_data"))
+				.ok_or_else(|| PsdError::NotFound(format!("additional data of layer {}", layer)))?;
+			let target = additional_data.children.iter().find(|x| {
+				x.kind
+					== BlockKind::TaggedBlock {
+						key: block_key,
+						index: 0,
+					}
+			});
+			let edit = match target {
+				Some(target) => {
+					let mut signature = [0; 4];
+					signature.copy_from_slice(&self.read_range(target.start, 4)?);
+					BlockEdit {
+						start: target.start,
+						end: target.end(),
+						signature,
+						key: block_key,
+						length_size: target.child("length").map_or(4, |x| x.size),
+						data: data.clone(),
+					}
+				}
+				None => BlockEdit {
+					start: additional_data.end(),
+					end: additional_data.end(),
+					signature: *b"8BIM",
+					key: block_key,
+					length_size: if is_psb && PSB_LONG_KEYS.iter().any(|x| x[..] == block_key) {
+						8
+					} else {
+						4
+					},
+					data: data.clone(),
+				},
+			};
+			let record_edits = edits.entry(label.clone()).or_default();
+			record_edits.retain(|x| x.key != block_key);
+			record_edits.push(edit);
+		}
+		self.write_rebuilt(output, &edits, false)
+	}
+
+	/// Writes file with `TySh` blocks of the text layers replaced, `texts` are layer index
+	/// and its text layer, see `TextLayer::set_text` and `write_tagged_blocks`
+	pub fn write_text_layers<W: Write>(
+		&mut self,
+		texts: &[(usize, TextLayer)],
+		output: &mut W,
+	) -> Result<(), PsdError> {
+		let mut blocks = vec![];
+		for (layer, text) in texts {
+			let is_text = self
+				.get_layers()?
+				.get(*layer)
+				.is_some_and(|x| x.get_tagged_block(TYPE_TOOL_KEY).is_some());
+			if !is_text {
+				return Err(PsdError::NotFound(format!("text of layer {}", layer)));
+			}
+			blocks.push((*layer, TYPE_TOOL_KEY, encode_text_layer(text)));
+		}
+		self.write_tagged_blocks(&blocks, output)
+	}
+
+	/// Writes file rebuilt from its blocks with `edits` of layer records by their labels,
+	/// see `write_repaired`. Layers are cut and merged image is checked only if `repair` is set
+	fn write_rebuilt<W: Write>(
+		&mut self,
+		output: &mut W,
+		edits: &HashMap<String, Vec<BlockEdit>>,
+		repair: bool,
+	) -> Result<(), PsdError> {
+		let indexes = self.get_indexes()?.clone();
+		let header = self.get_header()?.clone();
+		let block = |label: &str| {
+			indexes
+				.get(label)
+				.map(|(start, size)| Part::Copy(start, size))
+		};
+		let length = |size: u64| match header.version {
+			PSDFileType::PSD => u32_to_u8_be_vec(size as u32),
+			PSDFileType::PSB => u64_to_u8_be_vec(size),
+		};
+
+		let mut parts = vec![];
+		parts.extend(block("header"));
+		match (
+			block("color_mode_section_length"),
+			block("color_mode_section"),
+		) {
+			(Some(length), Some(section)) => parts.extend(vec![length, section]),
+			_ => parts.push(Part::Bytes(vec![0; 4])),
+		}
+
+		let resource = Pattern::new("image_resources/image_resource_{n}");
+		let mut resources: Vec<Part> = indexes
+			.clone()
+			.into_iter()
+			.filter(|x| resource.matches(&x.0))
+			.map(|x| Part::Copy(x.1, x.2))
+			.collect();
+		pad_parts(&mut resources, 2);
+		parts.push(Part::Bytes(u32_to_u8_be_vec(parts_size(&resources) as u32)));
+		parts.extend(resources);
+
+		let mut layers = vec![];
+		let mut layers_info =
+			self.rebuilt_layers_info(&indexes, "layers_resources/layers_info", edits, repair)?;
+		pad_parts(&mut layers_info, 4);
+		layers.push(Part::Bytes(length(parts_size(&layers_info))));
+		layers.extend(layers_info);
+		match (
+			block("layers_resources/global_mask_length"),
+			block("layers_resources/global_mask"),
+		) {
+			(Some(length), Some(mask)) => layers.extend(vec![length, mask]),
+			_ => layers.push(Part::Bytes(vec![0; 4])),
+		}
+
+		// tagged blocks with layers are rebuilt even if they weren't read to the end
+		let key = Pattern::new("layers_resources/additional_layer_information/*/key");
+		let tagged_blocks: Vec<String> = indexes
+			.clone()
+			.into_iter()
+			.filter(|x| key.matches(&x.0))
+			.filter_map(|x| x.0.strip_suffix("/key").map(|x| x.to_string()))
+			.collect();
+		for label in tagged_blocks {
+			let data = format!("{}/data", label);
+			let length_size = indexes.get(&format!("{}/length", label)).map(|x| x.1);
+			match length_size {
+				Some(length_size) if indexes.has(&format!("{}/layer_count", data)) => {
+					let mut info = self.rebuilt_layers_info(&indexes, &data, edits, repair)?;
+					pad_parts(&mut info, 4);
+					let size = parts_size(&info);
+					layers.extend(block(&format!("{}/signature", label)));
+					layers.extend(block(&format!("{}/key", label)));
+					layers.push(Part::Bytes(match length_size {
+						8 => u64_to_u8_be_vec(size),
+						_ => u32_to_u8_be_vec(size as u32),
+					}));
+					layers.extend(info);
+				}
+				_ => layers.extend(block(&label)),
+			}
+		}
+		parts.push(Part::Bytes(length(parts_size(&layers))));
+		parts.extend(layers);
+
+		let image_data = block("image_data");
+		match image_data {
+			Some(image_data) if !repair || self.get_image_data_channels().is_ok() => {
+				parts.push(image_data)
+			}
+			_ => parts.push(Part::Bytes(blank_image_data(&header)?)),
+		}
+
+		for part in parts {
+			match part {
+				Part::Copy(start, size) => {
+					self.file.seek(SeekFrom::Start(start))?;
+					let mut taken = Read::by_ref(&mut self.file).take(size);
+					copy(&mut taken, output)?;
+				}
+				Part::Bytes(data) => output.write_all(&data)?,
+			}
+		}
+		Ok(())
+	}
+
+	/// Rebuilds layers info at `prefix` with every layer. If `repair` is set, it has only layers
+	/// which records and channel data were read completely, cut after the last complete group,
+	/// so groups stay balanced, otherwise missing layer or unbalanced group is an error
+	fn rebuilt_layers_info(
+		&mut self,
+		indexes: &Indexes,
+		prefix: &str,
+		edits: &HashMap<String, Vec<BlockEdit>>,
+		repair: bool,
+	) -> Result<Vec<Part>, PsdError> {
+		let count = match indexes.get(&format!("{}/layer_count", prefix)) {
+			Some((start, 2)) => {
+				let data = self.read_range(start, 2)?;
+				i16::from_be_bytes([data[0], data[1]])
+			}
+			_ => return Ok(vec![]),
+		};
+
+		let mut complete = 0;
+		let mut depth = 0;
+		for index in 0..count.unsigned_abs() as usize {
+			let record = format!("{}/layer_{}", prefix, index);
+			let channel_data = format!("{}/channel_data/layer_{}", prefix, index);
+			if !indexes.has(&record) || !indexes.has(&channel_data) {
+				if !repair {
+					let missing = if indexes.has(&record) {
+						channel_data
+					} else {
+						record
+					};
+					return Err(PsdError::NotFound(format!("block \"{}\"", missing)));
+				}
+				break;
+			}
+			let divider = ["lsct", "lsdk"]
+				.iter()
+				.filter_map(|key| indexes.get(&format!("{}/additional_data/{}/data", record, key)))
+				.find(|x| x.1 >= 4);
+			if let Some((start, _)) = divider {
+				let data = self.read_range(start, 4)?;
+				let value = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+				match SectionDivider::from_u32(value) {
+					Some(SectionDivider::BoundingDivider) => depth += 1,
+					Some(SectionDivider::OpenFolder) | Some(SectionDivider::ClosedFolder) => {
+						depth -= 1
+					}
+					_ => (),
+				}
+			}
+			if depth < 0 {
+				if !repair {
+					return Err(PsdError::malformed(format!(
+						"layer_{} closes group which wasn't opened",
+						index
+					))
+					.within(&record, indexes.get(&record).map_or(0, |x| x.0)));
+				}
+				break;
+			}
+			if depth == 0 {
+				complete = index + 1;
+			}
+		}
+		if !repair && depth > 0 {
+			return Err(PsdError::malformed(format!(
+				"groups of \"{}\" weren't closed",
+				prefix
+			)));
+		}
+
+		// keep sign, which marks transparency of merged image
+		let complete_count = if count < 0 {
+			-(complete as i16)
+		} else {
+			complete as i16
+		};
+		let mut parts = vec![Part::Bytes(complete_count.to_be_bytes().to_vec())];
+		for index in 0..complete {
+			let label = format!("{}/layer_{}", prefix, index);
+			match edits.get(&label) {
+				Some(edits) => parts.extend(record_parts(indexes, &label, edits)?),
+				None => {
+					let (start, size) = indexes.get(&label).unwrap();
+					parts.push(Part::Copy(start, size));
+				}
+			}
+		}
+		for index in 0..complete {
+			let label = format!("{}/channel_data/layer_{}", prefix, index);
+			let (start, size) = indexes.get(&label).unwrap();
+			parts.push(Part::Copy(start, size));
+		}
+		Ok(parts)
+	}
+}
+
+impl PSDFile<File> {
+	/// Opens file at `path`, the file is read lazily on the first request
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, PsdError> {
+		Ok(Self::new(File::open(path)?))
+	}
+
+	/// Opens file at `path`, panics if it can't be opened.
+	/// Shadows `From` implementation, which can't be deprecated itself
+	#[deprecated(note = "use `PSDFile::open`, which returns error instead of panic")]
+	pub fn from<P: AsRef<Path>>(path: P) -> Self {
+		Self::open(path).unwrap()
+	}
 }
 
+/// Kept for compatibility, use `PSDFile::open`. Panics if file can't be opened
 impl<T: AsRef<Path>> From<T> for PSDFile<File> {
 	fn from(path: T) -> Self {
-		let file = File::open(path).unwrap();
-		Self {
-			file,
-			indexes: None,
-		}
+		Self::open(path).unwrap()
 	}
 }
 
@@ -204,6 +1205,266 @@ impl<T: Read + Seek> Seek for PSDFile<T> {
 
 impl<T: Read + Seek> WithIndexes for PSDFile<T> {
 	fn get_indexes(&mut self) -> Result<Indexes, String> {
-		self.get_lines()
+		self.get_lines().map_err(|x| x.to_string())
+	}
+}
+
+#[cfg(test)]
+mod psd_file_tests {
+	use super::*;
+	use forward_reader::ForwardReader;
+	use psd_header::ColorMode;
+	use std::cell::RefCell;
+	use std::fs::read;
+	use std::io::Cursor;
+	use validate::{validate, Severity};
+
+	type MemoryFile = PSDFile<Cursor<Vec<u8>>>;
+
+	/// Gets damaged file read in lenient mode and the file it was repaired into
+	fn repair(data: &[u8]) -> (MemoryFile, MemoryFile) {
+		let mut file = PSDFile::new(Cursor::new(data.to_vec()));
+		file.set_lenient(true);
+		let mut out = vec![];
+		file.write_repaired(&mut out).unwrap();
+		(file, PSDFile::new(Cursor::new(out)))
+	}
+
+	#[test]
+	#[allow(deprecated)]
+	fn from_path_test() {
+		let mut file = PSDFile::from("./test_data/a_a.psd");
+		assert_eq!(file.get_layers().unwrap().len(), 2);
+		let mut file: PSDFile<File> = "./test_data/a_a.psd".into();
+		assert_eq!(file.get_layers().unwrap().len(), 2);
+	}
+
+	#[test]
+	fn get_header_test() {
+		let data = read("./test_data/a_a.psd").unwrap();
+		// only the header is read
+		let mut file = PSDFile::new(Cursor::new(data[..26].to_vec()));
+		assert_eq!(file.get_header().unwrap().width, 20);
+		assert!(file.get_layers().is_err());
+
+		let mut unknown = data.clone();
+		unknown[25] = 5;
+		let mut file = PSDFile::new(Cursor::new(unknown));
+		assert_eq!(file.get_header().unwrap().color_mode, ColorMode::Unknown(5));
+		assert_eq!(file.get_lines().unwrap().len(), 73);
+		assert_eq!(file.get_layers().unwrap().len(), 2);
+		match file.composite_image() {
+			Err(PsdError::Unsupported(_)) => (),
+			x => panic!("expected Unsupported, got {:?}", x),
+		}
+		assert!(validate(&mut file).is_empty());
+	}
+
+	#[test]
+	fn image_resource_error_label_test() {
+		let mut data = read("./test_data/a_a.psd").unwrap();
+		// 4 bytes of resource 11 become the first thumbnail
+		data[56226..56228].copy_from_slice(&THUMBNAIL_ID.to_be_bytes());
+		for (mode, expected) in &[
+			(
+				LabelMode::Position,
+				"image_resources/image_resource_11/data",
+			),
+			(LabelMode::Id, "image_resources/image_resource_id_1036/data"),
+		] {
+			let mut file = PSDFile::new(Cursor::new(data.clone()));
+			file.set_label_mode(*mode);
+			// thumbnail is read first, before the whole file is read
+			for err in &[
+				file.get_thumbnail().unwrap_err(),
+				file.get_image_resource_data(THUMBNAIL_ID).unwrap_err(),
+			] {
+				match err {
+					PsdError::Truncated { label, .. } => assert_eq!(label, expected),
+					x => panic!("expected Truncated, got {:?}", x),
+				}
+			}
+			assert!(file.get_indexes().unwrap().has(expected));
+		}
+	}
+
+	#[test]
+	fn write_repaired_test() {
+		let data = read("./test_data/a_a.psd").unwrap();
+		let (_, repaired) = repair(&data);
+		assert_eq!(repaired.file.get_ref(), &data);
+
+		// inside channel data of the second layer
+		let (mut file, mut repaired) = repair(&data[..64002]);
+		assert!(file.get_recovery().unwrap().is_some());
+		assert_eq!(repaired.get_layers().unwrap().len(), 1);
+		assert_eq!(
+			repaired.get_layers().unwrap()[0].name,
+			file.get_layers().unwrap()[0].name
+		);
+		assert_eq!(
+			repaired.layer_image(0).unwrap(),
+			file.layer_image(0).unwrap()
+		);
+		assert_eq!(
+			repaired.get_image_resources().unwrap().len(),
+			file.get_image_resources().unwrap().len()
+		);
+		let (start, _) = repaired
+			.get_indexes()
+			.unwrap()
+			.get("layers_resources/layers_info/layer_count")
+			.unwrap();
+		assert_eq!(repaired.read_range(start, 2).unwrap(), vec![0xFF, 0xFF]);
+		let image = repaired.composite_image().unwrap();
+		assert!(image.data.iter().all(|x| *x == 0));
+		assert!(validate(&mut repaired).is_empty());
+
+		// header only
+		let (_, mut repaired) = repair(&data[..26]);
+		assert!(repaired.get_layers().unwrap().is_empty());
+		assert!(repaired.composite_image().is_ok());
+	}
+
+	#[test]
+	fn write_repaired_groups_test() {
+		let data = read("./test_data/groups.psd").unwrap();
+		let layers = PSDFile::new(Cursor::new(data.clone()))
+			.get_layers()
+			.unwrap()
+			.len();
+		let mut counts = vec![];
+		for cut in (1246..1398).step_by(8) {
+			let (_, mut repaired) = repair(&data[..cut]);
+			let count = repaired.get_layers().unwrap().len();
+			assert!(repaired.get_layer_tree().is_ok(), "cut at {}", cut);
+			assert!(
+				validate(&mut repaired)
+					.iter()
+					.all(|x| x.severity == Severity::Warning),
+				"cut at {}",
+				cut
+			);
+			counts.push(count);
+		}
+		assert!(counts.iter().all(|x| *x < layers));
+		assert!(counts.iter().any(|x| *x > 0));
+	}
+
+	#[test]
+	fn write_tagged_blocks_signature_test() {
+		let mut data = read("./test_data/a_a.psd").unwrap();
+		let (luni, _) = PSDFile::new(Cursor::new(data.clone()))
+			.get_indexes()
+			.unwrap()
+			.get("layers_resources/layers_info/layer_0/additional_data/luni")
+			.unwrap();
+		let luni = luni as usize;
+		data[luni..luni + 4].copy_from_slice(b"8B64");
+
+		let mut name = 4u32.to_be_bytes().to_vec();
+		for unit in "Name".encode_utf16() {
+			name.extend_from_slice(&unit.to_be_bytes());
+		}
+		let mut out = vec![];
+		PSDFile::new(Cursor::new(data))
+			.write_tagged_blocks(
+				&[(0, "luni", name), (0, "lyvr", vec![0, 0, 0, 1])],
+				&mut out,
+			)
+			.unwrap();
+		let mut file = PSDFile::new(Cursor::new(out));
+		assert_eq!(file.get_layers().unwrap()[0].name, "Name");
+		let blocks = [("luni", "8B64"), ("lyvr", "8BIM")];
+		for (key, signature) in &blocks {
+			let (start, _) = file
+				.get_indexes()
+				.unwrap()
+				.get(&format!(
+					"layers_resources/layers_info/layer_0/additional_data/{}",
+					key
+				))
+				.unwrap();
+			assert_eq!(file.read_range(start, 4).unwrap(), signature.as_bytes());
+		}
+	}
+
+	#[test]
+	fn write_tagged_blocks_keeps_layers_test() {
+		let mut data = read("./test_data/groups.psd").unwrap();
+		let mut out = vec![];
+		PSDFile::new(Cursor::new(data.clone()))
+			.write_tagged_blocks(&[(8, "lyvr", vec![0, 0, 0, 1])], &mut out)
+			.unwrap();
+		let mut file = PSDFile::new(Cursor::new(out));
+		assert_eq!(file.get_layers().unwrap().len(), 9);
+		assert!(file.get_layer_tree().is_ok());
+
+		// bounding divider of "UI" turns into a layer, so "UI" closes group which wasn't opened
+		let (start, _) = PSDFile::new(Cursor::new(data.clone()))
+			.get_indexes()
+			.unwrap()
+			.get("layers_resources/layers_info/layer_1/additional_data/lsct/data")
+			.unwrap();
+		data[start as usize + 3] = 0;
+		let mut file = PSDFile::new(Cursor::new(data));
+		match file.write_tagged_blocks(&[(0, "lyvr", vec![])], &mut vec![]) {
+			Err(PsdError::Malformed { label, .. }) => {
+				assert_eq!(label, "layers_resources/layers_info/layer_7")
+			}
+			x => panic!("expected Malformed, got {:?}", x),
+		}
+	}
+
+	#[test]
+	fn line_tracker_test() {
+		let files = std::fs::read_dir("./test_data")
+			.unwrap()
+			.map(|x| x.unwrap().path())
+			.filter(|x| x.extension().is_some_and(|x| x == "psd" || x == "psb"));
+		for path in files {
+			let mut expected: Vec<(String, u64, u64)> = PSDFile::new(File::open(&path).unwrap())
+				.get_lines()
+				.unwrap()
+				.into_iter()
+				.collect();
+			expected.sort();
+
+			// positions where bytes of another line start
+			let changes = RefCell::new(vec![(0, None)]);
+			let tracker = RefCell::new(LineTracker::new());
+			let mut stream = ForwardReader::new(File::open(&path).unwrap());
+			{
+				let mut reader = PSDReader::new(&mut stream);
+				reader.set_forward_only(true);
+				reader.set_start_listener(|block, label, _| {
+					let line = tracker.borrow_mut().start(block, label);
+					changes.borrow_mut().push((block.start, line));
+				});
+				reader.set_listener(|block, _, _| {
+					let line = tracker.borrow_mut().end();
+					changes.borrow_mut().push((block.start + block.size, line));
+				});
+				reader.get_indexes().unwrap();
+			}
+
+			let tracker = tracker.into_inner();
+			let mut sizes = vec![0; tracker.lines().len()];
+			let mut changes = changes.into_inner();
+			changes.push((path.metadata().unwrap().len(), None));
+			for pair in changes.windows(2) {
+				if let (start, Some(line)) = pair[0] {
+					sizes[line] += pair[1].0 - start;
+				}
+			}
+			let mut lines: Vec<(String, u64, u64)> = tracker
+				.lines()
+				.iter()
+				.zip(sizes)
+				.map(|((label, start), size)| (label.clone(), *start, size))
+				.collect();
+			lines.sort();
+			assert_eq!(lines, expected, "{:?}", path);
+		}
 	}
 }
@@ -0,0 +1,60 @@
+//! Contains `PSDHeader` struct
+
+use psd_file::PSDFileType;
+
+/// Color mode of the document, stored in `header/color_mode`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+	Bitmap,
+	Grayscale,
+	Indexed,
+	Rgb,
+	Cmyk,
+	Multichannel,
+	Duotone,
+	Lab,
+	/// Mode which isn't supported by decoders, e.g. of a newer format version
+	Unknown(u16),
+}
+
+impl ColorMode {
+	pub fn from_u16(value: u16) -> Self {
+		match value {
+			0 => ColorMode::Bitmap,
+			1 => ColorMode::Grayscale,
+			2 => ColorMode::Indexed,
+			3 => ColorMode::Rgb,
+			4 => ColorMode::Cmyk,
+			7 => ColorMode::Multichannel,
+			8 => ColorMode::Duotone,
+			9 => ColorMode::Lab,
+			x => ColorMode::Unknown(x),
+		}
+	}
+
+	pub fn to_u16(self) -> u16 {
+		match self {
+			ColorMode::Bitmap => 0,
+			ColorMode::Grayscale => 1,
+			ColorMode::Indexed => 2,
+			ColorMode::Rgb => 3,
+			ColorMode::Cmyk => 4,
+			ColorMode::Multichannel => 7,
+			ColorMode::Duotone => 8,
+			ColorMode::Lab => 9,
+			ColorMode::Unknown(x) => x,
+		}
+	}
+}
+
+/// Decoded values of the `header` section
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PSDHeader {
+	pub version: PSDFileType,
+	pub number_of_channels: u16,
+	pub height: u32,
+	pub width: u32,
+	/// bits per channel: 1, 8, 16 or 32
+	pub depth: u16,
+	pub color_mode: ColorMode,
+}
@@ -1,14 +1,89 @@
 //! Contains `PSDReader` struct
 
-use bin_diff::functions::{read_usize_be, u_to_i16_be};
+use bin_diff::functions::u_to_i16_be;
 use bin_diff::indexes::Indexes;
-use std::collections::HashMap;
-use std::io::{Read, Seek, SeekFrom};
+use block_tree::{Block, BlockKind, BlockTree};
+use byte_reader::{decode_mac_roman, ByteReader};
+use error::PsdError;
+use image_resources::ImageResource;
+use layer::{
+	BlendMode, BlendingRange, Channel, ChannelId, Layer, LayerMask, MaskParameters, RealMask, Rect,
+	SectionDivider,
+};
+use psd_file::PSDFileType;
+use psd_header::{ColorMode, PSDHeader};
+use std::borrow::Cow;
+use std::cmp::min;
+use std::collections::{HashMap, HashSet};
+use std::io::{copy, sink, Read, Seek, SeekFrom};
+use std::mem;
+use std::ops::RangeInclusive;
 
 static BPS_SIGNATURE: [u8; 4] = [0x38, 0x42, 0x50, 0x53];
 static BIM_SIGNATURE: [u8; 4] = [0x38, 0x42, 0x49, 0x4D];
 static B64_SIGNATURE: [u8; 4] = [0x38, 0x42, 0x36, 0x34];
 
+/// Tagged block keys which have 8 byte length in psb files
+pub(crate) static PSB_LONG_KEYS: [&[u8; 4]; 13] = [
+	b"LMsk", b"Lr16", b"Lr32", b"Layr", b"Mt16", b"Mt32", b"Mtrn", b"Alph", b"FMsk", b"lnk2",
+	b"FEid", b"FXid", b"PxSD",
+];
+
+/// Tagged block keys which data is layers info of the layers of other depth
+pub(crate) static LAYERS_KEYS: [&[u8; 4]; 3] = [b"Lr16", b"Lr32", b"Layr"];
+
+/// Defines how repeated blocks are labeled in `Indexes`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LabelMode {
+	/// `image_resource_{n}` and `layer_{n}` by position in file
+	Position,
+	/// `image_resource_id_{id}` by resource id and `layer_id_{id}` by `lyid` layer id,
+	/// so the same block keeps its label across file versions.
+	/// Layers without `lyid` fall back to `layer_{n}`
+	Id,
+}
+
+/// Limits of the values read from file, exceeding them results in `PsdError::LimitExceeded`.
+/// Defaults accept any file allowed by specification
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+	/// Max width of the document and layer rects
+	pub max_width: u32,
+	/// Max height of the document and layer rects
+	pub max_height: u32,
+	/// Max count of layer records, including nested in `Lr16`, `Lr32` and `Layr`
+	pub max_layers: usize,
+	/// Max count of image resources
+	pub max_resources: usize,
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Self {
+			max_width: 300_000,
+			max_height: 300_000,
+			max_layers: 100_000,
+			max_resources: 10_000,
+		}
+	}
+}
+
+/// Blocks which weren't read in lenient mode
+#[derive(Debug)]
+pub struct Recovery {
+	/// Labels of the blocks which were started but not read completely, outermost first
+	pub partial: Vec<String>,
+	/// Error which stopped reading
+	pub error: PsdError,
+}
+
+/// Keys of layer record tagged blocks which data is read along with the record,
+/// so forward only input doesn't have to seek back for it
+static LAYER_DATA_KEYS: [&[u8; 4]; 4] = [b"lyid", b"luni", b"lsct", b"lsdk"];
+
+/// Max nesting of sections, e.g. layer records inside `Lr16` inside layer records
+const MAX_SECTION_DEPTH: usize = 16;
+
 enum PSDType {
 	PSD,
 	PSB,
@@ -21,17 +96,62 @@ impl PSDType {
 			PSDType::PSB => 8,
 		}
 	}
+
+	fn tagged_block_length(&self, key: &[u8]) -> u8 {
+		match self {
+			PSDType::PSB if PSB_LONG_KEYS.iter().any(|x| x[..] == *key) => 8,
+			_ => 4,
+		}
+	}
 }
 
+/// Tagged block as found in layer records and `additional_layer_information`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TaggedBlock {
+	pub key: String,
+	/// start of the data in file
+	pub data_start: u64,
+	/// size of the data without padding
+	pub data_size: u64,
+}
+
+/// Function called with the block, its label and depth
+type Listener<'a> = Box<dyn FnMut(&Block, &str, usize) + 'a>;
+
 /// PSDReader structure used to get `Indexes` from psd file
 pub struct PSDReader<'a, T: 'a + Read + Seek> {
 	file: &'a mut T,
 	indexes: Option<Indexes>,
+	tree: Option<BlockTree>,
 	pos: u64,
-	starts: HashMap<String, u64>,
-	ends: HashMap<String, u64>,
-	order: Vec<String>,
+	/// blocks being read, innermost last
+	open: Vec<Block>,
+	/// label of the blocks nested in the innermost block being read, without their names
+	label: String,
+	/// length of `label` before each block being read was started
+	label_bases: Vec<usize>,
+	/// top level blocks read completely
+	blocks: Vec<Block>,
 	file_type: PSDType,
+	header: Option<PSDHeader>,
+	image_resources: Vec<ImageResource>,
+	layers: Vec<Layer>,
+	label_mode: LabelMode,
+	image_resources_read: bool,
+	limits: Limits,
+	lenient: bool,
+	recovery: Option<Recovery>,
+	forward_only: bool,
+	file_size: u64,
+	/// ends of the sections being read, innermost last
+	bounds: Vec<u64>,
+	/// data of `LAYER_DATA_KEYS` blocks by data start
+	layer_data: HashMap<u64, Vec<u8>>,
+	listener: Option<Listener<'a>>,
+	start_listener: Option<Listener<'a>>,
+	/// depth of the layer record which blocks aren't passed to listener
+	/// until its labels are known
+	hold: Option<usize>,
 }
 
 impl<'a, T: 'a + Read + Seek> PSDReader<'a, T> {
@@ -39,78 +159,374 @@ impl<'a, T: 'a + Read + Seek> PSDReader<'a, T> {
 		Self {
 			file,
 			indexes: None,
+			tree: None,
 			pos: 0,
-			starts: HashMap::new(),
-			ends: HashMap::new(),
-			order: vec![],
+			open: vec![],
+			label: String::new(),
+			label_bases: vec![],
+			blocks: vec![],
 			file_type: PSDType::PSD,
+			header: None,
+			image_resources: vec![],
+			layers: vec![],
+			label_mode: LabelMode::Position,
+			image_resources_read: false,
+			limits: Limits::default(),
+			lenient: false,
+			recovery: None,
+			forward_only: false,
+			file_size: 0,
+			bounds: vec![],
+			layer_data: HashMap::new(),
+			listener: None,
+			start_listener: None,
+			hold: None,
 		}
 	}
 
-	fn start(&mut self, label: &str) {
-		// eprintln!("starting {:?} at {}", label, self.pos);
-		self.starts.insert(label.to_string(), self.pos);
-		self.order.push(label.to_string());
+	/// Sets labeling mode, default is `LabelMode::Position`
+	pub fn set_label_mode(&mut self, mode: LabelMode) {
+		self.label_mode = mode;
 	}
 
-	fn end(&mut self, label: &str) {
-		// eprintln!("ending   {:?} at {}", label, self.pos);
-		self.ends.insert(label.to_string(), self.pos);
+	/// Sets limits of the values read from file, default is `Limits::default()`
+	pub fn set_limits(&mut self, limits: Limits) {
+		self.limits = limits;
 	}
 
-	fn advance(&mut self, label: &str, size: u64) {
-		self.start(label);
-		self.pos += size;
-		self.end(label);
+	/// Sets lenient mode: reading stops at the first error after the header instead of failing,
+	/// indexes contain only blocks read completely and layers only ones with complete channel data.
+	/// Skipped blocks are described by `take_recovery`
+	pub fn set_lenient(&mut self, lenient: bool) {
+		self.lenient = lenient;
 	}
 
-	fn advance_and_read(&mut self, label: &str, size: u64) -> Result<u64, String> {
-		self.start(label);
+	/// Sets forward only mode for input which can't seek back, e.g. `ForwardReader` over stdin.
+	/// File is read in a single pass without querying its size, `image_data` spans
+	/// to the end of input and position isn't restored after reading
+	pub fn set_forward_only(&mut self, forward_only: bool) {
+		self.forward_only = forward_only;
+	}
 
-		self.file
-			.seek(SeekFrom::Start(self.pos))
-			.map_err(|err| err.to_string())?;
-		let res = read_usize_be(&mut self.file, size as usize).map_err(|err| err.to_string())?;
-		self.pos += size;
-		self.end(label);
+	/// Sets function called with every block, its label and depth while indexes are read.
+	/// Block is passed as soon as it's read completely, so nested blocks are passed
+	/// before the block containing them, and blocks of the same parent in file order.
+	/// In `LabelMode::Id` blocks of layer record are passed after its `lyid` is read
+	pub fn set_listener<F: FnMut(&Block, &str, usize) + 'a>(&mut self, listener: F) {
+		self.listener = Some(Box::new(listener));
+	}
 
-		Ok(res as u64)
+	/// Sets function called with every block, its label and depth when reading of it starts,
+	/// before any of its data is read. Block has no size and nested blocks yet.
+	/// In `LabelMode::Id` it isn't called for blocks of layer records, which labels aren't known
+	pub fn set_start_listener<F: FnMut(&Block, &str, usize) + 'a>(&mut self, listener: F) {
+		self.start_listener = Some(Box::new(listener));
 	}
 
-	fn advance_and_read_vec(&mut self, label: &str, size: u64) -> Result<Vec<u8>, String> {
-		self.start(label);
+	/// Clears state of the previous pass
+	fn reset(&mut self) -> Result<(), PsdError> {
+		self.file_size = if self.forward_only {
+			u64::MAX
+		} else {
+			self.file.seek(SeekFrom::End(0))?
+		};
+		self.bounds.clear();
+		self.pos = 0;
+		self.open.clear();
+		self.label.clear();
+		self.label_bases.clear();
+		self.blocks.clear();
+		self.header = None;
+		self.image_resources.clear();
+		self.layers.clear();
+		self.recovery = None;
+		self.layer_data.clear();
+		self.hold = None;
+		Ok(())
+	}
 
-		self.file
-			.seek(SeekFrom::Start(self.pos))
-			.map_err(|err| err.to_string())?;
-		let mut buf = vec![0; size as usize];
-		self.file
-			.read_exact(&mut buf)
-			.map_err(|err| err.to_string())?;
-		self.pos += size as u64;
-		self.end(label);
+	/// Starts block `name` nested in the innermost block being read.
+	/// Names of the fields are prefixed with `:` if they're separated so,
+	/// functions below take names of the nested blocks the same way
+	/// and empty name for the innermost block itself
+	fn start<N: Into<Cow<'static, str>>>(&mut self, name: N) {
+		self.start_kind(name, BlockKind::Field);
+	}
 
-		Ok(buf)
+	fn start_kind<N: Into<Cow<'static, str>>>(&mut self, name: N, kind: BlockKind) {
+		let block = Block::new(kind, name, self.pos);
+		self.label_bases.push(self.label.len());
+		block.push_label(&mut self.label);
+		if self.hold.is_none() {
+			if let Some(listener) = self.start_listener.as_mut() {
+				listener(&block, &self.label, self.open.len());
+			}
+		}
+		self.open.push(block);
+	}
+
+	/// Starts block which nested blocks are labeled as if they were nested in its parent
+	fn start_transparent(&mut self, name: &'static str) {
+		self.start(name);
+		if let Some(block) = self.open.last_mut() {
+			block.transparent = true;
+		}
+		if let Some(base) = self.label_bases.last() {
+			self.label.truncate(*base);
+		}
+	}
+
+	/// Sets kind of the innermost block being read
+	fn set_kind(&mut self, kind: BlockKind) {
+		if let Some(block) = self.open.last_mut() {
+			block.kind = kind;
+		}
+	}
+
+	/// Ends the innermost block being read
+	fn end(&mut self) {
+		// eprintln!("ending at {}", self.pos);
+		let (mut block, base) = match (self.open.pop(), self.label_bases.pop()) {
+			(Some(block), Some(base)) => (block, base),
+			_ => return,
+		};
+		block.size = self.pos.saturating_sub(block.start);
+		if block.transparent {
+			block.push_label(&mut self.label);
+		}
+		if self.hold.is_none() {
+			if let Some(listener) = self.listener.as_mut() {
+				listener(&block, &self.label, self.open.len());
+			}
+		}
+		self.label.truncate(base);
+		match self.open.last_mut() {
+			Some(parent) => parent.children.push(block),
+			None => self.blocks.push(block),
+		}
 	}
 
-	fn advance_and_check(&mut self, label: &str, subj: &[u8]) -> Result<(), String> {
-		let res = self.advance_and_read_vec(label, subj.len() as u64)?;
-		if res != subj {
-			return Err(format!("Check failed on \"{}\"", label));
+	/// Gets label of the block `name`, see `start`
+	fn label_of(&self, name: &str) -> String {
+		let mut label = self.label.clone();
+		if !label.is_empty() && !name.is_empty() && !name.starts_with(':') {
+			label.push('/');
 		}
+		label.push_str(name);
+		label
+	}
+
+	/// Gets labels of the blocks being read, outermost first
+	fn open_labels(&self) -> Vec<String> {
+		let mut label = String::new();
+		let mut out = vec![];
+		for block in &self.open {
+			let base = label.len();
+			block.push_label(&mut label);
+			out.push(label.clone());
+			if block.transparent {
+				label.truncate(base);
+			}
+		}
+		out
+	}
+
+	/// Gets start of the innermost block being read or the last block read if it's `name`
+	fn start_of(&self, name: &str) -> Option<u64> {
+		let name = name.trim_start_matches(':');
+		if name.is_empty() {
+			return self.open.last().map(|x| x.start);
+		}
+		let last = match self.open.last() {
+			Some(parent) => parent.children.last(),
+			None => self.blocks.last(),
+		};
+		last.filter(|x| x.name == name).map(|x| x.start)
+	}
 
+	/// Passes the last block read along with its nested blocks to listener
+	fn emit_last(&mut self) {
+		let depth = self.open.len();
+		let last = match self.open.last() {
+			Some(parent) => parent.children.last(),
+			None => self.blocks.last(),
+		};
+		if let (Some(listener), Some(block)) = (self.listener.as_mut(), last) {
+			block.walk_nested_first(&self.label, depth, listener);
+		}
+	}
+
+	/// Checks that `size` bytes of `name` at current position fit into file
+	/// and the innermost section, returns their end
+	fn check_size(&self, name: &str, size: u64) -> Result<u64, PsdError> {
+		let bound = self.bounds.last().cloned().unwrap_or(self.file_size);
+		match self.pos.checked_add(size) {
+			Some(end) if end <= bound => Ok(end),
+			Some(end) if end <= self.file_size => Err(PsdError::Overflow {
+				label: self.label_of(name),
+				offset: self.pos,
+				end,
+				bound,
+			}),
+			_ => Err(PsdError::Truncated {
+				label: self.label_of(name),
+				offset: self.pos,
+				expected: size,
+				actual: self.file_size.saturating_sub(self.pos),
+			}),
+		}
+	}
+
+	/// Gets end of section `name` of `size` bytes at current position. In lenient mode
+	/// section past the end of file ends with file, so its complete blocks can be read
+	fn section_end(&self, name: &str, size: u64) -> Result<u64, PsdError> {
+		match self.check_size(name, size) {
+			Err(PsdError::Truncated { .. })
+				if self.lenient
+					&& self.bounds.last().cloned().unwrap_or(self.file_size) == self.file_size =>
+			{
+				Ok(self.file_size)
+			}
+			x => x,
+		}
+	}
+
+	/// Enters section `name` ending at `end`, reads past `end` fail until `leave_section`
+	fn enter_section(&mut self, name: &str, end: u64) -> Result<(), PsdError> {
+		if self.bounds.len() >= MAX_SECTION_DEPTH {
+			return Err(PsdError::LimitExceeded {
+				label: self.label_of(name),
+				offset: self.pos,
+				value: self.bounds.len() as u64 + 1,
+				limit: MAX_SECTION_DEPTH as u64,
+			});
+		}
+		self.check_size(name, end.saturating_sub(self.pos))?;
+		self.bounds.push(end);
 		Ok(())
 	}
 
-	fn advance_and_check_multiple(&mut self, label: &str, subj: &[&[u8]]) -> Result<(), String> {
-		let res = self.advance_and_read_vec(label, subj[0].len() as u64)?;
+	fn leave_section(&mut self) {
+		self.bounds.pop();
+	}
+
+	/// Checks that value of the last read field `name` is in one of the `ranges`
+	fn check_value(
+		&self,
+		name: &str,
+		value: u64,
+		ranges: &[RangeInclusive<u64>],
+	) -> Result<(), PsdError> {
+		if !ranges.iter().any(|x| x.contains(&value)) {
+			return Err(PsdError::InvalidValue {
+				label: self.label_of(name),
+				offset: self.start_of(name).unwrap_or(self.pos),
+				value,
+			});
+		}
+		Ok(())
+	}
+
+	fn check_limit(&self, name: &str, value: u64, limit: u64) -> Result<(), PsdError> {
+		if value > limit {
+			return Err(PsdError::LimitExceeded {
+				label: self.label_of(name),
+				offset: self.start_of(name).unwrap_or(self.pos),
+				value,
+				limit,
+			});
+		}
+		Ok(())
+	}
+
+	fn advance(&mut self, name: &'static str, size: u64) -> Result<(), PsdError> {
+		let end = self.check_size(name, size)?;
+		self.start(name);
+		self.pos = end;
+		self.end();
+		Ok(())
+	}
+
+	/// Gets the last block read completely
+	fn last_block_mut(&mut self) -> Option<&mut Block> {
+		match self.open.last_mut() {
+			Some(parent) => parent.children.last_mut(),
+			None => self.blocks.last_mut(),
+		}
+	}
+
+	/// Reads `size` bytes of `name` at `pos`, reporting end of file as `PsdError::Truncated`
+	fn read_at(&mut self, name: &str, pos: u64, size: u64) -> Result<Vec<u8>, PsdError> {
+		self.file.seek(SeekFrom::Start(pos))?;
+		let mut buf = vec![];
+		Read::by_ref(&mut self.file)
+			.take(size)
+			.read_to_end(&mut buf)?;
+		if (buf.len() as u64) < size {
+			return Err(PsdError::Truncated {
+				label: self.label_of(name),
+				offset: pos,
+				expected: size,
+				actual: buf.len() as u64,
+			});
+		}
+		Ok(buf)
+	}
+
+	fn to_number(data: &[u8]) -> u64 {
+		data.iter().fold(0u64, |c, x| c << 8 | u64::from(*x))
+	}
+
+	/// Reads number of `name` at `pos` without moving position
+	fn peek(&mut self, name: &str, pos: u64, size: u64) -> Result<u64, PsdError> {
+		let data = self.read_at(name, pos, size)?;
+		Ok(Self::to_number(&data))
+	}
+
+	/// Reads bytes of `name` at `pos` without moving position
+	fn peek_vec(&mut self, name: &str, pos: u64, size: u64) -> Result<Vec<u8>, PsdError> {
+		self.read_at(name, pos, size)
+	}
+
+	fn advance_and_read(&mut self, name: &'static str, size: u64) -> Result<u64, PsdError> {
+		let data = self.advance_and_read_vec(name, size)?;
+		Ok(Self::to_number(&data))
+	}
+
+	fn advance_and_read_vec(&mut self, name: &'static str, size: u64) -> Result<Vec<u8>, PsdError> {
+		self.check_size(name, size)?;
+		self.start(name);
+		let pos = self.pos;
+		let buf = self.read_at("", pos, size)?;
+		self.pos += size;
+		self.end();
+
+		Ok(buf)
+	}
+
+	fn advance_and_check(&mut self, name: &'static str, subj: &[u8]) -> Result<(), PsdError> {
+		self.advance_and_check_multiple(name, &[subj])
+	}
+
+	fn advance_and_check_multiple(
+		&mut self,
+		name: &'static str,
+		subj: &[&[u8]],
+	) -> Result<(), PsdError> {
+		let offset = self.pos;
+		let res = self.advance_and_read_vec(name, subj[0].len() as u64)?;
 		for sub in subj {
 			if res == *sub {
 				return Ok(());
 			}
 		}
 
-		Err(format!("Check failed on \"{}\"", label))
+		Err(PsdError::Mismatch {
+			label: self.label_of(name),
+			offset,
+			expected: subj.iter().map(|x| x.to_vec()).collect(),
+			actual: res,
+		})
 	}
 
 	fn pad(n: u64, pad: u64) -> u64 {
@@ -119,422 +535,785 @@ impl<'a, T: 'a + Read + Seek> PSDReader<'a, T> {
 			return n;
 		}
 
-		n + (pad - rem)
+		n.saturating_add(pad - rem)
 	}
 
-	fn get_header(&mut self) -> Result<(), String> {
+	fn get_header(&mut self) -> Result<(), PsdError> {
 		self.start("header");
 
-		self.advance_and_check("header/signature", &BPS_SIGNATURE)?;
+		self.advance_and_check("signature", &BPS_SIGNATURE)
+			.map_err(|err| match err {
+				PsdError::Mismatch { .. } | PsdError::Truncated { .. } => PsdError::NotPsd,
+				err => err,
+			})?;
 
-		let file_type = self.advance_and_read_vec("header/version", 2)?;
-		match file_type.as_slice() {
-			[0x00, 0x01] => self.file_type = PSDType::PSD,
-			[0x00, 0x02] => self.file_type = PSDType::PSB,
-			_ => return Err("Unknown File format".to_string()),
-		}
-
-		self.advance("header/reserved", 6);
-		self.advance("header/number_of_channels", 2);
-		self.advance("header/height", 4);
-		self.advance("header/width", 4);
-		self.advance("header/depth", 2);
-		self.advance("header/color_mode", 2);
+		let version = match self.advance_and_read("version", 2)? {
+			1 => PSDFileType::PSD,
+			2 => PSDFileType::PSB,
+			x => return Err(PsdError::UnsupportedVersion(x as u16)),
+		};
+		self.file_type = match version {
+			PSDFileType::PSD => PSDType::PSD,
+			PSDFileType::PSB => PSDType::PSB,
+		};
 
-		self.end("header");
+		self.advance("reserved", 6)?;
+		let number_of_channels = self.advance_and_read("number_of_channels", 2)?;
+		self.check_value("number_of_channels", number_of_channels, &[1..=56])?;
+		let height = self.advance_and_read("height", 4)?;
+		self.check_value("height", height, &[1..=300_000])?;
+		self.check_limit("height", height, u64::from(self.limits.max_height))?;
+		let width = self.advance_and_read("width", 4)?;
+		self.check_value("width", width, &[1..=300_000])?;
+		self.check_limit("width", width, u64::from(self.limits.max_width))?;
+		let depth = self.advance_and_read("depth", 2)?;
+		self.check_value("depth", depth, &[1..=1, 8..=8, 16..=16, 32..=32])?;
+		// unknown mode is rejected only by image decoding, blocks don't depend on it
+		let color_mode = ColorMode::from_u16(self.advance_and_read("color_mode", 2)? as u16);
+
+		self.header = Some(PSDHeader {
+			version,
+			number_of_channels: number_of_channels as u16,
+			height: height as u32,
+			width: width as u32,
+			depth: depth as u16,
+			color_mode,
+		});
+
+		self.end();
 		Ok(())
 	}
 
-	fn get_color_mode(&mut self) -> Result<(), String> {
+	fn get_color_mode(&mut self) -> Result<(), PsdError> {
 		let len = self.advance_and_read("color_mode_section_length", 4)?;
-		self.advance("color_mode_section", len);
+		self.advance("color_mode_section", len)?;
 
 		Ok(())
 	}
 
-	fn get_image_resource_section(&mut self) -> Result<(), String> {
+	fn get_image_resource_section(&mut self) -> Result<(), PsdError> {
 		let len = self.advance_and_read("image_resources_length", 4)?;
 
+		let end = self.section_end("image_resources", len)?;
+		self.enter_section("image_resources", end)?;
 		self.start("image_resources");
 
-		let mut resource_index: u16 = 0;
-		let end = self.pos + len as u64;
+		let mut resource_index: usize = 0;
+		let mut id_counts: HashMap<u64, usize> = HashMap::new();
 
 		while self.pos < end {
-			self.file
-				.seek(SeekFrom::Start(self.pos))
-				.map_err(|x| x.to_string())?;
-			self.start(&format!(
-				"image_resources/image_resource_{}",
-				resource_index
-			));
+			self.check_limit(
+				"",
+				resource_index as u64 + 1,
+				self.limits.max_resources as u64,
+			)?;
+			let name = match self.label_mode {
+				LabelMode::Position => format!("image_resource_{}", resource_index),
+				LabelMode::Id => {
+					let pos = self.pos;
+					let id =
+						self.peek(&format!("image_resource_{}/id", resource_index), pos + 4, 2)?;
+					let count = id_counts.entry(id).or_insert(0);
+					*count += 1;
+					match *count {
+						1 => format!("image_resource_id_{}", id),
+						n => format!("image_resource_id_{}_{}", id, n - 1),
+					}
+				}
+			};
+			self.start(name);
 			{
-				let name_length;
-				let data_length;
-
-				self.advance_and_check_multiple(
-					&format!(
-						"image_resources/image_resource_{}/signature",
-						resource_index
-					),
-					&[&BIM_SIGNATURE, &B64_SIGNATURE],
-				)?;
+				self.advance_and_check_multiple("signature", &[&BIM_SIGNATURE, &B64_SIGNATURE])?;
 
-				self.advance(
-					&format!("image_resources/image_resource_{}/id", resource_index),
-					2,
-				);
+				let id = self.advance_and_read("id", 2)?;
+				self.set_kind(BlockKind::ImageResource {
+					index: resource_index,
+					id: id as u16,
+				});
 
-				name_length = self.advance_and_read(
-					&format!(
-						"image_resources/image_resource_{}/name_length",
-						resource_index
-					),
-					1,
-				)?;
+				let name_length = self.advance_and_read("name_length", 1)?;
 
-				if name_length == 0 {
-					self.advance(
-						&format!("image_resources/image_resource_{}/name", resource_index),
-						1,
-					);
-				} else {
-					self.advance(
-						&format!("image_resources/image_resource_{}/name", resource_index),
-						Self::pad(name_length + 1, 2) - 1,
-					);
-				}
-
-				data_length = Self::pad(
-					self.advance_and_read(
-						&format!(
-							"image_resources/image_resource_{}/data_length",
-							resource_index
-						),
-						4,
-					)?,
-					2,
-				);
-
-				self.advance(
-					&format!("image_resources/image_resource_{}/data", resource_index),
-					data_length,
-				);
+				let name = self.advance_and_read_vec(
+					"name",
+					if name_length == 0 {
+						1
+					} else {
+						Self::pad(name_length + 1, 2) - 1
+					},
+				)?;
+				let name = String::from_utf8_lossy(&name[..name_length as usize]).to_string();
+
+				let data_size = self.advance_and_read("data_length", 4)?;
+
+				let data_start = self.pos;
+				self.check_size("data", data_size)?;
+				// padding of the last resource may be missing
+				let size = min(Self::pad(data_size, 2), end - self.pos);
+				self.advance("data", size)?;
+
+				self.image_resources.push(ImageResource {
+					id: id as u16,
+					name,
+					data_start,
+					data_size,
+				});
 			}
-			self.end(&format!(
-				"image_resources/image_resource_{}",
-				resource_index
-			));
+			self.end();
 
 			resource_index += 1;
 		}
 
-		self.end("image_resources");
+		self.end();
+		self.leave_section();
 
 		Ok(())
 	}
 
-	fn get_layer(&mut self, prefix: &str) -> Result<(), String> {
+	fn advance_and_read_rect(&mut self, name: &'static str) -> Result<Rect, PsdError> {
+		self.start(name);
+		let top = self.advance_and_read("top", 4)? as u32 as i32;
+		let left = self.advance_and_read("left", 4)? as u32 as i32;
+		let bottom = self.advance_and_read("bottom", 4)? as u32 as i32;
+		let right = self.advance_and_read("right", 4)? as u32 as i32;
+		self.end();
+		Ok(Rect {
+			top,
+			left,
+			bottom,
+			right,
+		})
+	}
+
+	fn advance_and_read_f64(&mut self, name: &'static str) -> Result<f64, PsdError> {
+		let bits = self.advance_and_read(name, 8)?;
+		Ok(f64::from_bits(bits))
+	}
+
+	/// Reads fields of the `mask_data` being read
+	fn get_mask_data(&mut self, length: u64) -> Result<LayerMask, PsdError> {
+		let rect = self.advance_and_read_rect("rect")?;
+		let default_color = self.advance_and_read("default_color", 1)? as u8;
+		let flags = self.advance_and_read("flags", 1)? as u8;
+
+		let mut parameters = None;
+		if flags & 0b0001_0000 != 0 {
+			let params = self.advance_and_read("parameters", 1)?;
+			let mut out = MaskParameters::default();
+			if params & 0b0000_0001 != 0 {
+				out.user_mask_density = Some(self.advance_and_read("user_mask_density", 1)? as u8);
+			}
+			if params & 0b0000_0010 != 0 {
+				out.user_mask_feather = Some(self.advance_and_read_f64("user_mask_feather")?);
+			}
+			if params & 0b0000_0100 != 0 {
+				out.vector_mask_density =
+					Some(self.advance_and_read("vector_mask_density", 1)? as u8);
+			}
+			if params & 0b0000_1000 != 0 {
+				out.vector_mask_feather = Some(self.advance_and_read_f64("vector_mask_feather")?);
+			}
+			parameters = Some(out);
+		}
+
+		let mut real = None;
+		if length == 20 {
+			self.advance("padding", 2)?;
+		} else {
+			let flags = self.advance_and_read("real_flags", 1)? as u8;
+			let background = self.advance_and_read("real_user_mask_background", 1)? as u8;
+			let rect = self.advance_and_read_rect("real_rect")?;
+			real = Some(RealMask {
+				flags,
+				background,
+				rect,
+			});
+		}
+
+		Ok(LayerMask {
+			rect,
+			default_color,
+			flags,
+			parameters,
+			real,
+		})
+	}
+
+	fn get_layer(&mut self, index: u16) -> Result<Layer, PsdError> {
 		let len = u64::from(self.file_type.length());
-		self.start(&prefix);
+		self.start(format!("layer_{}", index));
 
-		self.start(&format!("{}/rect", prefix));
-		self.advance(&format!("{}/rect/top", prefix), 4);
-		self.advance(&format!("{}/rect/left", prefix), 4);
-		self.advance(&format!("{}/rect/bottom", prefix), 4);
-		self.advance(&format!("{}/rect/right", prefix), 4);
-		self.end(&format!("{}/rect", prefix));
+		let rect = self.advance_and_read_rect("rect")?;
+		self.check_limit(
+			"rect",
+			u64::from(rect.width()),
+			u64::from(self.limits.max_width),
+		)?;
+		self.check_limit(
+			"rect",
+			u64::from(rect.height()),
+			u64::from(self.limits.max_height),
+		)?;
 
-		self.start(&format!("{}/channel_info", prefix));
+		self.start("channel_info");
 
-		let number_of_channels =
-			self.advance_and_read(&format!("{}/channel_info:number", prefix), 2)?;
+		let number_of_channels = self.advance_and_read(":number", 2)?;
 
+		let mut channels = vec![];
 		{
 			for i in 0..number_of_channels {
-				self.start(&format!("{}/channel_info/channel_{}", prefix, i));
-				self.advance(&format!("{}/channel_info/channel_{}/id", prefix, i), 2);
-				self.advance(
-					&format!("{}/channel_info/channel_{}:length", prefix, i),
-					len,
-				);
-				self.end(&format!("{}/channel_info/channel_{}", prefix, i));
+				self.start_kind(format!("channel_{}", i), BlockKind::Channel(i as usize));
+				let id = self.advance_and_read("id", 2)?;
+				let length = self.advance_and_read(":length", len)?;
+				channels.push(Channel {
+					id: ChannelId::from(u_to_i16_be(id as u16)),
+					length,
+					data_start: 0,
+				});
+				self.end();
 			}
 		}
-		self.end(&format!("{}/channel_info", prefix));
+		self.end();
+
+		self.advance_and_check_multiple("blend_mode_signature", &[&BIM_SIGNATURE, &B64_SIGNATURE])?;
+		let mut blend_mode_key = [0; 4];
+		blend_mode_key.copy_from_slice(&self.advance_and_read_vec("blend_mode_key", 4)?);
+		let opacity = self.advance_and_read("opacity", 1)? as u8;
+		let clipping = self.advance_and_read("clipping", 1)? != 0;
+		let flags = self.advance_and_read("flags", 1)? as u8;
+		self.advance("filler", 1)?;
+
+		let extra_data_length = self.advance_and_read("extra_data_length", 4)?;
+
+		let extra_data_end = self.section_end("extra_data", extra_data_length)?;
+		self.enter_section("extra_data", extra_data_end)?;
+		let mut mask = None;
+		let mut blending_ranges = vec![];
+		let pascal_name;
+		let tagged_blocks;
+
+		self.start_transparent("extra_data");
+		{
+			let mask_data_length = self.advance_and_read("mask_data_length", 4)?;
+			let mask_end = self.section_end("mask_data", mask_data_length)?;
+			self.enter_section("mask_data", mask_end)?;
+			self.start("mask_data");
+			if mask_data_length > 0 {
+				mask = Some(self.get_mask_data(mask_data_length)?);
+				if self.pos < mask_end {
+					let size = mask_end - self.pos;
+					self.advance("padding", size)?;
+				}
+			}
+			self.end();
+			self.leave_section();
+
+			let blending_ranges_length = self.advance_and_read("blending_ranges_length", 4)?;
+			let ranges = self.advance_and_read_vec("blending_ranges", blending_ranges_length)?;
+			for range in ranges.chunks(8).filter(|x| x.len() == 8) {
+				let mut source = [0; 4];
+				let mut destination = [0; 4];
+				source.copy_from_slice(&range[0..4]);
+				destination.copy_from_slice(&range[4..8]);
+				blending_ranges.push(BlendingRange {
+					source,
+					destination,
+				});
+			}
 
-		self.advance_and_check_multiple(
-			&format!("{}/blend_mode_signature", prefix),
-			&[&BIM_SIGNATURE, &B64_SIGNATURE],
-		)?;
-		self.advance(&format!("{}/blend_mode_key", prefix), 4);
-		self.advance(&format!("{}/opacity", prefix), 1);
-		self.advance(&format!("{}/clipping", prefix), 1);
-		self.advance(&format!("{}/flags", prefix), 1);
-		self.advance(&format!("{}/filler", prefix), 1);
+			let name_length = self.advance_and_read("name_length", 1)?;
+			// name is padded to multiple of 4 along with its length byte, even if it's empty
+			let padded_name_length = Self::pad(name_length + 1, 4) - 1;
+			let name_data = self.advance_and_read_vec("name", padded_name_length)?;
+			pascal_name = decode_mac_roman(&name_data[..name_length as usize]);
 
-		let extra_data_length =
-			self.advance_and_read(&format!("{}/extra_data_length", prefix), 4)?;
+			self.start("additional_data");
+			tagged_blocks = self.get_tagged_blocks(extra_data_end, 2)?;
+			self.end();
+		}
+		self.end();
+		self.leave_section();
+		self.end();
+
+		let id = match tagged_blocks.iter().find(|x| x.key == "lyid") {
+			Some(block) if block.data_size >= 4 => {
+				let data = self.layer_data(index, block)?;
+				Some(Self::to_number(&data[..4]) as u32)
+			}
+			_ => None,
+		};
 
-		let extra_data_end = self.pos + extra_data_length;
+		// name which can't be decoded falls back to pascal name, so it doesn't fail the whole file
+		let name = match tagged_blocks.iter().find(|x| x.key == "luni") {
+			Some(block) => {
+				let data = self.layer_data(index, block)?;
+				ByteReader::new(&data)
+					.read_unicode_string()
+					.unwrap_or_else(|_| pascal_name.clone())
+			}
+			None => pascal_name.clone(),
+		};
 
-		self.start(&format!("{}/extra_data", prefix));
+		let section_divider = match tagged_blocks
+			.iter()
+			.find(|x| x.key == "lsct" || x.key == "lsdk")
 		{
-			let mask_data_length =
-				self.advance_and_read(&format!("{}/mask_data_length", prefix), 4)?;
-			self.start(&format!("{}/mask_data", prefix));
-			{
-				if mask_data_length > 0 {
-					self.start(&format!("{}/mask_data/rect", prefix));
-					self.advance(&format!("{}/mask_data/rect/top", prefix), 4);
-					self.advance(&format!("{}/mask_data/rect/left", prefix), 4);
-					self.advance(&format!("{}/mask_data/rect/bottom", prefix), 4);
-					self.advance(&format!("{}/mask_data/rect/right", prefix), 4);
-					self.end(&format!("{}/mask_data/rect", prefix));
-
-					self.advance(&format!("{}/mask_data/default_color", prefix), 1);
-
-					let mask_flags =
-						self.advance_and_read(&format!("{}/mask_data/flags", prefix), 1)?;
-
-					if mask_flags & 0b0001_0000 != 0 {
-						let params =
-							self.advance_and_read(&format!("{}/mask_data/parameters", prefix), 1)?;
-						if params & 0b1000_0000 != 0 {
-							self.advance(&format!("{}/mask_data/user_mask_density", prefix), 1);
-						}
-						if params & 0b0100_0000 != 0 {
-							self.advance(&format!("{}/mask_data/user_mask_feather", prefix), 2);
-						}
-						if params & 0b0010_0000 != 0 {
-							self.advance(&format!("{}/mask_data/vector_mask_density", prefix), 1);
-						}
-						if params & 0b0001_0000 != 0 {
-							self.advance(&format!("{}/mask_data/vector_mask_feather", prefix), 2);
-						}
-					}
+			Some(block) if block.data_size >= 4 => {
+				let value = Self::to_number(&self.layer_data(index, block)?[..4]);
+				// unknown type doesn't open or close a group, so it's left to the layer itself
+				Some(SectionDivider::from_u32(value as u32).unwrap_or(SectionDivider::Other))
+			}
+			_ => None,
+		};
 
-					if mask_data_length == 20 {
-						self.advance(&format!("{}/mask_data/padding", prefix), 2);
-					} else {
-						self.advance(&format!("{}/mask_data/real_flags", prefix), 1);
+		Ok(Layer {
+			rect,
+			channels,
+			blend_mode: BlendMode::from_key(blend_mode_key),
+			opacity,
+			clipping,
+			flags,
+			visible: flags & 0b0000_0010 == 0,
+			locked: flags & 0b0000_0001 != 0,
+			mask,
+			blending_ranges,
+			name,
+			pascal_name,
+			id,
+			section_divider,
+			tagged_blocks,
+		})
+	}
+
+	/// Gets data of `LAYER_DATA_KEYS` block of the layer `index` read along with its record
+	fn layer_data(&mut self, index: u16, block: &TaggedBlock) -> Result<Vec<u8>, PsdError> {
+		match self.layer_data.remove(&block.data_start) {
+			Some(data) => Ok(data),
+			None => {
+				let name = format!("layer_{}/additional_data/{}/data", index, block.key);
+				self.peek_vec(&name, block.data_start, block.data_size)
+			}
+		}
+	}
+
+	/// Indexes layer count, layer records and channel data between current position and `end`
+	/// as nested blocks of the block being read
+	fn get_layers_info(&mut self, end: u64) -> Result<(), PsdError> {
+		let start = self.pos;
+		self.enter_section("", end)?;
+		let layers_count = if self.pos == end {
+			self.advance("layer_count", 0)?;
+			0
+		} else {
+			self.advance_and_read("layer_count", 2)?
+		};
 
-						self.advance(
-							&format!("{}/mask_data/real_user_mask_background", prefix),
-							1,
-						);
+		// negative count means that merged image has transparency
+		let layers_count = u_to_i16_be(layers_count as u16).unsigned_abs();
+		self.check_limit(
+			"layer_count",
+			(self.layers.len() + layers_count as usize) as u64,
+			self.limits.max_layers as u64,
+		)?;
 
-						self.advance(&format!("{}/mask_data/real_rect", prefix), 16);
+		let mut layers = vec![];
+		let mut layer_ids = HashSet::new();
+		let first_layer = self.layers.len();
+		for layer_index in 0..layers_count {
+			// names of the record aren't known until its `lyid` is read
+			let hold = self.hold.is_none() && self.label_mode == LabelMode::Id;
+			if hold {
+				self.hold = Some(self.open.len());
+			}
+			let layer = self.get_layer(layer_index)?;
+			let id_name = match (self.label_mode, layer.id) {
+				(LabelMode::Id, Some(id)) if layer_ids.insert(id) => {
+					Some(format!("layer_id_{}", id))
+				}
+				_ => None,
+			};
+			let name = match self.last_block_mut() {
+				Some(block) => {
+					block.kind = BlockKind::Layer {
+						index: layer_index as usize,
+						id: layer.id,
+					};
+					if let Some(name) = id_name {
+						block.name = Cow::Owned(name);
 					}
+					block.name.clone()
 				}
+				None => Cow::Borrowed(""),
+			};
+			if hold {
+				self.hold = None;
+				self.emit_last();
 			}
-			self.end(&format!("{}/mask_data", prefix));
+			let channel_lengths: Vec<u64> = layer.channels.iter().map(|x| x.length).collect();
+			layers.push((name, channel_lengths));
+			self.layers.push(layer);
+		}
 
-			let blending_ranges_length =
-				self.advance_and_read(&format!("{}/blending_ranges_length", prefix), 4)?;
-			self.advance(
-				&format!("{}/blending_ranges", prefix),
-				blending_ranges_length,
-			);
+		self.start("channel_data");
+		{
+			for (i, (name, channel_lengths)) in layers.into_iter().enumerate() {
+				self.start_kind(
+					name,
+					BlockKind::LayerChannels {
+						index: i,
+						id: self.layers[first_layer + i].id,
+					},
+				);
+				for (j, len) in channel_lengths.into_iter().enumerate() {
+					if len < 2 {
+						return Err(PsdError::InvalidValue {
+							label: self.label_of(&format!("channel_{}", j)),
+							offset: self.pos,
+							value: len,
+						});
+					}
+					let data_start = self.pos;
+					self.start_kind(format!("channel_{}", j), BlockKind::Channel(j));
+					self.advance(":compression_method", 2)?;
+					self.advance(":data", len - 2)?;
+					self.end();
+					self.layers[first_layer + i].channels[j].data_start = data_start;
+				}
+				self.end();
+			}
+		}
+		self.end();
+
+		if self.pos < end {
+			let diff = end - self.pos;
+			self.advance("padding", diff)?;
+		} else if self.pos > end {
+			return Err(PsdError::Overflow {
+				label: self.label_of(""),
+				offset: start,
+				end: self.pos,
+				bound: end,
+			});
+		} else {
+			self.advance("padding", 0)?;
+		}
+		self.leave_section();
+
+		Ok(())
+	}
+
+	/// Checks that tagged block starts at `pos`, `name` is one of the blocks being read
+	fn is_tagged_block_at(&mut self, name: &str, pos: u64, end: u64) -> Result<bool, PsdError> {
+		if pos + 12 > end {
+			return Ok(false);
+		}
+		let signature = self.peek_vec(name, pos, 4)?;
+		Ok(signature == BIM_SIGNATURE || signature == B64_SIGNATURE)
+	}
+
+	/// Indexes tagged blocks between current position and `end` as `{key}` blocks nested
+	/// in the block being read, with `signature`, `key`, `length` and `data` children.
+	/// Block data is padded to `pad` bytes
+	fn get_tagged_blocks(&mut self, end: u64, pad: u64) -> Result<Vec<TaggedBlock>, PsdError> {
+		let mut key_counts: HashMap<String, usize> = HashMap::new();
+		let mut blocks = vec![];
 
-			let mut layer_name_length =
-				self.advance_and_read(&format!("{}/name_length", prefix), 1)?;
-			if layer_name_length > 1 {
-				layer_name_length = Self::pad(layer_name_length + 1, 4) - 1;
+		while self.pos < end {
+			let pos = self.pos;
+			if !self.is_tagged_block_at("", pos, end)? {
+				self.advance("padding", end - pos)?;
+				break;
+			}
+
+			let mut key = [0; 4];
+			key.copy_from_slice(&self.peek_vec("", pos + 4, 4)?);
+			let mut name: String = key
+				.iter()
+				.map(|c| {
+					if c.is_ascii_alphanumeric() {
+						*c as char
+					} else {
+						'_'
+					}
+				}).collect();
+			let count = {
+				let count = key_counts.entry(name.clone()).or_insert(0);
+				*count += 1;
+				*count - 1
+			};
+			if count > 0 {
+				name = format!("{}_{}", name, count);
 			}
-			self.advance(&format!("{}/name", prefix), layer_name_length);
 
-			self.start(&format!("{}/additional_data", prefix));
-			self.pos = extra_data_end;
-			self.end(&format!("{}/additional_data", prefix));
+			self.start_kind(name, BlockKind::TaggedBlock { key, index: count });
+			self.advance_and_check_multiple("signature", &[&BIM_SIGNATURE, &B64_SIGNATURE])?;
+			self.advance("key", 4)?;
+			let len = u64::from(self.file_type.tagged_block_length(&key));
+			let size = self.advance_and_read("length", len)?;
+
+			let has_layers = LAYERS_KEYS.iter().any(|x| x[..] == key[..]);
+			let unpadded = if has_layers {
+				self.section_end("data", size)?
+			} else {
+				self.check_size("data", size)?
+			};
+			if unpadded > end {
+				return Err(PsdError::Overflow {
+					label: self.label_of(""),
+					offset: pos,
+					end: unpadded,
+					bound: end,
+				});
+			}
+			// not every writer pads blocks, so fall back to unpadded end if next block starts there
+			let padded = min(self.pos.saturating_add(Self::pad(size, pad)), end);
+			let data_end = if padded == unpadded
+				|| self.is_tagged_block_at("", padded, end)?
+				|| !self.is_tagged_block_at("", unpadded, end)?
+			{
+				padded
+			} else {
+				unpadded
+			};
+
+			blocks.push(TaggedBlock {
+				key: String::from_utf8_lossy(&key).to_string(),
+				data_start: self.pos,
+				data_size: size,
+			});
+
+			if LAYER_DATA_KEYS.iter().any(|x| x[..] == key[..]) {
+				let pos = self.pos;
+				let data = self.read_at("data", pos, size)?;
+				self.layer_data.insert(pos, data);
+			}
+			if has_layers {
+				self.start("data");
+				self.get_layers_info(data_end)?;
+				self.end();
+			} else {
+				let size = data_end - self.pos;
+				self.advance("data", size)?;
+			}
+			self.end();
 		}
-		self.end(&format!("{}/extra_data", prefix));
-		self.end(prefix);
 
-		Ok(())
+		Ok(blocks)
 	}
 
-	fn get_layers_resources(&mut self) -> Result<(), String> {
+	fn get_layers_resources(&mut self) -> Result<(), PsdError> {
 		let len = u64::from(self.file_type.length());
 		let layers_length = self.advance_and_read("layers_resources_length", len)?;
-		let layers_end = self.pos + layers_length;
+		let layers_end = self.section_end("layers_resources", layers_length)?;
+		self.enter_section("layers_resources", layers_end)?;
 
 		self.start("layers_resources");
 		{
-			let layers_info_len = Self::pad(
-				self.advance_and_read("layers_resources/layers_info_length", len)?,
-				2,
+			let layers_info_len = self.advance_and_read("layers_info_length", len)?;
+			self.section_end("layers_info", layers_info_len)?;
+			let layers_info_end = min(
+				self.pos.saturating_add(Self::pad(layers_info_len, 2)),
+				layers_end,
 			);
 
-			let layers_info_end = self.pos + layers_info_len;
+			self.start("layers_info");
+			self.get_layers_info(layers_info_end)?;
+			self.end();
 
-			self.start("layers_resources/layers_info");
-			{
-				let layers_count = match layers_info_len {
-					0 => {
-						self.advance("layers_resources/layers_info/layer_count", 0);
-						0
-					}
-					_ => self.advance_and_read("layers_resources/layers_info/layer_count", 2)?,
-				};
+			let global_mask_len = self.advance_and_read("global_mask_length", 4)?;
+			self.advance("global_mask", global_mask_len)?;
 
-				let mut layers_count = u_to_i16_be(layers_count as u16);
-				if layers_count < 0 {
-					layers_count = -layers_count;
-				}
+			self.start("additional_layer_information");
+			self.get_tagged_blocks(layers_end, 4)?;
+			self.end();
+		}
+		self.end();
+		self.leave_section();
 
-				let mut layer_index = 0;
-				while layer_index < layers_count {
-					self.get_layer(&format!(
-						"layers_resources/layers_info/layer_{}",
-						layer_index,
-					))?;
-					layer_index += 1;
-				}
+		Ok(())
+	}
 
-				self.start("layers_resources/layers_info/channel_data");
-				{
-					for i in 0..layers_count {
-						self.start(&format!(
-							"layers_resources/layers_info/channel_data/layer_{}",
-							i
-						));
-						for j in 0.. {
-							let len_bound = match self.starts.get(&format!("layers_resources/layers_info/layer_{}/channel_info/channel_{}:length", i, j)) {
-								None => {
-									break;
-								}
-								Some(start) => {
-									let end = self.ends.get(
-										&format!("layers_resources/layers_info/layer_{}/channel_info/channel_{}:length", i, j)
-									).ok_or_else(||
-										format!("layers_resources/layers_info/layer_{}/channel_info/channel_{}:length end wasn't found", i, j)
-									)?;
-									let len = *end - *start;
-									(*start, len)
-								}
-							};
-							{
-								let init_pos = self.pos;
-								let _ = self.file.seek(SeekFrom::Start(len_bound.0));
-								let len = read_usize_be(&mut self.file, len_bound.1 as usize)
-									.map_err(|x| x.to_string())?;
-								self.file
-									.seek(SeekFrom::Start(init_pos))
-									.map_err(|x| x.to_string())?;
-								self.pos = init_pos;
-								self.start(&format!(
-									"layers_resources/layers_info/channel_data/layer_{}/channel_{}",
-									i, j
-								));
-								self.advance(&format!("layers_resources/layers_info/channel_data/layer_{}/channel_{}:compression_method", i, j), 2);
-								self.advance(&format!("layers_resources/layers_info/channel_data/layer_{}/channel_{}:data", i, j), (len - 2) as u64);
-								self.end(&format!(
-									"layers_resources/layers_info/channel_data/layer_{}/channel_{}",
-									i, j
-								));
-							}
-						}
-						self.end(&format!(
-							"layers_resources/layers_info/channel_data/layer_{}",
-							i
-						));
-					}
+	fn get_image_data(&mut self) -> Result<(), PsdError> {
+		self.start("image_data");
+		self.advance("compression_method", 2)?;
+		self.start("data");
+		if self.forward_only {
+			self.file.seek(SeekFrom::Start(self.pos))?;
+			self.pos += copy(&mut Read::by_ref(&mut self.file), &mut sink())?;
+		} else {
+			self.pos = self.file.seek(SeekFrom::End(0))?;
+		}
+		self.end();
+		self.end();
+
+		Ok(())
+	}
+
+	/// Reads sections following the header
+	fn get_sections(&mut self) -> Result<(), PsdError> {
+		self.get_color_mode()?;
+		self.get_image_resource_section()?;
+		self.get_layers_resources()?;
+		self.get_image_data()
+	}
+
+	/// Drops blocks and layers which weren't read completely after reading stopped with `error`
+	fn recover(&mut self, error: PsdError) {
+		// complete blocks of the held layer record are passed with their positional labels
+		if let (Some(depth), Some(listener)) = (self.hold.take(), self.listener.as_mut()) {
+			let mut label = String::new();
+			for (i, block) in self.open.iter().enumerate() {
+				let base = label.len();
+				block.push_label(&mut label);
+				if block.transparent {
+					label.truncate(base);
 				}
-				self.end("layers_resources/layers_info/channel_data");
-
-				if self.pos < layers_info_end {
-					let diff = layers_info_end - self.pos;
-					self.advance("layers_resources/layers_info/padding", diff);
-				} else if self.pos > layers_info_end {
-					return Err("layers_resources/layers_info bound overflow".to_string());
-				} else {
-					self.advance("layers_resources/layers_info/padding", 0);
+				if i >= depth {
+					for child in &block.children {
+						child.walk_nested_first(&label, i + 1, listener);
+					}
 				}
-
-				if self.pos != layers_info_end {
-					return Err("layers/resources position mismatch".to_string());
+			}
+		}
+		// unfinished blocks are dropped and their complete nested blocks take their place
+		let partial = self.open_labels();
+		while let Some(block) = self.open.pop() {
+			// nested blocks keep their labels
+			let mut parents = block.unfinished_parents.clone();
+			if !block.transparent {
+				if !self.open.is_empty() || !parents.is_empty() {
+					parents.push(block.separator);
 				}
+				parents.push_str(&block.name);
+			}
+			let children = block.children.into_iter().map(|mut x| {
+				x.unfinished_parents.insert_str(0, &parents);
+				x
+			});
+			match self.open.last_mut() {
+				Some(parent) => parent.children.extend(children),
+				None => self.blocks.extend(children),
 			}
-			self.end("layers_resources/layers_info");
+		}
+		self.label.clear();
+		self.label_bases.clear();
+		self.bounds.clear();
+		self.layers
+			.retain(|x| x.channels.iter().all(|x| x.data_start != 0));
+		self.recovery = Some(Recovery { partial, error });
+	}
 
-			let global_mask_len =
-				self.advance_and_read("layers_resources/global_mask_length", 4)?;
-			self.advance("layers_resources/global_mask", global_mask_len);
+	/// Reads blocks of the file into `BlockTree`
+	fn read(&mut self) -> Result<(), PsdError> {
+		if self.tree.is_some() {
+			return Ok(());
+		};
 
-			self.start("layers_resources/additional_layer_information");
-			self.pos = layers_end;
-			self.end("layers_resources/additional_layer_information");
+		let pos = self.file.stream_position()?;
+
+		self.reset()?;
+		self.get_header()?;
+		if let Err(err) = self.get_sections() {
+			if !self.lenient {
+				return Err(err);
+			}
+			self.recover(err);
+		}
+
+		if let (Some(block), Some(label)) = (self.open.last(), self.open_labels().pop()) {
+			return Err(PsdError::Malformed {
+				label,
+				offset: block.start,
+				message: "block wasn't closed".to_string(),
+			});
 		}
-		self.end("layers_resources");
 
+		self.tree = Some(BlockTree {
+			blocks: mem::take(&mut self.blocks),
+		});
+		self.indexes = None;
+		if !self.forward_only {
+			self.file.seek(SeekFrom::Start(pos))?;
+		}
 		Ok(())
 	}
 
-	fn get_image_data(&mut self) -> Result<(), String> {
-		self.start("image_data");
-		self.advance("image_data/compression_method", 2);
-		self.start("image_data/data");
-		let res = self
-			.file
-			.seek(SeekFrom::End(0))
-			.map_err(|err| err.to_string())?;
-		self.pos = res;
-		self.end("image_data/data");
-		self.end("image_data");
-
-		Ok(())
+	/// Gets `Indexes`, which are derived from `BlockTree` on the first call
+	pub fn get_indexes(&mut self) -> Result<&Indexes, PsdError> {
+		self.read()?;
+		if self.indexes.is_none() {
+			self.indexes = self.tree.as_ref().map(|x| x.to_indexes());
+		}
+		self.indexes
+			.as_ref()
+			.ok_or_else(|| PsdError::NotFound("indexes".to_string()))
 	}
 
-	/// Gets `Indexes`
-	pub fn get_indexes(&mut self) -> Result<&Indexes, String> {
-		if self.indexes.is_some() {
-			return Ok(self.indexes.as_ref().unwrap());
-		};
+	/// Gets `BlockTree`, hierarchy of the blocks of `Indexes`
+	pub fn get_block_tree(&mut self) -> Result<&BlockTree, PsdError> {
+		self.read()?;
+		self.tree
+			.as_ref()
+			.ok_or_else(|| PsdError::NotFound("block tree".to_string()))
+	}
 
-		let pos = self
-			.file
-			.seek(SeekFrom::Current(0))
-			.map_err(|x| x.to_string())?;
+	/// Gets decoded `header` section.
+	/// Reads only the header if indexes weren't requested yet
+	pub fn get_psd_header(&mut self) -> Result<&PSDHeader, PsdError> {
+		if self.tree.is_none() && self.header.is_none() {
+			let pos = self.file.stream_position()?;
+			self.reset()?;
+			self.get_header()?;
+			if !self.forward_only {
+				self.file.seek(SeekFrom::Start(pos))?;
+			}
+		}
+		self.header
+			.as_ref()
+			.ok_or_else(|| PsdError::NotFound("header".to_string()))
+	}
 
-		self.get_header()?;
-		self.get_color_mode()?;
-		self.get_image_resource_section()?;
-		self.get_layers_resources()?;
-		self.get_image_data()?;
-
-		let mut indexes: Indexes = Indexes::new();
-
-		for key in &self.order {
-			let s = self
-				.starts
-				.get(key)
-				.ok_or_else(|| format!("failed to get key: {}", key))?;
-			let e = self
-				.ends
-				.get(key)
-				.ok_or_else(|| format!("failed to get key: {}", key))?;
-			if e < s {
-				return Err(format!("end {} is shorter that start {} at {}", e, s, key));
-			};
-			indexes.insert(key.clone(), *s, e - s);
+	/// Gets image resource blocks in file order.
+	/// Reads file only up to the end of `image_resources` if indexes weren't requested yet
+	pub fn get_image_resources(&mut self) -> Result<&[ImageResource], PsdError> {
+		if self.tree.is_none() && !self.image_resources_read {
+			let pos = self.file.stream_position()?;
+			self.reset()?;
+			self.get_header()?;
+			self.get_color_mode()?;
+			self.get_image_resource_section()?;
+			self.image_resources_read = true;
+			if !self.forward_only {
+				self.file.seek(SeekFrom::Start(pos))?;
+			}
 		}
+		Ok(&self.image_resources)
+	}
 
-		self.starts.clear();
-		self.ends.clear();
+	/// Gets decoded layer records in file order, including ones nested in `Lr16`, `Lr32` and `Layr`
+	pub fn get_layers(&mut self) -> Result<&[Layer], PsdError> {
+		self.read()?;
+		Ok(&self.layers)
+	}
 
-		self.indexes = Some(indexes);
-		self.file
-			.seek(SeekFrom::Start(pos))
-			.map_err(|x| x.to_string())?;
-		Ok(self.indexes.as_ref().unwrap())
+	/// Takes description of the blocks skipped in lenient mode,
+	/// `None` if file was read completely
+	pub fn take_recovery(&mut self) -> Result<Option<Recovery>, PsdError> {
+		self.read()?;
+		Ok(self.recovery.take())
 	}
 }
 
 #[cfg(test)]
 mod psd_reader_tests {
-	use super::PSDReader;
-	use std::fs::{read_dir, File};
+	use super::{LabelMode, Limits, PSDReader};
+	use error::PsdError;
+	use forward_reader::ForwardReader;
+	use psd_file::{PSDFile, PSDFileType};
+	use psd_header::ColorMode;
+	use std::cell::Cell;
+	use std::fs::{read, read_dir, File};
+	use std::io::{Cursor, Read, Result as IOResult};
+	use std::time::{Duration, Instant};
 
 	#[test]
 	fn get_indexes_test() {
@@ -565,6 +1344,430 @@ mod psd_reader_tests {
 		assert!(!r.has("layers_resources/layers_info/layer_2"));
 	}
 
+	#[test]
+	fn get_psd_header_test() {
+		let mut file = File::open("./test_data/a_a.psd").unwrap();
+		let mut reader = PSDReader::new(&mut file);
+		let header = reader.get_psd_header().unwrap();
+		assert_eq!(header.version, PSDFileType::PSD);
+		assert_eq!(header.number_of_channels, 4);
+		assert_eq!(header.width, 20);
+		assert_eq!(header.height, 27);
+		assert_eq!(header.depth, 8);
+		assert_eq!(header.color_mode, ColorMode::Rgb);
+
+		let mut file = File::open("./test_data/indexed-color.psd").unwrap();
+		let mut reader = PSDReader::new(&mut file);
+		let header = reader.get_psd_header().unwrap();
+		assert_eq!(header.color_mode, ColorMode::Indexed);
+		assert_eq!(header.number_of_channels, 1);
+
+		let mut file = File::open("./test_data/large.psb").unwrap();
+		let mut reader = PSDReader::new(&mut file);
+		assert_eq!(reader.get_psd_header().unwrap().version, PSDFileType::PSB);
+	}
+
+	#[test]
+	fn label_mode_test() {
+		let mut file_a = File::open("./test_data/a_a.psd").unwrap();
+		let mut reader_a = PSDReader::new(&mut file_a);
+		reader_a.set_label_mode(LabelMode::Id);
+		let a = reader_a.get_indexes().unwrap();
+
+		let mut file_b = File::open("./test_data/a_b.psd").unwrap();
+		let mut reader_b = PSDReader::new(&mut file_b);
+		reader_b.set_label_mode(LabelMode::Id);
+		let b = reader_b.get_indexes().unwrap();
+
+		// a_b has resource 1010 inserted before 1037 and a new layer with id 51
+		assert!(!a.has("image_resources/image_resource_id_1010"));
+		assert!(b.has("image_resources/image_resource_id_1010"));
+		assert_eq!(
+			a.get("image_resources/image_resource_id_1037/data")
+				.unwrap()
+				.1,
+			b.get("image_resources/image_resource_id_1037/data")
+				.unwrap()
+				.1
+		);
+		assert!(!a.has("image_resources/image_resource_11"));
+
+		for id in &[14, 50] {
+			let label = format!("layers_resources/layers_info/layer_id_{}/name", id);
+			assert_eq!(a.get(&label).unwrap().1, b.get(&label).unwrap().1);
+			assert!(a.has(&format!(
+				"layers_resources/layers_info/channel_data/layer_id_{}/channel_0:data",
+				id
+			)));
+		}
+		assert!(b.has("layers_resources/layers_info/layer_id_51"));
+		assert!(!b.has("layers_resources/layers_info/layer_0"));
+	}
+
+	#[test]
+	fn additional_layer_information_test() {
+		let mut file = File::open("./test_data/16bit.psd").unwrap();
+		let mut reader = PSDReader::new(&mut file);
+		let r = reader.get_indexes().unwrap();
+		let prefix = "layers_resources/additional_layer_information";
+		assert_eq!(r.get(&format!("{}/Lr16", prefix)).unwrap(), (74, 364));
+		assert_eq!(
+			r.get(&format!("{}/Lr16/data/layer_count", prefix)).unwrap(),
+			(86, 2)
+		);
+		assert!(r.has(&format!("{}/Lr16/data/layer_1/name", prefix)));
+		assert_eq!(
+			r.get(&format!(
+				"{}/Lr16/data/channel_data/layer_1/channel_3:data",
+				prefix
+			)).unwrap(),
+			(426, 11)
+		);
+		assert_eq!(r.get(&format!("{}/Mt16", prefix)).unwrap(), (438, 12));
+		assert_eq!(r.get(&format!("{}/FMsk/data", prefix)).unwrap(), (462, 12));
+
+		let mut file = File::open("./test_data/large.psb").unwrap();
+		let mut reader = PSDReader::new(&mut file);
+		let r = reader.get_indexes().unwrap();
+		assert_eq!(r.get(&format!("{}/FMsk/length", prefix)).unwrap().1, 8);
+		assert_eq!(r.get(&format!("{}/FMsk/data", prefix)).unwrap().1, 12);
+	}
+
+	#[test]
+	fn additional_data_test() {
+		let mut file = File::open("./test_data/a_a.psd").unwrap();
+		let mut reader = PSDReader::new(&mut file);
+		let r = reader.get_indexes().unwrap();
+		let prefix = "layers_resources/layers_info/layer_0/additional_data";
+		assert_eq!(r.get(prefix).unwrap(), (62134, 236));
+		assert_eq!(r.get(&format!("{}/luni", prefix)).unwrap(), (62134, 24));
+		assert_eq!(r.get(&format!("{}/luni/key", prefix)).unwrap(), (62138, 4));
+		assert_eq!(
+			r.get(&format!("{}/lyid/length", prefix)).unwrap(),
+			(62166, 4)
+		);
+		assert_eq!(r.get(&format!("{}/lyid/data", prefix)).unwrap(), (62170, 4));
+		assert_eq!(r.get(&format!("{}/fxrp", prefix)).unwrap(), (62342, 28));
+		assert!(!r.has(&format!("{}/padding", prefix)));
+	}
+
+	#[test]
+	fn get_image_resources_test() {
+		// a_a.psd truncated at the end of image_resources
+		let data = read("./test_data/a_a.psd").unwrap();
+		let mut file = Cursor::new(data[..61988].to_vec());
+		let mut reader = PSDReader::new(&mut file);
+		assert_eq!(reader.get_image_resources().unwrap().len(), 31);
+		assert!(reader.get_indexes().is_err());
+	}
+
+	#[test]
+	fn hostile_lengths_test() {
+		let data = read("./test_data/a_a.psd").unwrap();
+		let patched = |pos: usize, value: &[u8]| {
+			let mut data = data.clone();
+			data[pos..pos + value.len()].copy_from_slice(value);
+			Cursor::new(data)
+		};
+
+		let mut file = patched(30, &[0xFF, 0xFF, 0xFF, 0xFF]);
+		match PSDReader::new(&mut file).get_indexes() {
+			Err(PsdError::Truncated { label, offset, .. }) => {
+				assert_eq!((label.as_str(), offset), ("image_resources", 34))
+			}
+			x => panic!("expected Truncated, got {:?}", x.map(|_| ())),
+		}
+
+		let mut file = patched(61988, &[0x7F, 0xFF, 0xFF, 0xFF]);
+		match PSDReader::new(&mut file).get_indexes() {
+			Err(PsdError::Truncated { label, .. }) => assert_eq!(label, "layers_resources"),
+			x => panic!("expected Truncated, got {:?}", x.map(|_| ())),
+		}
+
+		// length of the first channel of the first layer
+		let mut file = patched(62018, &[0, 0, 0, 0]);
+		match PSDReader::new(&mut file).get_indexes() {
+			Err(PsdError::InvalidValue { label, value, .. }) => {
+				assert_eq!(
+					label,
+					"layers_resources/layers_info/channel_data/layer_0/channel_0"
+				);
+				assert_eq!(value, 0);
+			}
+			x => panic!("expected InvalidValue, got {:?}", x.map(|_| ())),
+		}
+
+		// extra data length of the first layer
+		let mut file = patched(62058, &[0x00, 0x00, 0x08, 0x00]);
+		match PSDReader::new(&mut file).get_indexes() {
+			Err(PsdError::Overflow { label, .. }) => {
+				assert_eq!(label, "layers_resources/layers_info/layer_0/extra_data")
+			}
+			x => panic!("expected Overflow, got {:?}", x.map(|_| ())),
+		}
+
+		// truncated and corrupted files must fail without panic
+		for cut in (0..data.len()).step_by(499) {
+			let mut file = PSDFile::new(Cursor::new(data[..cut].to_vec()));
+			let _ = file.get_lines();
+		}
+		// header and layers, image resources are read only on request
+		for pos in (0..34).chain(61988..64070).step_by(5) {
+			let mut data = data.clone();
+			data[pos] ^= 0xFF;
+			let mut file = PSDFile::new(Cursor::new(data));
+			let _ = file.get_lines();
+			let _ = file.get_layer_tree();
+			let _ = file.composite_image();
+			for layer in 0..file.get_layers().map(|x| x.len()).unwrap_or(0) {
+				let _ = file.layer_image(layer);
+			}
+		}
+	}
+
+	#[test]
+	fn lenient_test() {
+		let data = read("./test_data/a_a.psd").unwrap();
+		// inside channel data of the second layer
+		let mut file = Cursor::new(data[..64002].to_vec());
+		assert!(PSDReader::new(&mut file).get_indexes().is_err());
+
+		let mut reader = PSDReader::new(&mut file);
+		reader.set_lenient(true);
+		{
+			let r = reader.get_indexes().unwrap();
+			assert!(r.has("image_resources"));
+			assert!(r.has("layers_resources/layers_info/layer_1"));
+			assert!(r.has("layers_resources/layers_info/channel_data/layer_0"));
+			assert!(!r.has("layers_resources/layers_info/channel_data/layer_1"));
+			assert!(!r.has("layers_resources"));
+			assert!(!r.has("image_data"));
+		}
+		assert_eq!(reader.get_layers().unwrap().len(), 1);
+		let recovery = reader.take_recovery().unwrap().unwrap();
+		assert_eq!(recovery.partial[0], "layers_resources");
+		assert!(recovery
+			.partial
+			.contains(&"layers_resources/layers_info/channel_data/layer_1".to_string()));
+		match recovery.error {
+			PsdError::Truncated { offset, actual, .. } => assert_eq!((offset, actual), (64002, 0)),
+			x => panic!("expected Truncated, got {:?}", x),
+		}
+
+		let mut file = File::open("./test_data/a_a.psd").unwrap();
+		let mut reader = PSDReader::new(&mut file);
+		reader.set_lenient(true);
+		assert!(reader.take_recovery().unwrap().is_none());
+
+		// header is required
+		let mut file = Cursor::new(data[..20].to_vec());
+		let mut reader = PSDReader::new(&mut file);
+		reader.set_lenient(true);
+		assert!(reader.get_indexes().is_err());
+	}
+
+	#[test]
+	fn forward_only_test() {
+		let files = read_dir("./test_data")
+			.unwrap()
+			.map(|x| x.unwrap().path())
+			.filter(|x| x.extension().is_some_and(|x| x == "psd" || x == "psb"));
+		for path in files {
+			for mode in &[LabelMode::Position, LabelMode::Id] {
+				let mut file = File::open(&path).unwrap();
+				let mut reader = PSDReader::new(&mut file);
+				reader.set_label_mode(*mode);
+				let expected: Vec<(String, u64, u64)> =
+					reader.get_indexes().unwrap().clone().into_iter().collect();
+				let mut read_order = vec![];
+				for block in &reader.get_block_tree().unwrap().blocks {
+					block.walk_nested_first("", 0, &mut |block, label, _| {
+						read_order.push((label.to_string(), block.start, block.size))
+					});
+				}
+
+				let mut blocks = vec![];
+				let mut stream = ForwardReader::new(File::open(&path).unwrap());
+				let indexes = {
+					let mut reader = PSDReader::new(&mut stream);
+					reader.set_label_mode(*mode);
+					reader.set_forward_only(true);
+					reader.set_listener(|block, label, _| {
+						blocks.push((label.to_string(), block.start, block.size))
+					});
+					reader.get_indexes().unwrap().clone()
+				};
+				let indexes: Vec<(String, u64, u64)> = indexes.into_iter().collect();
+				assert_eq!(indexes, expected, "{:?}", path);
+				assert_eq!(blocks, read_order, "{:?}", path);
+			}
+		}
+	}
+
+	/// Counts bytes read from the inner reader
+	struct CountingReader<'a> {
+		inner: Cursor<Vec<u8>>,
+		read: &'a Cell<u64>,
+	}
+
+	impl<'a> Read for CountingReader<'a> {
+		fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+			let size = self.inner.read(buf)?;
+			self.read.set(self.read.get() + size as u64);
+			Ok(size)
+		}
+	}
+
+	/// Builds file of `count` copies of the first layer of a_a.psd
+	fn many_layers(count: usize) -> Vec<u8> {
+		let data = read("./test_data/a_a.psd").unwrap();
+		let indexes = PSDReader::new(&mut Cursor::new(&data))
+			.get_indexes()
+			.unwrap()
+			.clone();
+		let range = |label: &str| {
+			let (start, size) = indexes.get(label).unwrap();
+			start as usize..(start + size) as usize
+		};
+		let info = "layers_resources/layers_info";
+		let layer_count = range(&format!("{}/layer_count", info));
+		let record = range(&format!("{}/layer_0", info));
+		let channels = range(&format!("{}/channel_data/layer_0", info));
+		let channel_data = range(&format!("{}/channel_data", info));
+
+		let mut out = data[..layer_count.start].to_vec();
+		let sign = if data[layer_count.start] & 0x80 != 0 {
+			-1
+		} else {
+			1
+		};
+		out.extend_from_slice(&(sign * count as i16).to_be_bytes());
+		for _ in 0..count {
+			out.extend_from_slice(&data[record.clone()]);
+		}
+		for _ in 0..count {
+			out.extend_from_slice(&data[channels.clone()]);
+		}
+		out.extend_from_slice(&data[channel_data.end..]);
+
+		let delta = (out.len() - data.len()) as u32;
+		for label in &[
+			"layers_resources_length",
+			"layers_resources/layers_info_length",
+		] {
+			let at = range(label).start;
+			let mut length = [0; 4];
+			length.copy_from_slice(&out[at..at + 4]);
+			let length = u32::from_be_bytes(length) + delta;
+			out[at..at + 4].copy_from_slice(&length.to_be_bytes());
+		}
+		out
+	}
+
+	#[test]
+	fn listener_streaming_test() {
+		let data = many_layers(1000);
+		let file_size = data.len() as u64;
+		for mode in &[LabelMode::Position, LabelMode::Id] {
+			let read = Cell::new(0);
+			let mut stream = ForwardReader::new(CountingReader {
+				inner: Cursor::new(data.clone()),
+				read: &read,
+			});
+			let mut consumed = vec![];
+			{
+				let mut reader = PSDReader::new(&mut stream);
+				reader.set_label_mode(*mode);
+				reader.set_forward_only(true);
+				reader.set_listener(|_, label, depth| {
+					consumed.push((label.to_string(), depth, read.get()))
+				});
+				reader.get_indexes().unwrap();
+			}
+			// layer record is passed before image data is read, not after
+			// the whole `layers_resources` section
+			let layer = consumed
+				.iter()
+				.find(|x| x.1 == 2 && x.0.starts_with("layers_resources/layers_info/layer_"))
+				.unwrap();
+			assert!(layer.2 < 1 << 20, "{:?}", layer);
+			let last = consumed.last().unwrap();
+			assert_eq!((last.0.as_str(), last.1), ("image_data", 0));
+			assert_eq!(last.2, file_size);
+		}
+	}
+
+	/// Measures reading of a file with many layers, run with
+	/// `cargo test --release read_many_layers_bench -- --ignored --nocapture`
+	#[test]
+	#[ignore]
+	fn read_many_layers_bench() {
+		let count = 10_000;
+		let runs = 5;
+		let data = many_layers(count);
+		let mut tree_time = Duration::default();
+		let mut indexes_time = Duration::default();
+		for _ in 0..runs {
+			let mut cursor = Cursor::new(&data);
+			let mut reader = PSDReader::new(&mut cursor);
+			let time = Instant::now();
+			reader.get_block_tree().unwrap();
+			tree_time += time.elapsed();
+			let time = Instant::now();
+			reader.get_indexes().unwrap();
+			indexes_time += time.elapsed();
+		}
+		println!(
+			"{} layers: block tree {:?}, indexes from tree {:?}",
+			count,
+			tree_time / runs,
+			indexes_time / runs
+		);
+	}
+
+	#[test]
+	fn limits_test() {
+		let limits = Limits {
+			max_width: 10,
+			..Limits::default()
+		};
+		let mut file = File::open("./test_data/a_a.psd").unwrap();
+		let mut reader = PSDReader::new(&mut file);
+		reader.set_limits(limits);
+		match reader.get_indexes() {
+			Err(PsdError::LimitExceeded {
+				label,
+				offset,
+				value,
+				limit,
+			}) => {
+				assert_eq!(label, "header/width");
+				assert_eq!((offset, value, limit), (18, 20, 10));
+			}
+			x => panic!("expected LimitExceeded, got {:?}", x.map(|_| ())),
+		}
+
+		let mut file = PSDFile::open("./test_data/a_a.psd").unwrap();
+		file.set_limits(Limits {
+			max_layers: 1,
+			..Limits::default()
+		});
+		match file.get_indexes() {
+			Err(PsdError::LimitExceeded { label, value, .. }) => {
+				assert_eq!(label, "layers_resources/layers_info/layer_count");
+				assert_eq!(value, 2);
+			}
+			x => panic!("expected LimitExceeded, got {:?}", x.map(|_| ())),
+		}
+		file.set_limits(Limits {
+			max_resources: 30,
+			..Limits::default()
+		});
+		assert!(file.get_image_resources().is_err());
+		file.set_limits(Limits::default());
+		assert_eq!(file.get_image_resources().unwrap().len(), 31);
+	}
+
 	#[test]
 	fn parse_test() {
 		let files = read_dir("./test_data")
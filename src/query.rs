@@ -0,0 +1,220 @@
+//! Contains `Pattern` struct, glob-style pattern matching `Indexes` labels
+
+/// Part of the pattern
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+	Char(char),
+	/// `?`: any character except separators
+	One,
+	/// `*`: any characters except separators
+	Any,
+	/// `**`: any characters including separators
+	AnyPath,
+	/// `{n}`: index of the repeated block, either position `3` or id `id_1005` or `id_1005_1`
+	Index,
+}
+
+/// Glob-style pattern matching the whole label. Separators are `/` and `:`.
+/// `*` matches part of a segment, e.g. `layer_*/name`,
+/// `**` matches any number of segments, e.g. `**/channel_*:data`,
+/// `?` matches single character of a segment and `{n}` matches index of the repeated block:
+/// `layer_{n}` matches `layer_3` and `layer_id_12` but not `layer_count`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+	tokens: Vec<Token>,
+}
+
+fn is_separator(c: char) -> bool {
+	c == '/' || c == ':'
+}
+
+/// Gets ends of the index at the start of `label`: digits, `id_` and digits,
+/// and `id_` digits `_` digits for repeated ids
+fn index_ends(label: &[char]) -> Vec<usize> {
+	let digits = |from: usize| {
+		label[from..]
+			.iter()
+			.take_while(|x| x.is_ascii_digit())
+			.count()
+	};
+	let mut ends = vec![];
+	let count = digits(0);
+	if count > 0 {
+		ends.push(count);
+	}
+	if label.starts_with(&['i', 'd', '_']) {
+		let count = digits(3);
+		if count > 0 {
+			let end = 3 + count;
+			ends.push(end);
+			if label.get(end) == Some(&'_') {
+				let count = digits(end + 1);
+				if count > 0 {
+					ends.push(end + 1 + count);
+				}
+			}
+		}
+	}
+	ends
+}
+
+/// Matches tokens against label. Positions which didn't match are remembered,
+/// so wildcards don't backtrack over the same parts of the label again
+struct Matcher<'a> {
+	tokens: &'a [Token],
+	label: &'a [char],
+	/// whether tokens from `t` don't match label from `l`, at `t * (label.len() + 1) + l`
+	failed: Vec<bool>,
+}
+
+impl<'a> Matcher<'a> {
+	fn new(tokens: &'a [Token], label: &'a [char]) -> Self {
+		Self {
+			tokens,
+			label,
+			failed: vec![false; (tokens.len() + 1) * (label.len() + 1)],
+		}
+	}
+
+	/// Checks that tokens starting from `t` match label starting from `l`
+	fn matches(&mut self, t: usize, l: usize) -> bool {
+		let key = t * (self.label.len() + 1) + l;
+		if self.failed[key] {
+			return false;
+		}
+		let matched = self.matches_token(t, l);
+		if !matched {
+			self.failed[key] = true;
+		}
+		matched
+	}
+
+	fn matches_token(&mut self, t: usize, l: usize) -> bool {
+		let label = &self.label[l..];
+		let token = match self.tokens.get(t) {
+			Some(x) => x,
+			None => return label.is_empty(),
+		};
+		match token {
+			Token::Char(c) => label.first() == Some(c) && self.matches(t + 1, l + 1),
+			Token::One => {
+				label.first().is_some_and(|x| !is_separator(*x)) && self.matches(t + 1, l + 1)
+			}
+			Token::Any => {
+				let max = label.iter().take_while(|x| !is_separator(**x)).count();
+				(0..=max).any(|i| self.matches(t + 1, l + i))
+			}
+			Token::AnyPath => {
+				// `**/` matches no segments as well
+				if self.tokens.get(t + 1) == Some(&Token::Char('/')) && self.matches(t + 2, l) {
+					return true;
+				}
+				(l..=self.label.len()).any(|i| self.matches(t + 1, i))
+			}
+			Token::Index => index_ends(label)
+				.into_iter()
+				.any(|i| self.matches(t + 1, l + i)),
+		}
+	}
+}
+
+impl Pattern {
+	pub fn new(pattern: &str) -> Self {
+		let mut tokens = vec![];
+		let mut rest = pattern;
+		while let Some(c) = rest.chars().next() {
+			let (token, size) = if rest.starts_with("**") {
+				(Token::AnyPath, 2)
+			} else if rest.starts_with("{n}") {
+				(Token::Index, 3)
+			} else {
+				match c {
+					'*' => (Token::Any, 1),
+					'?' => (Token::One, 1),
+					c => (Token::Char(c), c.len_utf8()),
+				}
+			};
+			tokens.push(token);
+			rest = &rest[size..];
+		}
+		Self { tokens }
+	}
+
+	/// Checks that pattern has no wildcards, so it matches only the label equal to it
+	pub fn is_literal(&self) -> bool {
+		self.tokens.iter().all(|x| matches!(x, Token::Char(_)))
+	}
+
+	pub fn matches(&self, label: &str) -> bool {
+		let label: Vec<char> = label.chars().collect();
+		Matcher::new(&self.tokens, &label).matches(0, 0)
+	}
+}
+
+#[cfg(test)]
+mod query_tests {
+	use super::*;
+	use psd_file::PSDFile;
+
+	#[test]
+	fn matches_test() {
+		let pattern = Pattern::new("layers_resources/layers_info/layer_*/name");
+		assert!(pattern.matches("layers_resources/layers_info/layer_0/name"));
+		assert!(pattern.matches("layers_resources/layers_info/layer_id_5/name"));
+		assert!(!pattern.matches("layers_resources/layers_info/layer_0/name_length"));
+		assert!(!pattern.matches("layers_resources/layers_info/layer_0/extra_data/name"));
+
+		let pattern = Pattern::new("**/channel_*:data");
+		assert!(pattern.matches("layers_resources/layers_info/channel_data/layer_1/channel_2:data"));
+		assert!(pattern.matches("channel_0:data"));
+		assert!(!pattern.matches("layers_resources/layers_info/channel_data/layer_1/channel_2"));
+
+		let pattern = Pattern::new("image_resources/image_resource_{n}");
+		assert!(pattern.matches("image_resources/image_resource_12"));
+		assert!(pattern.matches("image_resources/image_resource_id_1005"));
+		assert!(pattern.matches("image_resources/image_resource_id_1005_1"));
+		assert!(!pattern.matches("image_resources/image_resource_id_"));
+		assert!(!pattern.matches("image_resources/image_resource_12/id"));
+		assert!(!Pattern::new("layer_{n}").matches("layer_count"));
+
+		assert!(Pattern::new("header/?ersion").matches("header/version"));
+		assert!(!Pattern::new("header?version").matches("header/version"));
+		assert!(Pattern::new("header/version").is_literal());
+		assert!(!Pattern::new("header/*").is_literal());
+
+		// would backtrack exponentially without remembering failed positions
+		let pattern = Pattern::new(&format!("{}b", "**a".repeat(16)));
+		assert!(!pattern.matches(&"a".repeat(200)));
+		assert!(pattern.matches(&format!("{}ab", "a/".repeat(100))));
+	}
+
+	#[test]
+	fn query_test() {
+		let mut file = PSDFile::open("./test_data/a_a.psd").unwrap();
+		let names = file
+			.query("layers_resources/layers_info/layer_*/name")
+			.unwrap();
+		assert_eq!(
+			names,
+			vec![
+				(
+					"layers_resources/layers_info/layer_0/name".to_string(),
+					62131,
+					3
+				),
+				(
+					"layers_resources/layers_info/layer_1/name".to_string(),
+					62477,
+					7
+				),
+			]
+		);
+
+		let data = file.query("**/layer_1/channel_*:data").unwrap();
+		assert_eq!(data.len(), 4);
+		assert!(data.iter().all(|x| x
+			.0
+			.starts_with("layers_resources/layers_info/channel_data/layer_1/channel_")));
+		assert!(file.query("nothing/*").unwrap().is_empty());
+	}
+}
@@ -0,0 +1,607 @@
+//! Contains `TextLayer` struct, decoded `TySh` tagged block of text layers
+
+use byte_reader::ByteReader;
+use descriptor::{read_versioned, Descriptor, Value};
+use engine_data::{decode_engine_data, encode_engine_data, EngineValue};
+use error::PsdError;
+use layer::Rect;
+
+/// Key of the tagged block of text layers
+pub const TYPE_TOOL_KEY: &str = "TySh";
+
+const TYPE_TOOL_VERSION: u16 = 1;
+const TEXT_VERSION: u16 = 50;
+const WARP_VERSION: u16 = 1;
+
+/// Style of the range of text. Offsets are in UTF-16 code units, as Photoshop counts them
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextStyle {
+	pub start: usize,
+	pub length: usize,
+	/// PostScript name of the font, e.g. `ArialMT`
+	pub font: Option<String>,
+	/// Size in points before `transform` is applied
+	pub size: Option<f64>,
+	/// Fill color: alpha, red, green and blue from 0 to 1
+	pub color: Option<[f64; 4]>,
+}
+
+/// Range of the paragraph in UTF-16 code units, including its trailing `\r`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Paragraph {
+	pub start: usize,
+	pub length: usize,
+}
+
+/// Text layer: content and styles of its text
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextLayer {
+	/// Transform matrix: xx, xy, yx, yy, tx, ty
+	pub transform: [f64; 6],
+	/// Content of the layer, lines are separated by `\r`
+	pub text: String,
+	/// Fonts used by the layer
+	pub fonts: Vec<String>,
+	/// Style runs covering `text`
+	pub styles: Vec<TextStyle>,
+	pub paragraphs: Vec<Paragraph>,
+	pub bounds: Rect,
+	/// Text descriptor, holding `Txt ` and `EngineData` among others
+	pub descriptor: Descriptor,
+	/// Warp descriptor
+	pub warp: Descriptor,
+	/// Decoded `EngineData` of the text descriptor
+	pub engine_data: Option<EngineValue>,
+}
+
+impl TextLayer {
+	/// Gets text of the range of UTF-16 code units, e.g. of `TextStyle`
+	pub fn slice(&self, start: usize, length: usize) -> String {
+		let units: Vec<u16> = self.text.encode_utf16().skip(start).take(length).collect();
+		String::from_utf16_lossy(&units)
+	}
+
+	/// Replaces content of the layer, `\n` and `\r\n` line breaks are stored as `\r`.
+	/// Updates `Txt ` of the descriptor and text, style and paragraph runs of EngineData:
+	/// paragraphs take properties of the old paragraphs in order and extra ones
+	/// of the last old paragraph. Style runs keep their styles and are scaled
+	/// to the length of the new text, so text of the same length keeps its styles as is.
+	/// Otherwise styles are lost where the new text doesn't follow the old one,
+	/// e.g. bold word of the old text makes bold the part of the new text at the same
+	/// relative position, and runs which become empty are dropped. If old style runs
+	/// have no lengths, the whole text takes style of the first run.
+	/// Photoshop re-renders the text on open, so bounds are left as is
+	pub fn set_text(&mut self, text: &str) {
+		let text = text.replace("\r\n", "\r").replace('\n', "\r");
+		self.descriptor
+			.set("Txt ", Value::Text(text.clone().into()));
+		self.text = text;
+
+		let mut engine = match self.engine_data.take() {
+			Some(engine) => engine,
+			None => return,
+		};
+		// EngineData text always ends with paragraph break
+		let engine_text = format!("{}\r", self.text);
+		if let Some(value) = engine.get_path_mut(&["EngineDict", "Editor", "Text"]) {
+			*value = EngineValue::Text(engine_text.clone());
+		}
+
+		let lengths: Vec<usize> = engine_text
+			.split_terminator('\r')
+			.map(|x| x.encode_utf16().count() + 1)
+			.collect();
+		if let Some(run) = engine.get_path_mut(&["EngineDict", "ParagraphRun"]) {
+			set_runs(run, &lengths);
+		}
+		let total = lengths.iter().sum();
+		if let Some(run) = engine.get_path_mut(&["EngineDict", "StyleRun"]) {
+			scale_runs(run, total);
+		}
+
+		self.descriptor
+			.set("EngineData", Value::RawData(encode_engine_data(&engine)));
+		self.styles = decode_styles(&engine, &self.fonts);
+		self.paragraphs = decode_paragraphs(&engine);
+		self.engine_data = Some(engine);
+	}
+}
+
+/// Sets `RunLengthArray` of the run to `lengths`, `RunArray` items are taken from
+/// the old items in order, repeating the last one
+fn set_runs(run: &mut EngineValue, lengths: &[usize]) {
+	let items: Vec<EngineValue> = match run.get("RunArray").and_then(|x| x.as_array()) {
+		Some(items) if !items.is_empty() => (0..lengths.len())
+			.map(|i| items[i.min(items.len() - 1)].clone())
+			.collect(),
+		_ => return,
+	};
+	write_runs(run, items, lengths);
+}
+
+/// Scales `RunLengthArray` of the run, so runs cover `total` code units with their boundaries
+/// at the same relative position. Runs which become empty are dropped
+fn scale_runs(run: &mut EngineValue, total: usize) {
+	let old: Vec<(usize, usize, EngineValue)> = runs(Some(&*run))
+		.into_iter()
+		.map(|(start, length, item)| (start, length, item.clone()))
+		.collect();
+	let old_total: usize = old.iter().map(|x| x.1).sum();
+	if old_total == 0 {
+		set_runs(run, &[total]);
+		return;
+	}
+	let mut items = vec![];
+	let mut lengths = vec![];
+	let mut start = 0;
+	for (old_start, old_length, item) in old {
+		// rounded, the end of the last run is `total`
+		let end = ((old_start + old_length) * total + old_total / 2) / old_total;
+		if end > start {
+			items.push(item);
+			lengths.push(end - start);
+			start = end;
+		}
+	}
+	write_runs(run, items, &lengths);
+}
+
+fn write_runs(run: &mut EngineValue, items: Vec<EngineValue>, lengths: &[usize]) {
+	if let Some(value) = run.get_mut("RunArray") {
+		*value = EngineValue::Array(items);
+	}
+	if let Some(value) = run.get_mut("RunLengthArray") {
+		*value = EngineValue::Array(
+			lengths
+				.iter()
+				.map(|x| EngineValue::Integer(*x as i64))
+				.collect(),
+		);
+	}
+}
+
+/// Items of `RunArray` with their ranges from `RunLengthArray`
+fn runs(run: Option<&EngineValue>) -> Vec<(usize, usize, &EngineValue)> {
+	let items = run
+		.and_then(|x| x.get("RunArray"))
+		.and_then(|x| x.as_array());
+	let lengths = run
+		.and_then(|x| x.get("RunLengthArray"))
+		.and_then(|x| x.as_array());
+	let (items, lengths) = match (items, lengths) {
+		(Some(items), Some(lengths)) => (items, lengths),
+		_ => return vec![],
+	};
+	let mut start = 0;
+	let mut out = vec![];
+	for (item, length) in items.iter().zip(lengths) {
+		let length = length.as_i64().unwrap_or(0).max(0) as usize;
+		out.push((start, length, item));
+		start += length;
+	}
+	out
+}
+
+fn decode_styles(engine: &EngineValue, fonts: &[String]) -> Vec<TextStyle> {
+	let resources = engine.get("ResourceDict");
+	let default = resources.and_then(|x| {
+		let index = x.get("TheNormalStyleSheet")?.as_i64()?;
+		x.get("StyleSheetSet")?
+			.as_array()?
+			.get(index as usize)?
+			.get("StyleSheetData")
+	});
+	runs(engine.get_path(&["EngineDict", "StyleRun"]))
+		.into_iter()
+		.map(|(start, length, run)| {
+			let data = run.get_path(&["StyleSheet", "StyleSheetData"]);
+			// run keeps only values which differ from the default style sheet
+			let field = |key: &str| {
+				data.and_then(|x| x.get(key))
+					.or_else(|| default.and_then(|x| x.get(key)))
+			};
+			let color = field("FillColor")
+				.and_then(|x| x.get("Values"))
+				.and_then(|x| x.as_array())
+				.filter(|x| x.len() == 4)
+				.and_then(|x| {
+					let mut color = [0.0; 4];
+					for (i, value) in x.iter().enumerate() {
+						color[i] = value.as_f64()?;
+					}
+					Some(color)
+				});
+			TextStyle {
+				start,
+				length,
+				font: field("Font")
+					.and_then(|x| x.as_i64())
+					.and_then(|x| fonts.get(x as usize))
+					.cloned(),
+				size: field("FontSize").and_then(|x| x.as_f64()),
+				color,
+			}
+		})
+		.collect()
+}
+
+fn decode_paragraphs(engine: &EngineValue) -> Vec<Paragraph> {
+	runs(engine.get_path(&["EngineDict", "ParagraphRun"]))
+		.into_iter()
+		.map(|(start, length, _)| Paragraph { start, length })
+		.collect()
+}
+
+fn read_version(r: &mut ByteReader, expected: u16) -> Result<(), PsdError> {
+	let offset = r.position() as u64;
+	let version = r.read_u16()?;
+	if version != expected {
+		return Err(PsdError::InvalidValue {
+			label: String::new(),
+			offset,
+			value: u64::from(version),
+		});
+	}
+	Ok(())
+}
+
+/// Decodes data of `TySh` tagged block
+pub fn decode_text_layer(data: &[u8]) -> Result<TextLayer, PsdError> {
+	let mut r = ByteReader::new(data);
+	read_version(&mut r, TYPE_TOOL_VERSION)?;
+	let mut transform = [0.0; 6];
+	for x in transform.iter_mut() {
+		*x = r.read_f64()?;
+	}
+	read_version(&mut r, TEXT_VERSION)?;
+	let descriptor = read_versioned(&mut r)?;
+	read_version(&mut r, WARP_VERSION)?;
+	let warp = read_versioned(&mut r)?;
+	let left = r.read_i32()?;
+	let top = r.read_i32()?;
+	let right = r.read_i32()?;
+	let bottom = r.read_i32()?;
+
+	let engine_data = match descriptor.get("EngineData").and_then(|x| x.as_data()) {
+		Some(data) => Some(decode_engine_data(data)?),
+		None => None,
+	};
+	let fonts: Vec<String> = engine_data
+		.as_ref()
+		.and_then(|x| x.get_path(&["ResourceDict", "FontSet"]))
+		.and_then(|x| x.as_array())
+		.unwrap_or(&[])
+		.iter()
+		.map(|x| {
+			x.get("Name")
+				.and_then(|x| x.as_str())
+				.unwrap_or("")
+				.to_string()
+		})
+		.collect();
+	let text = match descriptor.get("Txt ").and_then(|x| x.as_str()) {
+		Some(text) => text.to_string(),
+		None => engine_data
+			.as_ref()
+			.and_then(|x| x.get_path(&["EngineDict", "Editor", "Text"]))
+			.and_then(|x| x.as_str())
+			.unwrap_or("")
+			.to_string(),
+	};
+	let (styles, paragraphs) = match engine_data {
+		Some(ref engine) => (decode_styles(engine, &fonts), decode_paragraphs(engine)),
+		None => (vec![], vec![]),
+	};
+
+	Ok(TextLayer {
+		transform,
+		text,
+		fonts,
+		styles,
+		paragraphs,
+		bounds: Rect {
+			top,
+			left,
+			bottom,
+			right,
+		},
+		descriptor,
+		warp,
+		engine_data,
+	})
+}
+
+/// Encodes `TySh` tagged block data
+pub fn encode_text_layer(layer: &TextLayer) -> Vec<u8> {
+	let mut out = TYPE_TOOL_VERSION.to_be_bytes().to_vec();
+	for x in &layer.transform {
+		out.extend_from_slice(&x.to_bits().to_be_bytes());
+	}
+	out.extend_from_slice(&TEXT_VERSION.to_be_bytes());
+	out.extend(layer.descriptor.encode_versioned());
+	out.extend_from_slice(&WARP_VERSION.to_be_bytes());
+	out.extend(layer.warp.encode_versioned());
+	let bounds = &layer.bounds;
+	for x in &[bounds.left, bounds.top, bounds.right, bounds.bottom] {
+		out.extend_from_slice(&x.to_be_bytes());
+	}
+	out
+}
+
+#[cfg(test)]
+mod text_tests {
+	use super::*;
+	use psd_file::PSDFile;
+	use std::io::Cursor;
+	use validate::validate;
+
+	/// Encodes string as EngineData UTF-16 string, escaping parentheses and backslashes
+	fn engine_string(value: &str) -> Vec<u8> {
+		let mut out = b"(\xFE\xFF".to_vec();
+		for unit in value.encode_utf16() {
+			for byte in &unit.to_be_bytes() {
+				if b"()\\".contains(byte) {
+					out.push(b'\\');
+				}
+				out.push(*byte);
+			}
+		}
+		out.push(b')');
+		out
+	}
+
+	/// Builds `TySh` block with two style runs and two paragraphs of "Hello\rWorld"
+	fn type_tool_block() -> Vec<u8> {
+		let mut engine = b"\n\n<<\n\t/EngineDict\n\t<<\n\t\t/Editor\n\t\t<<\n\t\t\t/Text ".to_vec();
+		engine.extend(engine_string("Hello\rWorld\r"));
+		engine.extend_from_slice(
+			b"\n\t\t>>\n\t\t/ParagraphRun\n\t\t<<\n\t\t\t/RunArray [ << /ParagraphSheet << /DefaultStyleSheet 0 >> >> << /ParagraphSheet << /DefaultStyleSheet 0 >> >> ]\n\t\t\t/RunLengthArray [ 6 6 ]\n\t\t>>\n\t\t/StyleRun\n\t\t<<\n\t\t\t/RunArray [ << /StyleSheet << /StyleSheetData << /Font 1 /FontSize 24.0 >> >> >> << /StyleSheet << /StyleSheetData << /FillColor << /Type 1 /Values [ 1.0 1.0 0.0 .5 ] >> >> >> >> ]\n\t\t\t/RunLengthArray [ 6 6 ]\n\t\t>>\n\t>>\n\t/ResourceDict\n\t<<\n\t\t/FontSet [ << /Name ",
+		);
+		engine.extend(engine_string("AdobeInvisFont"));
+		engine.extend_from_slice(b" >> << /Name ");
+		engine.extend(engine_string("ArialMT"));
+		engine.extend_from_slice(
+			b" >> ]\n\t\t/StyleSheetSet [ << /Name (Normal RGB) /StyleSheetData << /Font 0 /FontSize 12 /FillColor << /Type 1 /Values [ 1.0 0.0 0.0 0.0 ] >> >> >> ]\n\t\t/TheNormalStyleSheet 0\n\t>>\n>>",
+		);
+
+		let mut descriptor = Descriptor::new("TxLr");
+		descriptor.set("Txt ", Value::Text("Hello\rWorld".into()));
+		descriptor.set("EngineData", Value::RawData(engine));
+		let warp = Descriptor::new("warp");
+
+		let mut data = vec![];
+		data.extend_from_slice(&1u16.to_be_bytes());
+		for x in &[1.0f64, 0.0, 0.0, 1.0, 10.0, 20.0] {
+			data.extend_from_slice(&x.to_bits().to_be_bytes());
+		}
+		data.extend_from_slice(&50u16.to_be_bytes());
+		data.extend(descriptor.encode_versioned());
+		data.extend_from_slice(&1u16.to_be_bytes());
+		data.extend(warp.encode_versioned());
+		for x in &[0i32, -18, 120, 8] {
+			data.extend_from_slice(&x.to_be_bytes());
+		}
+		data
+	}
+
+	#[test]
+	fn decode_text_layer_test() {
+		let layer = decode_text_layer(&type_tool_block()).unwrap();
+		assert_eq!(layer.text, "Hello\rWorld");
+		assert_eq!(layer.transform, [1.0, 0.0, 0.0, 1.0, 10.0, 20.0]);
+		assert_eq!(
+			layer.bounds,
+			Rect {
+				top: -18,
+				left: 0,
+				bottom: 8,
+				right: 120
+			}
+		);
+		assert_eq!(layer.fonts, vec!["AdobeInvisFont", "ArialMT"]);
+		assert_eq!(
+			layer.styles,
+			vec![
+				TextStyle {
+					start: 0,
+					length: 6,
+					font: Some("ArialMT".to_string()),
+					size: Some(24.0),
+					color: Some([1.0, 0.0, 0.0, 0.0]),
+				},
+				TextStyle {
+					start: 6,
+					length: 6,
+					font: Some("AdobeInvisFont".to_string()),
+					size: Some(12.0),
+					color: Some([1.0, 1.0, 0.0, 0.5]),
+				},
+			]
+		);
+		assert_eq!(
+			layer.paragraphs,
+			vec![
+				Paragraph {
+					start: 0,
+					length: 6
+				},
+				Paragraph {
+					start: 6,
+					length: 6
+				},
+			]
+		);
+		assert_eq!(layer.slice(6, 6), "World");
+		assert_eq!(layer.warp.class.class_id, ::descriptor::Id::new("warp"));
+
+		let mut data = type_tool_block();
+		data[1] = 2;
+		match decode_text_layer(&data) {
+			Err(PsdError::InvalidValue { offset, value, .. }) => {
+				assert_eq!((offset, value), (0, 2))
+			}
+			x => panic!("expected InvalidValue, got {:?}", x.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn set_text_test() {
+		let data = type_tool_block();
+		let mut layer = decode_text_layer(&data).unwrap();
+		assert_eq!(encode_text_layer(&layer), data);
+
+		layer.set_text("Hallo\r\nschöne\nWelt");
+		assert_eq!(layer.text, "Hallo\rschöne\rWelt");
+		let layer = decode_text_layer(&encode_text_layer(&layer)).unwrap();
+		assert_eq!(layer.text, "Hallo\rschöne\rWelt");
+		assert_eq!(
+			layer
+				.engine_data
+				.as_ref()
+				.and_then(|x| x.get_path(&["EngineDict", "Editor", "Text"]))
+				.and_then(|x| x.as_str()),
+			Some("Hallo\rschöne\rWelt\r")
+		);
+		// runs of 6 and 6 code units are scaled to 18 code units
+		let styles: Vec<(usize, usize, Option<&str>)> = layer
+			.styles
+			.iter()
+			.map(|x| (x.start, x.length, x.font.as_deref()))
+			.collect();
+		assert_eq!(
+			styles,
+			vec![(0, 9, Some("ArialMT")), (9, 9, Some("AdobeInvisFont"))]
+		);
+		assert_eq!(layer.styles[1].color, Some([1.0, 1.0, 0.0, 0.5]));
+		let paragraphs: Vec<(usize, usize)> = layer
+			.paragraphs
+			.iter()
+			.map(|x| (x.start, x.length))
+			.collect();
+		assert_eq!(paragraphs, vec![(0, 6), (6, 7), (13, 5)]);
+		assert_eq!(layer.slice(6, 6), "schöne");
+
+		// text of the same length keeps its runs
+		let mut layer = decode_text_layer(&data).unwrap();
+		let styles = layer.styles.clone();
+		layer.set_text("Howdy\rthere");
+		assert_eq!(layer.styles, styles);
+
+		// runs of 6 and 12 code units, the first one becomes empty
+		let mut file = PSDFile::open("./test_data/text.psd").unwrap();
+		let mut layer = file.get_layer_text(1).unwrap().unwrap();
+		layer.set_text("");
+		assert_eq!(layer.styles.len(), 1);
+		assert_eq!(layer.styles[0].length, 1);
+		assert_eq!(layer.styles[0].font.as_deref(), Some("MyriadPro-Regular"));
+	}
+
+	#[test]
+	fn write_text_layers_test() {
+		let mut file = PSDFile::open("./test_data/a_a.psd").unwrap();
+		assert!(file.write_text_layers(&[], &mut vec![]).is_ok());
+		let text = decode_text_layer(&type_tool_block()).unwrap();
+		match file.write_text_layers(&[(0, text)], &mut vec![]) {
+			Err(PsdError::NotFound(_)) => (),
+			x => panic!("expected NotFound, got {:?}", x),
+		}
+
+		// make first layer a text layer
+		let mut data = vec![];
+		file.write_tagged_blocks(&[(0, TYPE_TOOL_KEY, type_tool_block())], &mut data)
+			.unwrap();
+		let mut text_file = PSDFile::new(Cursor::new(data));
+		let mut text = text_file.get_layer_text(0).unwrap().unwrap();
+		assert_eq!(text.text, "Hello\rWorld");
+		assert_eq!(
+			text_file.get_layers().unwrap()[0].name,
+			file.get_layers().unwrap()[0].name
+		);
+
+		text.set_text("Hallo Welt");
+		let mut data = vec![];
+		text_file
+			.write_text_layers(&[(0, text)], &mut data)
+			.unwrap();
+		let mut translated = PSDFile::new(Cursor::new(data));
+		assert!(validate(&mut translated).is_empty());
+		assert_eq!(
+			translated.get_layer_text(0).unwrap().unwrap().text,
+			"Hallo Welt"
+		);
+		assert_eq!(translated.get_layer_text(1).unwrap(), None);
+		let layers = file.get_layers().unwrap().to_vec();
+		assert_eq!(translated.get_layers().unwrap().len(), layers.len());
+		for (index, layer) in layers.iter().enumerate() {
+			for channel in 0..layer.channels.len() {
+				assert_eq!(
+					translated.get_layer_channel(index, channel).unwrap(),
+					file.get_layer_channel(index, channel).unwrap()
+				);
+			}
+		}
+		assert_eq!(
+			translated.composite_image().unwrap(),
+			file.composite_image().unwrap()
+		);
+	}
+
+	#[test]
+	fn get_layer_text_test() {
+		let mut file = PSDFile::open("./test_data/a_a.psd").unwrap();
+		assert_eq!(file.get_layer_text(0).unwrap(), None);
+		match file.get_layer_text(5) {
+			Err(PsdError::NotFound(_)) => (),
+			x => panic!("expected NotFound, got {:?}", x.map(|_| ())),
+		}
+
+		// second layer of text.psd is a text layer with two paragraphs in two styles
+		let mut file = PSDFile::open("./test_data/text.psd").unwrap();
+		assert_eq!(file.get_layer_text(0).unwrap(), None);
+		let text = file.get_layer_text(1).unwrap().unwrap();
+		assert_eq!(text.text, "Be art\rText layer");
+		assert_eq!(text.transform, [1.0, 0.0, 0.0, 1.0, 2.0, 12.0]);
+		assert_eq!(
+			text.fonts,
+			vec!["AdobeInvisFont", "ArialMT", "MyriadPro-Regular"]
+		);
+		let styles: Vec<(usize, usize, Option<&str>, Option<f64>)> = text
+			.styles
+			.iter()
+			.map(|x| (x.start, x.length, x.font.as_deref(), x.size))
+			.collect();
+		assert_eq!(
+			styles,
+			vec![
+				(0, 6, Some("ArialMT"), Some(36.0)),
+				(6, 12, Some("MyriadPro-Regular"), Some(18.0)),
+			]
+		);
+		assert_eq!(text.styles[0].color, Some([1.0, 0.8, 0.1, 0.1]));
+		// color of the normal style sheet
+		assert_eq!(text.styles[1].color, Some([1.0, 0.0, 0.0, 0.0]));
+		assert_eq!(
+			text.paragraphs,
+			vec![
+				Paragraph {
+					start: 0,
+					length: 7
+				},
+				Paragraph {
+					start: 7,
+					length: 11
+				},
+			]
+		);
+		assert!(text.descriptor.get("boundingBox").is_some());
+		assert_eq!(
+			text.bounds,
+			Rect {
+				top: -33,
+				left: 0,
+				bottom: 30,
+				right: 162
+			}
+		);
+	}
+}
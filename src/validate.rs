@@ -0,0 +1,592 @@
+//! Contains `validate` function which reports structural problems of psd file
+
+use compression::{decode_channel, decode_rle, ChannelSize, Compression};
+use error::PsdError;
+use image::color_channels;
+use psd_file::{PSDFile, PSDFileType};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+	/// Data is inconsistent but can be read, e.g. non-zero reserved bytes
+	Warning,
+	/// Data can't be read or decoded
+	Error,
+}
+
+/// Problem found in file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Issue {
+	pub severity: Severity,
+	/// `Indexes` label of the block, empty if problem isn't tied to a block
+	pub label: String,
+	/// Absolute position in file
+	pub offset: u64,
+	pub message: String,
+}
+
+impl Issue {
+	fn new<S: Into<String>>(severity: Severity, label: &str, offset: u64, message: S) -> Self {
+		Self {
+			severity,
+			label: label.to_string(),
+			offset,
+			message: message.into(),
+		}
+	}
+
+	/// Converts error which stopped reading into issue, keeping its location
+	fn from_error(err: &PsdError) -> Self {
+		let (label, offset) = err.location().unwrap_or(("", 0));
+		let message = match err {
+			PsdError::Truncated {
+				expected, actual, ..
+			} => format!(
+				"unexpected end of data: wanted {} bytes, {} left",
+				expected, actual
+			),
+			PsdError::Mismatch { actual, .. } => {
+				format!("check failed: found {:?}", String::from_utf8_lossy(actual))
+			}
+			PsdError::InvalidValue { value, .. } => format!("invalid value {}", value),
+			PsdError::Overflow { end, bound, .. } => format!(
+				"block ends at {}, past the end of its section at {}",
+				end, bound
+			),
+			PsdError::LimitExceeded { value, limit, .. } => {
+				format!("value {} exceeds limit {}", value, limit)
+			}
+			PsdError::Malformed { message, .. } => message.clone(),
+			err => err.to_string(),
+		};
+		Self::new(Severity::Error, label, offset, message)
+	}
+}
+
+impl fmt::Display for Issue {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let severity = match self.severity {
+			Severity::Warning => "warning",
+			Severity::Error => "error",
+		};
+		if self.label.is_empty() {
+			write!(f, "{}: {}", severity, self.message)
+		} else {
+			write!(
+				f,
+				"{}: \"{}\" (offset {}): {}",
+				severity, self.label, self.offset, self.message
+			)
+		}
+	}
+}
+
+/// Walks the file and reports every inconsistency found instead of stopping at the first one.
+/// Damaged structure is read up to the first error, which is reported along with issues
+/// of the blocks before it. Fails with a single issue only if header can't be read
+pub fn validate<T: Read + Seek>(file: &mut PSDFile<T>) -> Vec<Issue> {
+	let mut issues = vec![];
+	if let Err(err) = Validator::new(file, &mut issues).run() {
+		issues.push(Issue::from_error(&err));
+	}
+	issues
+}
+
+struct Validator<'a, T: 'a + Read + Seek> {
+	file: &'a mut PSDFile<T>,
+	issues: &'a mut Vec<Issue>,
+	all: Vec<(String, u64, u64)>,
+	file_type: PSDFileType,
+}
+
+impl<'a, T: 'a + Read + Seek> Validator<'a, T> {
+	fn new(file: &'a mut PSDFile<T>, issues: &'a mut Vec<Issue>) -> Self {
+		Self {
+			file,
+			issues,
+			all: vec![],
+			file_type: PSDFileType::PSD,
+		}
+	}
+
+	fn warn<S: Into<String>>(&mut self, label: &str, offset: u64, message: S) {
+		self.issues
+			.push(Issue::new(Severity::Warning, label, offset, message));
+	}
+
+	fn error<S: Into<String>>(&mut self, label: &str, offset: u64, message: S) {
+		self.issues
+			.push(Issue::new(Severity::Error, label, offset, message));
+	}
+
+	fn read_number(&mut self, start: u64, size: u64) -> Result<u64, PsdError> {
+		let data = self.file.read_range(start, size)?;
+		Ok(data.iter().fold(0u64, |c, x| c << 8 | u64::from(*x)))
+	}
+
+	/// Gets blocks which labels end with `suffix`
+	fn find(&self, suffix: &str) -> Vec<(String, u64, u64)> {
+		self.all
+			.iter()
+			.filter(|x| x.0.ends_with(suffix))
+			.cloned()
+			.collect()
+	}
+
+	fn run(&mut self) -> Result<(), PsdError> {
+		let lenient = self.file.is_lenient();
+		self.file.set_lenient(true);
+		let res = self.run_lenient();
+		self.file.set_lenient(lenient);
+		res
+	}
+
+	/// Runs checks over the blocks read before the reading error, if any
+	fn run_lenient(&mut self) -> Result<(), PsdError> {
+		self.all = self.file.get_indexes()?.clone().into_iter().collect();
+		self.file_type = self.file.get_header()?.version;
+		let (error, overflow) = match self.file.get_recovery()? {
+			Some(recovery) => {
+				let overflow = match recovery.error {
+					PsdError::Overflow {
+						ref label,
+						end,
+						bound,
+						..
+					} if label.starts_with("layers_resources/layers_info/") => Some((end, bound)),
+					_ => None,
+				};
+				(Some(Issue::from_error(&recovery.error)), overflow)
+			}
+			None => (None, None),
+		};
+
+		self.check_reserved()?;
+		self.check_padding()?;
+		let overrun = self.check_layers_info_length(overflow)?;
+		if !overrun {
+			self.issues.extend(error);
+		}
+		self.check_layer_count()?;
+		self.check_channels()?;
+		self.check_image_data()?;
+		Ok(())
+	}
+
+	fn check_reserved(&mut self) -> Result<(), PsdError> {
+		let mut blocks = self.find("header/reserved");
+		blocks.extend(self.find("/filler"));
+		for (label, start, size) in blocks {
+			if self.file.read_range(start, size)?.iter().any(|x| *x != 0) {
+				self.warn(&label, start, "reserved bytes aren't zero");
+			}
+		}
+		Ok(())
+	}
+
+	fn check_padding(&mut self) -> Result<(), PsdError> {
+		for (label, start, size) in self.find("/padding") {
+			if self.file.read_range(start, size)?.iter().any(|x| *x != 0) {
+				self.warn(&label, start, "padding isn't zero");
+			}
+		}
+
+		let resources = self.file.get_image_resources()?.to_vec();
+		for (label, start, size) in self.find("/data") {
+			if !label.starts_with("image_resources/") {
+				continue;
+			}
+			let resource = resources.iter().find(|x| x.data_start == start);
+			if let Some(resource) = resource {
+				if resource.data_size % 2 != 0 && size == resource.data_size {
+					self.warn(
+						&label,
+						start,
+						format!("odd data length {} isn't padded", resource.data_size),
+					);
+				}
+			}
+		}
+
+		for (label, start, size) in self.find("/length") {
+			let parent = &label[..label.len() - "/length".len()];
+			let pad = if parent.starts_with("layers_resources/additional_layer_information/") {
+				4
+			} else {
+				2
+			};
+			let data_label = format!("{}/data", parent);
+			let data = self.all.iter().find(|x| x.0 == data_label).cloned();
+			if let Some((_, data_start, data_size)) = data {
+				let length = self.read_number(start, size)?;
+				if length % pad != 0 && data_size == length {
+					self.warn(
+						&data_label,
+						data_start,
+						format!("data length {} isn't padded to {} bytes", length, pad),
+					);
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Compares declared length of layers info with the end of its channel data.
+	/// `overflow` is the end and the bound of the block which stopped reading of layers info,
+	/// returns whether it was reported as overrun of layers info
+	fn check_layers_info_length(&mut self, overflow: Option<(u64, u64)>) -> Result<bool, PsdError> {
+		let (start, size) = match self
+			.file
+			.get_indexes()?
+			.get("layers_resources/layers_info_length")
+		{
+			Some(x) => x,
+			None => return Ok(false),
+		};
+		let length = self.read_number(start, size)?;
+		let declared_end = start + size + length + length % 2;
+		let overrun = match overflow {
+			Some((end, bound)) if bound == declared_end => {
+				self.error(
+					"layers_resources/layers_info_length",
+					start,
+					format!(
+						"layers info doesn't fit its length {}: data reaches {}, past declared end at {}",
+						length, end, declared_end
+					),
+				);
+				true
+			}
+			_ => false,
+		};
+		if length % 2 != 0 {
+			self.warn(
+				"layers_resources/layers_info_length",
+				start,
+				format!("layers info length {} is odd", length),
+			);
+		}
+
+		for (label, start, size) in self.find("/padding") {
+			let parent = &label[..label.len() - "/padding".len()];
+			if !self
+				.all
+				.iter()
+				.any(|x| x.0 == format!("{}/layer_count", parent))
+			{
+				continue;
+			}
+			if size > 3 {
+				self.warn(
+					&label,
+					start,
+					format!(
+						"layers info ends at {}, but its length declares end at {}",
+						start,
+						start + size
+					),
+				);
+			}
+		}
+		Ok(overrun)
+	}
+
+	/// Checks that negative layer count, which marks transparency of merged image,
+	/// has an alpha channel to refer to
+	fn check_layer_count(&mut self) -> Result<(), PsdError> {
+		let header = self.file.get_header()?.clone();
+		// channels of unknown mode aren't known
+		let color = match color_channels(header.color_mode) {
+			Ok(color) => color,
+			Err(_) => return Ok(()),
+		};
+		for (label, start, size) in self.find("/layer_count") {
+			if size != 2 {
+				continue;
+			}
+			let count = self.read_number(start, size)? as u16 as i16;
+			if count < 0 && usize::from(header.number_of_channels) <= color {
+				self.warn(
+					&label,
+					start,
+					format!(
+						"negative layer count {} implies merged alpha, but image has only {} channels",
+						count, header.number_of_channels
+					),
+				);
+			}
+		}
+		Ok(())
+	}
+
+	fn check_channels(&mut self) -> Result<(), PsdError> {
+		let depth = self.file.get_header()?.depth;
+		let labels: HashMap<u64, String> = self
+			.all
+			.iter()
+			.filter(|x| {
+				x.0.contains("/channel_data/")
+					&& x.0.rsplit('/').next().unwrap().starts_with("channel_")
+			})
+			.filter(|x| !x.0.contains(':'))
+			.map(|x| (x.1, x.0.clone()))
+			.collect();
+
+		let layers = self.file.get_layers()?.to_vec();
+		for (i, layer) in layers.iter().enumerate() {
+			for (j, channel) in layer.channels.iter().enumerate() {
+				let label = labels
+					.get(&channel.data_start)
+					.cloned()
+					.unwrap_or_else(|| format!("layer_{}/channel_{}", i, j));
+				let rect = layer.channel_rect(channel.id);
+				let size = ChannelSize {
+					width: rect.width(),
+					rows: rect.height(),
+					depth,
+				};
+				let data = self.file.read_range(channel.data_start, channel.length)?;
+				self.check_channel(&label, channel.data_start, &data, size);
+			}
+		}
+		Ok(())
+	}
+
+	fn check_image_data(&mut self) -> Result<(), PsdError> {
+		let header = self.file.get_header()?.clone();
+		let (start, size) = match self.locate_image_data()? {
+			Some(x) => x,
+			None => return Ok(()),
+		};
+		if size < 2 {
+			self.error("image_data", start, "image data is missing");
+			return Ok(());
+		}
+		let data = self.file.read_range(start, size)?;
+		let rows = header
+			.height
+			.saturating_mul(u32::from(header.number_of_channels));
+		let size = ChannelSize {
+			width: header.width,
+			rows,
+			depth: header.depth,
+		};
+		self.check_channel("image_data", start, &data, size);
+		Ok(())
+	}
+
+	/// Gets `image_data` block, or, if reading stopped before it, its position
+	/// after the declared length of layers resources up to the end of file
+	fn locate_image_data(&mut self) -> Result<Option<(u64, u64)>, PsdError> {
+		if let Some(x) = self.file.get_indexes()?.get("image_data") {
+			return Ok(Some(x));
+		}
+		let (start, size) = match self.file.get_indexes()?.get("layers_resources_length") {
+			Some(x) => x,
+			None => return Ok(None),
+		};
+		let length = self.read_number(start, size)?;
+		let file_size = self.file.seek(SeekFrom::End(0))?;
+		match (start + size).checked_add(length) {
+			Some(start) if start < file_size => Ok(Some((start, file_size - start))),
+			_ => Ok(None),
+		}
+	}
+
+	/// Checks that data, starting with compression method, matches size of the channel
+	fn check_channel(&mut self, label: &str, start: u64, data: &[u8], size: ChannelSize) {
+		let method = u16::from(data[0]) << 8 | u16::from(data[1]);
+		let compression = match Compression::from_u16(method) {
+			Some(x) => x,
+			None => {
+				self.error(
+					label,
+					start,
+					format!("unknown compression method {}", method),
+				);
+				return;
+			}
+		};
+		let data = &data[2..];
+		let expected = match size.size() {
+			Ok(x) => x,
+			Err(err) => {
+				self.error(label, start, err.to_string());
+				return;
+			}
+		};
+
+		let res = match compression {
+			Compression::Raw if data.len() != expected => Err(format!(
+				"raw data has {} bytes, but {}x{} channel needs {}",
+				data.len(),
+				size.width,
+				size.rows,
+				expected
+			)),
+			Compression::Rle => self.check_rle(data, size),
+			_ => decode_channel(data, compression, size, self.file_type)
+				.map(|_| ())
+				.map_err(|err| err.to_string()),
+		};
+		if let Err(message) = res {
+			self.error(label, start, message);
+		}
+	}
+
+	/// Checks that row counts cover the whole data and rows decode to channel width
+	fn check_rle(&self, data: &[u8], size: ChannelSize) -> Result<(), String> {
+		let count_size = match self.file_type {
+			PSDFileType::PSD => 2,
+			PSDFileType::PSB => 4,
+		};
+		let counts_end = size.rows as usize * count_size;
+		if counts_end <= data.len() {
+			let total: usize = data[..counts_end]
+				.chunks(count_size)
+				.map(|x| x.iter().fold(0usize, |c, x| c << 8 | *x as usize))
+				.sum();
+			if counts_end + total != data.len() {
+				return Err(format!(
+					"row counts sum to {} bytes, but {} bytes of rows follow",
+					total,
+					data.len() - counts_end
+				));
+			}
+		}
+		decode_rle(data, size, self.file_type)
+			.map(|_| ())
+			.map_err(|err| err.to_string())
+	}
+}
+
+#[cfg(test)]
+mod validate_tests {
+	use super::*;
+	use std::fs::{read, read_dir};
+	use std::io::Cursor;
+
+	fn find<'a>(issues: &'a [Issue], label: &str) -> &'a Issue {
+		issues
+			.iter()
+			.find(|x| x.label == label)
+			.unwrap_or_else(|| panic!("no issue on {:?} in {:?}", label, issues))
+	}
+
+	#[test]
+	fn valid_files_test() {
+		for entry in read_dir("./test_data").unwrap() {
+			let path = entry.unwrap().path();
+			if path.extension().is_none_or(|x| x != "psd" && x != "psb") {
+				continue;
+			}
+			let mut file = PSDFile::open(&path).unwrap();
+			let issues = validate(&mut file);
+			assert!(
+				issues.iter().all(|x| x.severity == Severity::Warning),
+				"{:?}: {:?}",
+				path,
+				issues
+			);
+		}
+	}
+
+	#[test]
+	fn issues_test() {
+		let data = read("./test_data/a_a.psd").unwrap();
+		let indexes = PSDFile::new(Cursor::new(data.clone()))
+			.get_indexes()
+			.unwrap()
+			.clone();
+		let start = |label: &str| indexes.get(label).unwrap().0 as usize;
+		let layers_info = "layers_resources/layers_info";
+		let channel_0 = format!("{}/channel_data/layer_0/channel_0", layers_info);
+		let channel_1 = format!("{}/channel_data/layer_0/channel_1", layers_info);
+
+		let mut patched = data.clone();
+		patched[start("header/reserved") + 1] = 1;
+		patched[start(&format!("{}/layer_0/filler", layers_info))] = 1;
+		patched[start(&channel_1) + 1] = 9;
+		// first row count of RLE channel
+		let count = start(&channel_0) + 2;
+		let value = u16::from(patched[count]) << 8 | u16::from(patched[count + 1]);
+		patched[count..count + 2].copy_from_slice(&(value + 1).to_be_bytes());
+		// merged image of 3 channels has no alpha for the negative layer count
+		patched[13] = 3;
+
+		let issues = validate(&mut PSDFile::new(Cursor::new(patched)));
+		let issue = find(&issues, "header/reserved");
+		assert_eq!(issue.severity, Severity::Warning);
+		assert_eq!(issue.offset, 6);
+		find(&issues, &format!("{}/layer_0/filler", layers_info));
+		let issue = find(&issues, &channel_1);
+		assert_eq!(issue.severity, Severity::Error);
+		assert_eq!(issue.offset, start(&channel_1) as u64);
+		assert_eq!(issue.message, "unknown compression method 9");
+		assert_eq!(
+			find(&issues, &channel_0).message,
+			"row counts sum to 215 bytes, but 214 bytes of rows follow"
+		);
+		assert_eq!(
+			find(&issues, &format!("{}/layer_count", layers_info)).to_string(),
+			"warning: \"layers_resources/layers_info/layer_count\" (offset 61996): \
+			 negative layer count -2 implies merged alpha, but image has only 3 channels"
+		);
+		assert_eq!(find(&issues, "image_data").severity, Severity::Error);
+	}
+
+	#[test]
+	fn unreadable_test() {
+		let data = read("./test_data/a_a.psd").unwrap();
+		let issues = validate(&mut PSDFile::new(Cursor::new(data[..62000].to_vec())));
+		assert_eq!(issues.len(), 1);
+		assert_eq!(issues[0].severity, Severity::Error);
+		assert_eq!(
+			issues[0].label,
+			"layers_resources/layers_info/layer_0/rect/top"
+		);
+		assert_eq!(issues[0].offset, 61998);
+
+		let (image_data, _) = PSDFile::new(Cursor::new(data.clone()))
+			.get_indexes()
+			.unwrap()
+			.get("image_data")
+			.unwrap();
+		let image_data = image_data as usize;
+
+		// layers info length of 100 bytes, so its records overrun it
+		let mut patched = data.clone();
+		patched[61992..61996].copy_from_slice(&[0, 0, 0, 100]);
+		patched[image_data + 1] = 9;
+		let mut file = PSDFile::new(Cursor::new(patched));
+		let issues = validate(&mut file);
+		assert!(!file.is_lenient());
+		assert_eq!(issues.len(), 2);
+		assert_eq!(
+			find(&issues, "layers_resources/layers_info_length").message,
+			"layers info doesn't fit its length 100: data reaches 62370, past declared end at 62096"
+		);
+		// merged image is checked after the layers resources which weren't read
+		let issue = find(&issues, "image_data");
+		assert_eq!(issue.offset, image_data as u64);
+		assert_eq!(issue.message, "unknown compression method 9");
+
+		// length of the first channel of the first layer
+		let mut patched = data.clone();
+		patched[62018..62022].copy_from_slice(&[0, 0, 0, 0]);
+		patched[6] = 1;
+		let issues = validate(&mut PSDFile::new(Cursor::new(patched)));
+		assert_eq!(issues.len(), 2);
+		find(&issues, "header/reserved");
+		assert_eq!(
+			find(
+				&issues,
+				"layers_resources/layers_info/channel_data/layer_0/channel_0"
+			)
+			.message,
+			"invalid value 0"
+		);
+	}
+}
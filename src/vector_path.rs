@@ -0,0 +1,461 @@
+//! Contains `VectorPath` struct, decoded path records of `vmsk` and `vsms` tagged blocks
+
+use byte_reader::ByteReader;
+use error::PsdError;
+
+/// Key of the tagged block of vector mask
+pub const VECTOR_MASK_KEY: &str = "vmsk";
+/// Key of the tagged block of vector mask, written instead of `vmsk` since CS6
+pub const VECTOR_MASK_SETTING_KEY: &str = "vsms";
+
+/// Size of the path record
+const RECORD_SIZE: usize = 26;
+
+/// Point relative to the document, `x` and `y` are from 0 to 1 inside of the canvas
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Point {
+	pub x: f64,
+	pub y: f64,
+}
+
+/// Anchor point of the path with its Bezier control points
+#[derive(Clone, Debug, PartialEq)]
+pub struct Knot {
+	/// Control points move together
+	pub linked: bool,
+	/// Control point of the segment which precedes the anchor
+	pub before: Point,
+	pub anchor: Point,
+	/// Control point of the segment which leaves the anchor
+	pub after: Point,
+}
+
+/// Boolean operation which combines the subpath with the shape of the preceding subpaths
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathOperation {
+	/// Exclude overlapping shapes
+	Xor,
+	/// Combine shapes
+	Combine,
+	/// Subtract front shape
+	Subtract,
+	/// Intersect shape areas
+	Intersect,
+	/// Operation isn't stored, e.g. by versions older than CS6, shapes are excluded as of `Xor`
+	Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subpath {
+	pub closed: bool,
+	pub operation: PathOperation,
+	pub knots: Vec<Knot>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VectorPath {
+	pub invert: bool,
+	/// Mask moves along with the layer
+	pub linked: bool,
+	pub enabled: bool,
+	/// Fill starts with all pixels, so subpaths are cut out of the canvas
+	pub fill_starts_with_all_pixels: bool,
+	pub subpaths: Vec<Subpath>,
+}
+
+/// Formats number with at most 3 decimal places
+fn svg_number(value: f64) -> String {
+	let value = (value * 1000.0).round() / 1000.0;
+	// avoids "-0"
+	(value + 0.0).to_string()
+}
+
+impl Subpath {
+	/// Gets data of SVG `path` element of the subpath, `None` if it has no knots
+	pub fn to_svg_path(&self, width: f64, height: f64) -> Option<String> {
+		let point = |p: &Point| format!("{} {}", svg_number(p.x * width), svg_number(p.y * height));
+		let (first, last) = (self.knots.first()?, self.knots.last()?);
+		let mut commands = vec![format!("M {}", point(&first.anchor))];
+		for pair in self.knots.windows(2) {
+			commands.push(format!(
+				"C {} {} {}",
+				point(&pair[0].after),
+				point(&pair[1].before),
+				point(&pair[1].anchor)
+			));
+		}
+		if self.closed {
+			commands.push(format!(
+				"C {} {} {} Z",
+				point(&last.after),
+				point(&first.before),
+				point(&first.anchor)
+			));
+		}
+		Some(commands.join(" "))
+	}
+}
+
+impl VectorPath {
+	/// Gets data of SVG `path` element in the document of `width` and `height`.
+	/// Path should be filled with "evenodd" rule, so it ignores operations of subpaths
+	/// and excludes overlapping shapes
+	pub fn to_svg_path(&self, width: f64, height: f64) -> String {
+		let mut commands = vec![];
+		if self.invert != self.fill_starts_with_all_pixels {
+			commands.push(format!(
+				"M 0 0 H {} V {} H 0 Z",
+				svg_number(width),
+				svg_number(height)
+			));
+		}
+		for subpath in &self.subpaths {
+			commands.extend(subpath.to_svg_path(width, height));
+		}
+		commands.join(" ")
+	}
+
+	/// Gets SVG elements of the shape filled with `fill` color. Shape is built by applying
+	/// operations of subpaths one after another, each step is kept as mask
+	fn to_svg_masks(&self, width: f64, height: f64, fill: &str) -> String {
+		let canvas = format!(
+			"width=\"{}\" height=\"{}\"",
+			svg_number(width),
+			svg_number(height)
+		);
+		let mut masks = vec![if self.fill_starts_with_all_pixels {
+			format!("<rect {} fill=\"white\"/>", canvas)
+		} else {
+			String::new()
+		}];
+		for subpath in &self.subpaths {
+			let d = match subpath.to_svg_path(width, height) {
+				Some(d) => d,
+				None => continue,
+			};
+			let previous = format!("mask=\"url(#shape{})\"", masks.len() - 1);
+			let shape = format!("<rect {} fill=\"white\" {}/>", canvas, previous);
+			masks.push(match subpath.operation {
+				PathOperation::Combine => format!("{}<path fill=\"white\" d=\"{}\"/>", shape, d),
+				PathOperation::Subtract => format!("{}<path fill=\"black\" d=\"{}\"/>", shape, d),
+				PathOperation::Intersect => {
+					format!("<path fill=\"white\" {} d=\"{}\"/>", previous, d)
+				}
+				PathOperation::Xor | PathOperation::Unknown => format!(
+					"{0}<path fill=\"white\" d=\"{2}\"/><path fill=\"black\" {1} d=\"{2}\"/>",
+					shape, previous, d
+				),
+			});
+		}
+		if self.invert {
+			let previous = format!("mask=\"url(#shape{})\"", masks.len() - 1);
+			masks.push(format!(
+				"<rect {0} fill=\"white\"/><rect {0} fill=\"black\" {1}/>",
+				canvas, previous
+			));
+		}
+
+		let mut elements = vec!["\t<defs>\n".to_string()];
+		for (i, mask) in masks.iter().enumerate() {
+			elements.push(format!("\t\t<mask id=\"shape{}\">{}</mask>\n", i, mask));
+		}
+		elements.push("\t</defs>\n".to_string());
+		elements.push(format!(
+			"\t<rect {} fill=\"{}\" mask=\"url(#shape{})\"/>\n",
+			canvas,
+			fill,
+			masks.len() - 1
+		));
+		elements.concat()
+	}
+
+	/// Gets SVG document of `width` and `height` with the path filled with `fill` color.
+	/// Path of subpaths which only exclude overlapping shapes is filled with "evenodd" rule,
+	/// otherwise subpaths are combined by masks
+	pub fn to_svg(&self, width: u32, height: u32, fill: &str) -> String {
+		let (w, h) = (f64::from(width), f64::from(height));
+		let evenodd = self
+			.subpaths
+			.iter()
+			.all(|x| x.operation == PathOperation::Xor || x.operation == PathOperation::Unknown);
+		let shape = if evenodd {
+			format!(
+				"\t<path fill=\"{}\" fill-rule=\"evenodd\" d=\"{}\"/>\n",
+				fill,
+				self.to_svg_path(w, h)
+			)
+		} else {
+			self.to_svg_masks(w, h, fill)
+		};
+		format!(
+			"<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n{2}</svg>\n",
+			width, height, shape
+		)
+	}
+}
+
+/// Reads point stored as vertical and horizontal 8.24 fixed point numbers
+fn read_point(r: &mut ByteReader) -> Result<Point, PsdError> {
+	let y = f64::from(r.read_i32()?) / 16_777_216.0;
+	let x = f64::from(r.read_i32()?) / 16_777_216.0;
+	Ok(Point { x, y })
+}
+
+/// Decodes 26 bytes path records, the same as of path image resources. Subpath length record
+/// holds knot count and operation, which is -1 if it isn't stored.
+/// Returns subpaths and whether fill starts with all pixels
+pub fn decode_path_records(data: &[u8]) -> Result<(Vec<Subpath>, bool), PsdError> {
+	let mut r = ByteReader::new(data);
+	let mut subpaths: Vec<Subpath> = vec![];
+	let mut remaining = 0;
+	let mut all_pixels = false;
+	while !r.is_empty() {
+		let offset = r.position() as u64;
+		let mut record = ByteReader::new(r.read_bytes(RECORD_SIZE)?);
+		let selector = record.read_u16()?;
+		match selector {
+			0 | 3 => {
+				remaining = record.read_u16()?;
+				let operation = match record.read_u16()? {
+					0 => PathOperation::Xor,
+					1 => PathOperation::Combine,
+					2 => PathOperation::Subtract,
+					3 => PathOperation::Intersect,
+					_ => PathOperation::Unknown,
+				};
+				subpaths.push(Subpath {
+					closed: selector == 0,
+					operation,
+					knots: vec![],
+				});
+			}
+			1 | 2 | 4 | 5 => {
+				let subpath = match subpaths.last_mut() {
+					Some(subpath) if remaining > 0 => subpath,
+					_ => {
+						return Err(PsdError::malformed("knot record outside of subpath")
+							.within("", offset))
+					}
+				};
+				remaining -= 1;
+				subpath.knots.push(Knot {
+					linked: selector == 1 || selector == 4,
+					before: read_point(&mut record)?,
+					anchor: read_point(&mut record)?,
+					after: read_point(&mut record)?,
+				});
+			}
+			// path fill rule and clipboard records
+			6 | 7 => (),
+			8 => all_pixels = record.read_u16()? != 0,
+			_ => {
+				return Err(PsdError::InvalidValue {
+					label: String::new(),
+					offset,
+					value: u64::from(selector),
+				})
+			}
+		}
+	}
+	Ok((subpaths, all_pixels))
+}
+
+/// Decodes data of `vmsk` or `vsms` tagged block: version, flags and path records
+pub fn decode_vector_mask(data: &[u8]) -> Result<VectorPath, PsdError> {
+	let mut r = ByteReader::new(data);
+	let version = r.read_u32()?;
+	if version != 3 {
+		return Err(PsdError::InvalidValue {
+			label: String::new(),
+			offset: 0,
+			value: u64::from(version),
+		});
+	}
+	let flags = r.read_u32()?;
+	let start = r.position() as u64;
+	let (subpaths, fill_starts_with_all_pixels) =
+		decode_path_records(r.read_bytes(r.remaining())?).map_err(|err| err.within("", start))?;
+	Ok(VectorPath {
+		invert: flags & 1 != 0,
+		linked: flags & 2 == 0,
+		enabled: flags & 4 == 0,
+		fill_starts_with_all_pixels,
+		subpaths,
+	})
+}
+
+#[cfg(test)]
+mod vector_path_tests {
+	use super::*;
+	use psd_file::PSDFile;
+	use std::io::Cursor;
+
+	fn record(selector: u16, values: &[i32]) -> Vec<u8> {
+		let mut data = selector.to_be_bytes().to_vec();
+		for x in values {
+			data.extend_from_slice(&x.to_be_bytes());
+		}
+		data.resize(RECORD_SIZE, 0);
+		data
+	}
+
+	/// Builds knot record of control points equal to the anchor
+	fn knot(selector: u16, x: f64, y: f64) -> Vec<u8> {
+		let x = (x * 16_777_216.0) as i32;
+		let y = (y * 16_777_216.0) as i32;
+		record(selector, &[y, x, y, x, y, x])
+	}
+
+	/// Builds `vmsk` block with closed triangle and open line
+	fn vector_mask_block(flags: u32) -> Vec<u8> {
+		let mut data = vec![0, 0, 0, 3];
+		data.extend_from_slice(&flags.to_be_bytes());
+		data.extend(record(6, &[]));
+		data.extend(record(8, &[]));
+		data.extend(record(0, &[3 << 16]));
+		data.extend(knot(1, 0.5, 0.0));
+		data.extend(knot(2, 1.0, 1.0));
+		data.extend(knot(1, 0.0, 1.0));
+		data.extend(record(3, &[2 << 16]));
+		data.extend(knot(4, 0.25, 0.5));
+		data.extend(knot(5, 0.75, 0.5));
+		data
+	}
+
+	#[test]
+	fn decode_vector_mask_test() {
+		let path = decode_vector_mask(&vector_mask_block(2)).unwrap();
+		assert!(!path.invert && !path.linked && path.enabled);
+		assert!(!path.fill_starts_with_all_pixels);
+		assert_eq!(path.subpaths.len(), 2);
+		assert!(path.subpaths[0].closed && !path.subpaths[1].closed);
+		assert_eq!(path.subpaths[0].operation, PathOperation::Xor);
+		assert_eq!(
+			path.subpaths[0].knots[1],
+			Knot {
+				linked: false,
+				before: Point { x: 1.0, y: 1.0 },
+				anchor: Point { x: 1.0, y: 1.0 },
+				after: Point { x: 1.0, y: 1.0 },
+			}
+		);
+		assert_eq!(path.subpaths[1].knots[0].anchor, Point { x: 0.25, y: 0.5 });
+
+		let mut data = vector_mask_block(0);
+		data.extend(knot(1, 0.0, 0.0));
+		match decode_vector_mask(&data) {
+			Err(PsdError::Malformed { offset, .. }) => assert_eq!(offset, 8 + 26 * 9),
+			x => panic!("expected Malformed, got {:?}", x),
+		}
+		match decode_vector_mask(&data[..40]) {
+			Err(PsdError::Truncated { offset, .. }) => assert_eq!(offset, 34),
+			x => panic!("expected Truncated, got {:?}", x),
+		}
+
+		let mut data = vec![0, 0, 0, 3, 0, 0, 0, 0];
+		for (i, operation) in [0xFFFF, 0, 1, 2, 3].iter().enumerate() {
+			data.extend(record(i as u16 % 2 * 3, &[*operation]));
+		}
+		assert_eq!(
+			decode_vector_mask(&data)
+				.unwrap()
+				.subpaths
+				.iter()
+				.map(|x| x.operation)
+				.collect::<Vec<PathOperation>>(),
+			vec![
+				PathOperation::Unknown,
+				PathOperation::Xor,
+				PathOperation::Combine,
+				PathOperation::Subtract,
+				PathOperation::Intersect,
+			]
+		);
+	}
+
+	#[test]
+	fn to_svg_test() {
+		let path = decode_vector_mask(&vector_mask_block(0)).unwrap();
+		assert_eq!(
+			path.to_svg_path(100.0, 50.0),
+			"M 50 0 C 50 0 100 50 100 50 C 100 50 0 50 0 50 C 0 50 50 0 50 0 Z \
+			 M 25 25 C 25 25 75 25 75 25"
+		);
+		let inverted = decode_vector_mask(&vector_mask_block(1)).unwrap();
+		assert!(inverted
+			.to_svg_path(100.0, 50.0)
+			.starts_with("M 0 0 H 100 V 50 H 0 Z M 50 0"));
+		assert_eq!(
+			path.to_svg(100, 50, "#ff0000"),
+			format!(
+				"<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"100\" height=\"50\" viewBox=\"0 0 100 50\">\n\t<path fill=\"#ff0000\" fill-rule=\"evenodd\" d=\"{}\"/>\n</svg>\n",
+				path.to_svg_path(100.0, 50.0)
+			)
+		);
+	}
+
+	#[test]
+	fn to_svg_operations_test() {
+		let mut data = vec![0, 0, 0, 3, 0, 0, 0, 0];
+		data.extend(record(0, &[2 << 16 | 1]));
+		data.extend(knot(1, 0.0, 0.0));
+		data.extend(knot(1, 1.0, 1.0));
+		data.extend(record(3, &[2 << 16 | 2]));
+		data.extend(knot(1, 0.0, 1.0));
+		data.extend(knot(1, 1.0, 0.0));
+		let mut path = decode_vector_mask(&data).unwrap();
+		let combined = "M 0 0 C 0 0 100 50 100 50 C 100 50 0 0 0 0 Z";
+		let subtracted = "M 0 50 C 0 50 100 0 100 0";
+		assert_eq!(
+			path.to_svg(100, 50, "#ff0000"),
+			format!(
+				"<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"100\" height=\"50\" viewBox=\"0 0 100 50\">\n\
+				 \t<defs>\n\
+				 \t\t<mask id=\"shape0\"></mask>\n\
+				 \t\t<mask id=\"shape1\"><rect width=\"100\" height=\"50\" fill=\"white\" mask=\"url(#shape0)\"/><path fill=\"white\" d=\"{}\"/></mask>\n\
+				 \t\t<mask id=\"shape2\"><rect width=\"100\" height=\"50\" fill=\"white\" mask=\"url(#shape1)\"/><path fill=\"black\" d=\"{}\"/></mask>\n\
+				 \t</defs>\n\
+				 \t<rect width=\"100\" height=\"50\" fill=\"#ff0000\" mask=\"url(#shape2)\"/>\n\
+				 </svg>\n",
+				combined, subtracted
+			)
+		);
+
+		path.subpaths[1].operation = PathOperation::Intersect;
+		path.fill_starts_with_all_pixels = true;
+		path.invert = true;
+		let svg = path.to_svg(100, 50, "#ff0000");
+		assert!(svg.contains(
+			"<mask id=\"shape0\"><rect width=\"100\" height=\"50\" fill=\"white\"/></mask>"
+		));
+		assert!(svg.contains(&format!(
+			"<mask id=\"shape2\"><path fill=\"white\" mask=\"url(#shape1)\" d=\"{}\"/></mask>",
+			subtracted
+		)));
+		assert!(svg.contains("<mask id=\"shape3\"><rect width=\"100\" height=\"50\" fill=\"white\"/><rect width=\"100\" height=\"50\" fill=\"black\" mask=\"url(#shape2)\"/></mask>"));
+		assert!(svg.contains("mask=\"url(#shape3)\"/>\n</svg>"));
+
+		path.subpaths[1].operation = PathOperation::Xor;
+		assert!(path.to_svg(100, 50, "#ff0000").contains(&format!(
+			"<path fill=\"white\" d=\"{0}\"/><path fill=\"black\" mask=\"url(#shape1)\" d=\"{0}\"/>",
+			subtracted
+		)));
+	}
+
+	#[test]
+	fn get_layer_vector_path_test() {
+		let mut file = PSDFile::open("./test_data/a_a.psd").unwrap();
+		assert_eq!(file.get_layer_vector_path(0).unwrap(), None);
+
+		let mut data = vec![];
+		file.write_tagged_blocks(
+			&[(0, VECTOR_MASK_SETTING_KEY, vector_mask_block(0))],
+			&mut data,
+		)
+		.unwrap();
+		let mut file = PSDFile::new(Cursor::new(data));
+		let path = file.get_layer_vector_path(0).unwrap().unwrap();
+		assert_eq!(path.subpaths.len(), 2);
+		assert_eq!(file.get_layer_vector_path(1).unwrap(), None);
+	}
+}